@@ -0,0 +1,182 @@
+//! Reading audio from WAV files or raw PCM streams, like a FIFO or the
+//! output of a loopback capture (e.g. `parec --format=s16le > fifo`). The
+//! audio is always converted into the format required by the fingerprinter:
+//! mono and sampled at `chroma::SAMPLE_RATE`.
+
+use crate::api::fingerprint::chroma::SAMPLE_RATE;
+use crate::error::{Error, Result};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::time;
+
+use hound::{SampleFormat, WavReader};
+
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Self {
+        match err {
+            hound::Error::IoError(e) => Error::IO(e),
+            e => Error::InvalidAudio(e.to_string()),
+        }
+    }
+}
+
+enum Source {
+    Wav(WavReader<BufReader<File>>),
+    /// Signed 16 bit little endian samples, which is the most common format
+    /// for raw PCM.
+    Raw(BufReader<File>),
+}
+
+pub struct AudioInput {
+    source: Source,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl AudioInput {
+    /// Opens the input, which may be either a WAV file or raw PCM. The
+    /// sample rate and channels are only used for the latter, since WAV
+    /// files already include them in their header.
+    pub fn open(path: &str, sample_rate: u32, channels: u16) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        // The header is peeked without consuming it, as FIFOs can't be
+        // rewinded.
+        let is_wav = reader.fill_buf()?.starts_with(b"RIFF");
+        let input = if is_wav {
+            let wav = WavReader::new(reader)?;
+            let spec = wav.spec();
+            AudioInput {
+                source: Source::Wav(wav),
+                sample_rate: spec.sample_rate,
+                channels: spec.channels,
+            }
+        } else {
+            AudioInput {
+                source: Source::Raw(reader),
+                sample_rate,
+                channels,
+            }
+        };
+
+        if input.channels == 0 || input.sample_rate == 0 {
+            return Err(Error::InvalidAudio(String::from(
+                "the input has no channels or a sample rate of zero",
+            )));
+        }
+
+        Ok(input)
+    }
+
+    /// Reads the next chunk of audio with the given duration, blocking
+    /// until it's available. Less audio may be returned if the input has
+    /// ended.
+    pub fn read(&mut self, duration: time::Duration) -> Result<Vec<f32>> {
+        let frames =
+            (duration.as_secs_f64() * self.sample_rate as f64) as usize;
+        let len = frames * self.channels as usize;
+
+        let samples = match &mut self.source {
+            Source::Wav(wav) => {
+                let spec = wav.spec();
+                match spec.sample_format {
+                    SampleFormat::Float => {
+                        wav.samples::<f32>()
+                            .take(len)
+                            .collect::<std::result::Result<Vec<_>, _>>()?
+                    }
+                    SampleFormat::Int => {
+                        let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                        wav.samples::<i32>()
+                            .take(len)
+                            .map(|s| s.map(|s| s as f32 / max))
+                            .collect::<std::result::Result<Vec<_>, _>>()?
+                    }
+                }
+            }
+            Source::Raw(reader) => {
+                let mut bytes = Vec::with_capacity(len * 2);
+                reader
+                    .by_ref()
+                    .take(len as u64 * 2)
+                    .read_to_end(&mut bytes)?;
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                    .collect()
+            }
+        };
+
+        let mono = downmix(&samples, self.channels);
+        Ok(resample(&mono, self.sample_rate, SAMPLE_RATE))
+    }
+}
+
+/// Reads a full audio file, which is useful to index the music library.
+pub fn read_file(path: &str) -> Result<Vec<f32>> {
+    let mut input = AudioInput::open(path, SAMPLE_RATE, 1)?;
+    let duration = match &input.source {
+        Source::Wav(wav) => wav.duration() as f64 / input.sample_rate as f64,
+        Source::Raw(_) => {
+            return Err(Error::InvalidAudio(format!(
+                "'{}' isn't a WAV file",
+                path
+            )))
+        }
+    };
+
+    input.read(time::Duration::from_secs_f64(duration))
+}
+
+/// Converts interleaved audio into mono by averaging its channels.
+fn downmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels == 1 {
+        return samples.to_vec();
+    }
+
+    samples
+        .chunks_exact(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// A naive resampler that averages the input samples covered by each output
+/// sample, which also works as a simple low-pass filter when downsampling.
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+
+    let ratio = from as f64 / to as f64;
+    let len = (samples.len() as f64 / ratio) as usize;
+    (0..len)
+        .map(|i| {
+            let start = (i as f64 * ratio) as usize;
+            let end = (((i + 1) as f64 * ratio) as usize)
+                .max(start + 1)
+                .min(samples.len());
+            samples[start..end].iter().sum::<f32>() / (end - start) as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn downmix_stereo() {
+        assert_eq!(downmix(&[1.0, 0.0, 0.5, 0.5], 2), vec![0.5, 0.5]);
+        assert_eq!(downmix(&[1.0, 0.0], 1), vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn resample_rates() {
+        let samples: Vec<f32> = (0..44100).map(|i| i as f32).collect();
+        assert_eq!(resample(&samples, 44100, 11025).len(), 11025);
+        assert_eq!(resample(&samples, 44100, 44100).len(), 44100);
+        assert_eq!(resample(&samples, 22050, 44100).len(), 88200);
+        assert_eq!(resample(&[1.0, 3.0, 5.0, 7.0], 4, 2), vec![2.0, 6.0]);
+    }
+}
@@ -0,0 +1,229 @@
+//! A simplified implementation of the Chromaprint algorithm. The audio is
+//! split in overlapping frames, whose spectrum is folded into the 12 notes
+//! of the chromatic scale. The resulting chroma features are then compared
+//! among themselves to obtain a 32-bit sub-fingerprint per frame, which
+//! doesn't depend on the volume and is fairly robust to noise.
+
+use std::time;
+
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::FFTplanner;
+
+/// The audio must be downmixed to mono and resampled to this rate before
+/// obtaining its fingerprint.
+pub const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const FRAME_STEP: usize = FRAME_SIZE / 3;
+/// The frequency range taken into account, from A0 to A7.
+const MIN_FREQ: f32 = 27.5;
+const MAX_FREQ: f32 = 3520.0;
+/// Number of frames the chroma features are averaged with, so that small
+/// misalignments between two recordings don't change the result.
+const SMOOTHING: usize = 5;
+/// The sub-fingerprint bits that only depend on a single frame. These are
+/// the most stable ones, so they're used as the key when looking them up.
+pub const KEY_MASK: u32 = 0x00ff_ffff;
+
+type Chroma = [f32; 12];
+
+/// The duration of the audio between two consecutive sub-fingerprints.
+pub fn item_duration() -> time::Duration {
+    time::Duration::from_secs_f64(FRAME_STEP as f64 / SAMPLE_RATE as f64)
+}
+
+/// Computes the fingerprint of some mono audio sampled at `SAMPLE_RATE`.
+pub fn fingerprint(samples: &[f32]) -> Vec<u32> {
+    let chroma = smooth(&chroma_features(samples));
+    chroma
+        .windows(2)
+        .map(|pair| sub_fingerprint(&pair[0], &pair[1]))
+        .collect()
+}
+
+/// The chroma features of each frame, normalized so that the volume of the
+/// audio is irrelevant.
+fn chroma_features(samples: &[f32]) -> Vec<Chroma> {
+    if samples.len() < FRAME_SIZE {
+        return Vec::new();
+    }
+
+    let mut planner = FFTplanner::new(false);
+    let fft = planner.plan_fft(FRAME_SIZE);
+    let notes = note_bins();
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| {
+            let x = i as f32 / (FRAME_SIZE - 1) as f32;
+            0.5 - 0.5 * (2.0 * std::f32::consts::PI * x).cos()
+        })
+        .collect();
+
+    let mut input = vec![Complex::zero(); FRAME_SIZE];
+    let mut output = vec![Complex::zero(); FRAME_SIZE];
+    let mut features = Vec::new();
+    for start in (0..=samples.len() - FRAME_SIZE).step_by(FRAME_STEP) {
+        let frame = &samples[start..start + FRAME_SIZE];
+        for (i, (sample, weight)) in frame.iter().zip(&window).enumerate() {
+            input[i] = Complex::new(sample * weight, 0.0);
+        }
+        fft.process(&mut input, &mut output);
+
+        let mut chroma = [0.0; 12];
+        for (bin, note) in notes.iter().enumerate() {
+            if let Some(note) = note {
+                chroma[*note] += output[bin].norm_sqr();
+            }
+        }
+        features.push(normalize(chroma));
+    }
+
+    features
+}
+
+/// The note each bin of the spectrum belongs to, if it's inside the
+/// frequency range.
+fn note_bins() -> Vec<Option<usize>> {
+    (0..FRAME_SIZE / 2)
+        .map(|bin| {
+            let freq = bin as f32 * SAMPLE_RATE as f32 / FRAME_SIZE as f32;
+            if !(MIN_FREQ..=MAX_FREQ).contains(&freq) {
+                return None;
+            }
+
+            let note = (12.0 * (freq / MIN_FREQ).log2()).round() as usize;
+            Some(note % 12)
+        })
+        .collect()
+}
+
+fn normalize(chroma: Chroma) -> Chroma {
+    let norm = chroma.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm < 1e-6 {
+        return [0.0; 12];
+    }
+
+    let mut normalized = chroma;
+    normalized.iter_mut().for_each(|x| *x /= norm);
+    normalized
+}
+
+/// Moving average of the chroma features.
+fn smooth(features: &[Chroma]) -> Vec<Chroma> {
+    if features.len() < SMOOTHING {
+        return Vec::new();
+    }
+
+    features
+        .windows(SMOOTHING)
+        .map(|window| {
+            let mut avg = [0.0; 12];
+            for chroma in window {
+                for (note, value) in chroma.iter().enumerate() {
+                    avg[note] += value / SMOOTHING as f32;
+                }
+            }
+            avg
+        })
+        .collect()
+}
+
+/// Each bit of the sub-fingerprint is the result of comparing the energy
+/// of two notes (or groups of notes). The lower 24 bits compare notes a
+/// semitone and a major third apart in the current frame. The upper 8 bits
+/// compare groups of notes with the previous frame and among themselves.
+fn sub_fingerprint(prev: &Chroma, cur: &Chroma) -> u32 {
+    let group = |chroma: &Chroma, i: usize| -> f32 {
+        chroma[i * 3..i * 3 + 3].iter().sum()
+    };
+
+    let mut bits = 0u32;
+    let mut pos = 0;
+    let mut push = |bit: bool| {
+        bits |= (bit as u32) << pos;
+        pos += 1;
+    };
+    for i in 0..12 {
+        push(cur[i] > cur[(i + 1) % 12]);
+    }
+    for i in 0..12 {
+        push(cur[i] > cur[(i + 4) % 12]);
+    }
+    for i in 0..4 {
+        push(group(cur, i) > group(prev, i));
+    }
+    for i in 0..4 {
+        push(group(cur, i) > group(cur, (i + 1) % 4));
+    }
+
+    bits
+}
+
+/// Compares two fingerprints with the given alignment, returning the ratio
+/// of bits that are equal. `None` is returned when they don't overlap
+/// enough to be compared.
+pub fn similarity(
+    query: &[u32],
+    reference: &[u32],
+    offset: i64,
+) -> Option<f32> {
+    let mut compared = 0;
+    let mut errors = 0;
+    for (i, item) in query.iter().enumerate() {
+        let pos = offset + i as i64;
+        if pos < 0 || pos >= reference.len() as i64 {
+            continue;
+        }
+
+        errors += (item ^ reference[pos as usize]).count_ones();
+        compared += 1;
+    }
+
+    if compared == 0 || compared < query.len() / 2 {
+        return None;
+    }
+
+    Some(1.0 - errors as f32 / (compared * 32) as f32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tone(freqs: &[f32], secs: f32) -> Vec<f32> {
+        let len = (secs * SAMPLE_RATE as f32) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                freqs
+                    .iter()
+                    .map(|f| (2.0 * std::f32::consts::PI * f * t).sin())
+                    .sum::<f32>()
+                    / freqs.len() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn volume_independent() {
+        let loud = tone(&[261.63, 329.63, 392.0], 3.0);
+        let quiet: Vec<f32> = loud.iter().map(|x| x * 0.1).collect();
+        let (loud, quiet) = (fingerprint(&loud), fingerprint(&quiet));
+
+        assert!(!loud.is_empty());
+        assert!(similarity(&loud, &quiet, 0).unwrap() > 0.9);
+    }
+
+    #[test]
+    fn different_chords() {
+        let major = fingerprint(&tone(&[261.63, 329.63, 392.0], 3.0));
+        let minor = fingerprint(&tone(&[293.66, 349.23, 440.0], 3.0));
+
+        assert!(similarity(&major, &minor, 0).unwrap() < 0.8);
+    }
+
+    #[test]
+    fn short_input() {
+        assert!(fingerprint(&tone(&[440.0], 0.1)).is_empty());
+        assert_eq!(similarity(&[1, 2, 3], &[1, 2, 3], 10), None);
+    }
+}
@@ -0,0 +1,176 @@
+//! The fingerprint index of the user's music library, which is stored in the
+//! data directory so that it only has to be updated when the files change.
+//! Only the WAV files are indexed, since no other format is decoded.
+
+use crate::api::fingerprint::{audio, chroma};
+use crate::data::{find_files, parse_file_name};
+use crate::error::Result;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// How many of the most voted alignments are compared bit by bit.
+const MAX_CANDIDATES: usize = 10;
+/// The minimum ratio of equal bits for a match. Unrelated audio usually has
+/// a similarity of around 0.5.
+const MIN_SIMILARITY: f32 = 0.7;
+const EXTENSIONS: &[&str] = &["wav", "wave"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Track {
+    pub path: String,
+    pub artist: Option<String>,
+    pub title: String,
+    /// Last modification of the file, in seconds since the UNIX epoch.
+    modified: u64,
+    fingerprint: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub struct Match {
+    /// The position of the track in the index.
+    pub track: usize,
+    /// The position in the track where the query starts.
+    pub position: time::Duration,
+    pub similarity: f32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Index {
+    tracks: Vec<Track>,
+    /// Inverted index from the sub-fingerprint key to the tracks and
+    /// positions it appears in. It's rebuilt after loading the index.
+    #[serde(skip)]
+    lookup: HashMap<u32, Vec<(usize, usize)>>,
+}
+
+impl Index {
+    /// Loads the index from a file, which may be empty.
+    pub fn load(path: &str) -> Result<Index> {
+        let data = fs::read_to_string(path)?;
+        let mut index: Index = if data.trim().is_empty() {
+            Index::default()
+        } else {
+            serde_json::from_str(&data)?
+        };
+        index.build_lookup();
+
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Updates the index with the audio files inside the given directories,
+    /// recursively. Only the new or modified files are fingerprinted again.
+    /// Returns whether the index has changed.
+    pub fn update(&mut self, dirs: &[PathBuf]) -> Result<bool> {
+        let mut files = Vec::new();
+        for dir in dirs {
+            find_files(dir, EXTENSIONS, &mut files)?;
+        }
+
+        // Removing the tracks that no longer exist or that were modified.
+        // Their positions change, so the lookup table has to be rebuilt.
+        let before = self.tracks.len();
+        let mut current: HashMap<String, u64> = files.into_iter().collect();
+        self.tracks
+            .retain(|track| current.get(&track.path) == Some(&track.modified));
+        let removed = before != self.tracks.len();
+        if removed {
+            self.build_lookup();
+        }
+
+        // The new tracks are appended, so they're added to the lookup table
+        // without rebuilding it.
+        for track in &self.tracks {
+            current.remove(&track.path);
+        }
+        let added = !current.is_empty();
+        for (path, modified) in current {
+            info!("Fingerprinting {}", path);
+            let samples = match audio::read_file(&path) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    warn!("Skipping {}: {}", path, e);
+                    continue;
+                }
+            };
+            // WAV files don't usually have tags, so the file name is used.
+            let (artist, title) = parse_file_name(Path::new(&path));
+            self.tracks.push(Track {
+                fingerprint: chroma::fingerprint(&samples),
+                path,
+                artist,
+                title,
+                modified,
+            });
+            self.add_lookup(self.tracks.len() - 1);
+        }
+
+        Ok(removed || added)
+    }
+
+    fn build_lookup(&mut self) {
+        self.lookup.clear();
+        for i in 0..self.tracks.len() {
+            self.add_lookup(i);
+        }
+    }
+
+    /// Adds the keys of a track to the lookup table.
+    fn add_lookup(&mut self, track: usize) {
+        let fingerprint = &self.tracks[track].fingerprint;
+        for (pos, item) in fingerprint.iter().enumerate() {
+            // Silence would match everything.
+            let key = item & chroma::KEY_MASK;
+            if key != 0 {
+                self.lookup.entry(key).or_default().push((track, pos));
+            }
+        }
+    }
+
+    /// Finds the track the fingerprint belongs to. The alignments with the
+    /// highest number of equal keys are obtained first, and then the best
+    /// one is chosen by comparing them bit by bit.
+    pub fn identify(&self, query: &[u32]) -> Option<Match> {
+        let mut votes: HashMap<(usize, i64), u32> = HashMap::new();
+        for (i, item) in query.iter().enumerate() {
+            let key = item & chroma::KEY_MASK;
+            for (track, pos) in self.lookup.get(&key).into_iter().flatten() {
+                let offset = *pos as i64 - i as i64;
+                *votes.entry((*track, offset)).or_insert(0) += 1;
+            }
+        }
+
+        let mut candidates: Vec<_> = votes.into_iter().collect();
+        candidates.sort_by_key(|(_, votes)| Reverse(*votes));
+        candidates
+            .into_iter()
+            .take(MAX_CANDIDATES)
+            .filter_map(|((track, offset), _)| {
+                let reference = &self.tracks[track].fingerprint;
+                let similarity = chroma::similarity(query, reference, offset)?;
+                Some((track, offset, similarity))
+            })
+            .filter(|(_, _, similarity)| *similarity >= MIN_SIMILARITY)
+            .max_by(|a, b| a.2.partial_cmp(&b.2).unwrap())
+            .map(|(track, offset, similarity)| Match {
+                track,
+                position: chroma::item_duration() * offset.max(0) as u32,
+                similarity,
+            })
+    }
+}
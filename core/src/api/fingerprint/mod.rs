@@ -0,0 +1,509 @@
+//! An API for sources that don't expose any metadata at all, like a vinyl
+//! through a line-in or a TV. The song is identified by computing the
+//! fingerprint of the audio being played, which is then looked up in an
+//! index of the user's music library. Only its WAV files are indexed,
+//! since the other formats like MP3 or FLAC aren't decoded.
+//!
+//! The audio input can be a WAV file or raw PCM (signed 16 bit, little
+//! endian), which includes FIFOs and loopback captures. For example, with
+//! PulseAudio:
+//!
+//! ```text
+//! mkfifo /tmp/vidify.pcm
+//! parec -d alsa_output.pci.monitor --format=s16le > /tmp/vidify.pcm
+//! ```
+
+mod audio;
+mod chroma;
+mod index;
+
+use crate::api::fingerprint::audio::AudioInput;
+use crate::api::fingerprint::index::Index;
+use crate::api::{APIBase, TrackInfo};
+use crate::config::Config;
+use crate::data::{Res, ResKind};
+use crate::error::{Error, Result};
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time;
+
+use log::{error, info};
+
+/// The audio is captured in chunks of this length in a separate thread, so
+/// that the song can be identified again as soon as each of them arrives.
+const CAPTURE_STEP: time::Duration = time::Duration::from_secs(1);
+/// The minimum audio needed to attempt an identification, unless the
+/// configured window is shorter.
+const MIN_WINDOW: time::Duration = time::Duration::from_secs(5);
+/// How long the first identification is waited for when starting, in case
+/// the input never provides enough audio, like an unused FIFO.
+const START_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// The last identified track, and when it was identified in order to know
+/// its current position.
+struct Identified {
+    track: usize,
+    position: time::Duration,
+    instant: time::Instant,
+}
+
+pub struct Fingerprint {
+    index: Index,
+    /// The chunks read by the capture thread, until the input ends or
+    /// fails.
+    chunks: Option<Receiver<Result<Vec<f32>>>>,
+    /// The latest audio captured, up to `window` long.
+    samples: Vec<f32>,
+    window: time::Duration,
+    identified: Option<Identified>,
+}
+
+impl Fingerprint {
+    /// Reads the audio input in a separate thread, which stops once the
+    /// receiver is dropped.
+    fn capture(mut input: AudioInput) -> Receiver<Result<Vec<f32>>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || loop {
+            match input.read(CAPTURE_STEP) {
+                Ok(samples) if samples.is_empty() => break,
+                Ok(samples) => {
+                    if sender.send(Ok(samples)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        });
+
+        receiver
+    }
+
+    /// Adds a new chunk of audio, keeping only the latest `window` of it.
+    fn push(&mut self, chunk: &[f32]) {
+        let max =
+            (self.window.as_secs_f64() * chroma::SAMPLE_RATE as f64) as usize;
+        self.samples.extend_from_slice(chunk);
+        if self.samples.len() > max {
+            self.samples.drain(..self.samples.len() - max);
+        }
+    }
+
+    /// Whether there's enough audio to attempt an identification.
+    fn has_enough(&self) -> bool {
+        let min = MIN_WINDOW.min(self.window).as_secs_f64()
+            * chroma::SAMPLE_RATE as f64;
+        self.samples.len() as f64 >= min
+    }
+
+    /// Looks up the latest audio in the index. The position is the one at
+    /// the end of the audio, which is the moment it's been completely read.
+    fn identify(&mut self) {
+        let query = chroma::fingerprint(&self.samples);
+        let duration = time::Duration::from_secs_f64(
+            self.samples.len() as f64 / chroma::SAMPLE_RATE as f64,
+        );
+
+        let tracks = self.index.tracks();
+        self.identified = self.index.identify(&query).map(|found| {
+            info!(
+                "Identified {} at {:?} (similarity of {:.2})",
+                tracks[found.track].path, found.position, found.similarity
+            );
+
+            Identified {
+                track: found.track,
+                position: found.position + duration,
+                instant: time::Instant::now(),
+            }
+        });
+    }
+
+    /// Handles the new chunks captured, if any, without blocking. Returns
+    /// whether the audio was identified again.
+    fn update(&mut self) -> Result<bool> {
+        let mut received = false;
+        while let Some(chunks) = &self.chunks {
+            match chunks.try_recv() {
+                Ok(Ok(chunk)) => {
+                    self.push(&chunk);
+                    received = true;
+                }
+                Ok(Err(e)) => {
+                    self.chunks = None;
+                    self.identified = None;
+                    return Err(e);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.chunks = None;
+                    self.identified = None;
+                    return Err(Error::InvalidAudio(String::from(
+                        "the audio input has ended",
+                    )));
+                }
+            }
+        }
+
+        if received && self.has_enough() {
+            self.identify();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Waits for the first identification attempt, which only needs the
+    /// minimum amount of audio.
+    fn wait_identified(&mut self, timeout: time::Duration) -> Result<()> {
+        let start = time::Instant::now();
+        while !self.update()? {
+            if start.elapsed() > timeout {
+                return Err(Error::NoTrackPlaying);
+            }
+            thread::sleep(CAPTURE_STEP / 10);
+        }
+        if self.identified.is_none() {
+            return Err(Error::NoTrackPlaying);
+        }
+
+        Ok(())
+    }
+}
+
+impl APIBase for Fingerprint {
+    fn new(config: &Config) -> Result<Self> {
+        let input = config.fingerprint_input.as_ref().ok_or_else(|| {
+            Error::FailedConnection(String::from(
+                "no audio input configured for fingerprinting",
+            ))
+        })?;
+        let library: Vec<PathBuf> = match &config.fingerprint_library {
+            Some(dirs) => env::split_paths(dirs).collect(),
+            None => Vec::new(),
+        };
+
+        // The index is updated with the latest changes in the library
+        // before starting.
+        let path = Res::new(ResKind::Data(String::from("fingerprints.json")))?;
+        let mut index = Index::load(&path)?;
+        if index.update(&library)? {
+            index.save(&path)?;
+        }
+        if index.tracks().is_empty() {
+            return Err(Error::FailedConnection(String::from(
+                "the fingerprint index is empty; check that the music \
+                library is configured and that it contains WAV files, \
+                since the other formats aren't indexed",
+            )));
+        }
+
+        let input = AudioInput::open(
+            input,
+            config.fingerprint_sample_rate,
+            config.fingerprint_channels,
+        )?;
+        let mut api = Fingerprint {
+            index,
+            chunks: Some(Fingerprint::capture(input)),
+            samples: Vec::new(),
+            window: time::Duration::from_secs(
+                config.fingerprint_window.into(),
+            ),
+            identified: None,
+        };
+        api.wait_identified(START_TIMEOUT)?;
+
+        Ok(api)
+    }
+
+    fn player_name(&self) -> String {
+        String::from("Audio Fingerprinting")
+    }
+
+    fn artist(&self) -> Option<String> {
+        let identified = self.identified.as_ref()?;
+        self.index.tracks()[identified.track].artist.clone()
+    }
+
+    fn title(&self) -> Option<String> {
+        let identified = self.identified.as_ref()?;
+        Some(self.index.tracks()[identified.track].title.clone())
+    }
+
+    fn track_info(&self) -> TrackInfo {
+        let identified = match &self.identified {
+            Some(identified) => identified,
+            None => return TrackInfo::default(),
+        };
+        let track = &self.index.tracks()[identified.track];
+
+        TrackInfo {
+            artist: track.artist.clone(),
+            title: Some(track.title.clone()),
+            url: Some(track.path.clone()),
+            ..TrackInfo::default()
+        }
+    }
+
+    fn position(&self) -> Option<time::Duration> {
+        let identified = self.identified.as_ref()?;
+        Some(identified.position + identified.instant.elapsed())
+    }
+
+    fn is_playing(&self) -> bool {
+        self.identified.is_some()
+    }
+
+    /// It doesn't block, since the audio is captured in the background.
+    fn event_loop(&mut self) {
+        if let Err(e) = self.update() {
+            error!("Failed to identify the audio: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::temp_dir;
+
+    use std::fs;
+    use std::path::Path;
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    /// A simple random number generator, so that the fixtures are always
+    /// the same.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (self.0 >> 33) % max
+        }
+    }
+
+    /// Generates a song as a sequence of random chords.
+    fn song(seed: u64, secs: f32, sample_rate: u32) -> Vec<f32> {
+        let mut rng = Lcg(seed);
+        let mut samples = Vec::new();
+        let total = (secs * sample_rate as f32) as usize;
+        while samples.len() < total {
+            let notes: Vec<f32> = (0..3)
+                .map(|_| {
+                    440.0 * 2f32.powf((rng.next(36) as f32 - 24.0) / 12.0)
+                })
+                .collect();
+            let len = (sample_rate as f32 * (0.4 + rng.next(5) as f32 / 10.0))
+                as usize;
+            for i in 0..len {
+                let t = i as f32 / sample_rate as f32;
+                let value: f32 = notes
+                    .iter()
+                    .map(|f| (2.0 * std::f32::consts::PI * f * t).sin())
+                    .sum();
+                samples.push(value / 4.0);
+            }
+        }
+        samples.truncate(total);
+        samples
+    }
+
+    fn write_wav(
+        path: &Path,
+        samples: &[f32],
+        sample_rate: u32,
+        channels: u16,
+    ) {
+        let spec = WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for sample in samples {
+            for _ in 0..channels {
+                writer.write_sample((sample * 32767.0) as i16).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn fixtures_dir(name: &str) -> PathBuf {
+        let dir = temp_dir(&format!("fingerprint-{}", name));
+        fs::create_dir_all(dir.join("library")).unwrap();
+        dir
+    }
+
+    fn build_library(dir: &Path) -> Index {
+        let library = dir.join("library");
+        for (seed, name) in [
+            (1, "Artist A - First"),
+            (2, "Artist B - Second"),
+            (3, "Third"),
+        ]
+        .iter()
+        {
+            let path = library.join(format!("{}.wav", name));
+            write_wav(&path, &song(*seed, 40.0, 22050), 22050, 1);
+        }
+
+        let mut index = Index::default();
+        assert!(index.update(&[library]).unwrap());
+        assert_eq!(index.tracks().len(), 3);
+        index
+    }
+
+    #[test]
+    fn identify_excerpt() {
+        let dir = fixtures_dir("excerpt");
+        let index = build_library(&dir);
+
+        // An excerpt of the second song, with a different format and some
+        // noise.
+        let mut rng = Lcg(1234);
+        let excerpt: Vec<f32> = song(2, 40.0, 44100)[44100 * 15..44100 * 25]
+            .iter()
+            .map(|s| s * 0.5 + (rng.next(1000) as f32 / 1000.0 - 0.5) * 0.05)
+            .collect();
+        let path = dir.join("excerpt.wav");
+        write_wav(&path, &excerpt, 44100, 2);
+
+        let mut input =
+            AudioInput::open(path.to_str().unwrap(), 44100, 2).unwrap();
+        let samples = input.read(time::Duration::from_secs(10)).unwrap();
+        let found = index.identify(&chroma::fingerprint(&samples)).unwrap();
+        let track = &index.tracks()[found.track];
+        assert_eq!(track.artist.as_deref(), Some("Artist B"));
+        assert_eq!(track.title, "Second");
+        let secs = found.position.as_secs_f32();
+        assert!(secs > 14.5 && secs < 15.5, "position was {}", secs);
+    }
+
+    #[test]
+    fn raw_pcm_input() {
+        let dir = fixtures_dir("raw");
+        let index = build_library(&dir);
+
+        let bytes: Vec<u8> = song(3, 40.0, 11025)[11025 * 5..11025 * 15]
+            .iter()
+            .flat_map(|s| ((s * 32767.0) as i16).to_le_bytes().to_vec())
+            .collect();
+        let path = dir.join("excerpt.pcm");
+        fs::write(&path, bytes).unwrap();
+
+        let mut input =
+            AudioInput::open(path.to_str().unwrap(), 11025, 1).unwrap();
+        let samples = input.read(time::Duration::from_secs(10)).unwrap();
+        let found = index.identify(&chroma::fingerprint(&samples)).unwrap();
+        let track = &index.tracks()[found.track];
+        assert_eq!(track.artist, None);
+        assert_eq!(track.title, "Third");
+    }
+
+    #[test]
+    fn background_capture() {
+        let dir = fixtures_dir("capture");
+        let index = build_library(&dir);
+
+        let excerpt = &song(1, 40.0, 22050)[22050 * 20..22050 * 32];
+        let path = dir.join("excerpt.wav");
+        write_wav(&path, excerpt, 22050, 1);
+        let input = AudioInput::open(path.to_str().unwrap(), 0, 0).unwrap();
+        let mut api = Fingerprint {
+            index,
+            chunks: Some(Fingerprint::capture(input)),
+            samples: Vec::new(),
+            window: time::Duration::from_secs(10),
+            identified: None,
+        };
+
+        // The audio is identified as soon as there's enough of it, without
+        // waiting for the full window.
+        let start = time::Instant::now();
+        while api.identified.is_none() {
+            api.update().unwrap();
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        assert_eq!(api.title().as_deref(), Some("First"));
+        let secs = api.position().unwrap().as_secs_f32();
+        assert!(secs > 24.5 && secs < 33.0, "position was {}", secs);
+
+        // Only the latest window is kept, and once the input ends it's no
+        // longer playing.
+        while api.update().is_ok() {
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        assert_eq!(api.samples.len(), 10 * chroma::SAMPLE_RATE as usize);
+        assert!(!api.is_playing());
+        assert!(!api.update().unwrap());
+    }
+
+    #[test]
+    fn silent_input() {
+        let dir = fixtures_dir("silent");
+        let index = build_library(&dir);
+
+        // Like a FIFO nobody writes to.
+        let (_sender, receiver) = mpsc::channel();
+        let mut api = Fingerprint {
+            index,
+            chunks: Some(receiver),
+            samples: Vec::new(),
+            window: time::Duration::from_secs(10),
+            identified: None,
+        };
+        let res = api.wait_identified(time::Duration::from_millis(300));
+        assert!(matches!(res, Err(Error::NoTrackPlaying)));
+    }
+
+    #[test]
+    fn unknown_audio() {
+        let dir = fixtures_dir("unknown");
+        let index = build_library(&dir);
+
+        let unknown = song(42, 10.0, chroma::SAMPLE_RATE);
+        assert!(index.identify(&chroma::fingerprint(&unknown)).is_none());
+    }
+
+    #[test]
+    fn incremental_update() {
+        let dir = fixtures_dir("update");
+        let mut index = build_library(&dir);
+        let library = vec![dir.join("library")];
+        assert!(!index.update(&library).unwrap());
+
+        fs::remove_file(dir.join("library").join("Third.wav")).unwrap();
+        assert!(index.update(&library).unwrap());
+        assert_eq!(index.tracks().len(), 2);
+
+        // The new files are added to the existing lookup table.
+        let path = dir.join("library").join("Fourth.wav");
+        write_wav(&path, &song(4, 40.0, 22050), 22050, 1);
+        assert!(index.update(&library).unwrap());
+        assert_eq!(index.tracks().len(), 3);
+        let samples = &song(4, 40.0, chroma::SAMPLE_RATE)[11025 * 10..];
+        let found = index.identify(&chroma::fingerprint(samples)).unwrap();
+        assert_eq!(index.tracks()[found.track].title, "Fourth");
+
+        // Saving and loading it again should keep the lookup table working.
+        let path = dir.join("index.json");
+        fs::write(&path, "").unwrap();
+        let path = path.to_str().unwrap();
+        assert!(Index::load(path).unwrap().tracks().is_empty());
+        index.save(path).unwrap();
+        let index = Index::load(path).unwrap();
+        let samples = song(1, 20.0, chroma::SAMPLE_RATE);
+        let found = index.identify(&chroma::fingerprint(&samples)).unwrap();
+        assert_eq!(index.tracks()[found.track].title, "First");
+    }
+}
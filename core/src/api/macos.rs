@@ -7,7 +7,7 @@ use std::time;
 pub struct MacOS {}
 
 impl APIBase for MacOS {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(_config: &Config) -> Result<Self> {
         Ok(MacOS {})
     }
 
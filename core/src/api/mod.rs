@@ -1,10 +1,11 @@
+pub mod fingerprint;
 pub mod macos;
 pub mod mpris;
 pub mod spotifyweb;
 pub mod windows;
 
 use crate::config::Config;
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 use std::time;
 
@@ -12,13 +13,46 @@ use strum_macros::{Display, EnumString};
 
 #[derive(Clone, Debug, Display, EnumString)]
 pub enum API {
-    #[cfg(any(target_os = "linux", target_os = "bsd"))]
+    #[cfg(target_os = "linux")]
     MPRIS,
     #[cfg(target_os = "windows")]
     Windows,
     #[cfg(target_os = "macos")]
     MacOS,
     SpotifyWeb,
+    Fingerprint,
+}
+
+/// The metadata of a track, as complete as the API is able to provide it.
+/// Most fields are optional because each API exposes different information.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackInfo {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<time::Duration>,
+    /// The URL of the album artwork.
+    pub art_url: Option<String>,
+    /// An identifier that doesn't change between sessions, like the Spotify
+    /// URI of the track.
+    pub id: Option<String>,
+    /// The International Standard Recording Code of the track.
+    pub isrc: Option<String>,
+    /// The location of the audio being played, for local players.
+    pub url: Option<String>,
+}
+
+/// The playback controls that an API is able to perform on the source music
+/// player. Some of them may not be available at all times, as the player
+/// itself might disallow them (for example, skipping to the next song when
+/// the playlist has ended).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    pub play: bool,
+    pub pause: bool,
+    pub seek: bool,
+    pub next: bool,
+    pub previous: bool,
 }
 
 /// The abstract base class used for any API in this app. The API is defined
@@ -48,6 +82,28 @@ pub trait APIBase {
     /// Returns the title of the currently playing song.
     fn title(&self) -> Option<String>;
 
+    /// Returns all the metadata available for the currently playing song.
+    ///
+    /// By default, it only includes the artist and the title, so APIs with
+    /// more information should override it.
+    fn track_info(&self) -> TrackInfo {
+        TrackInfo {
+            artist: self.artist(),
+            title: self.title(),
+            ..TrackInfo::default()
+        }
+    }
+
+    /// Returns the metadata of the song that will be played after the
+    /// current one, so that its video can be prepared in advance. It's
+    /// `None` when the queue has ended.
+    ///
+    /// Most APIs don't expose the queue, so it returns `Error::Unsupported`
+    /// by default.
+    fn upcoming_track(&self) -> Result<Option<TrackInfo>> {
+        Err(Error::Unsupported)
+    }
+
     /// Returns the position in milliseconds of the currently playing song.
     fn position(&self) -> Option<time::Duration>;
 
@@ -65,17 +121,55 @@ pub trait APIBase {
     /// `NotImplementedError` instead. This information is saved in the API
     /// entry inside the API list so that the event loop isn't called.
     fn event_loop(&mut self);
+
+    /// Returns the playback controls currently supported by the API. The
+    /// APIs are read-only by default, so none of them will be available
+    /// unless this method is overriden.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Resumes the playback of the source music player.
+    ///
+    /// The control methods below return `Error::Unsupported` by default,
+    /// which should also be the case for implementations in which the
+    /// player disallows that operation.
+    fn play(&mut self) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// Pauses the playback of the source music player.
+    fn pause(&mut self) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// Moves the position of the currently playing song to the absolute
+    /// value given.
+    fn seek(&mut self, _position: time::Duration) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// Skips to the next song in the source music player.
+    fn next(&mut self) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// Goes back to the previous song in the source music player.
+    fn previous(&mut self) -> Result<()> {
+        Err(Error::Unsupported)
+    }
 }
 
 pub fn init_api(api: API, config: &Config) -> Result<Box<dyn APIBase>> {
     let api: Box<dyn APIBase> = match api {
-        #[cfg(any(target_os = "linux", target_os = "bsd"))]
+        #[cfg(target_os = "linux")]
         API::MPRIS => Box::new(mpris::MPRIS::new(config)?),
         #[cfg(target_os = "windows")]
         API::Windows => Box::new(windows::Windows::new(config)?),
         #[cfg(target_os = "macos")]
         API::MacOS => Box::new(macos::MacOS::new(config)?),
         API::SpotifyWeb => Box::new(spotifyweb::SpotifyWeb::new(config)?),
+        API::Fingerprint => Box::new(fingerprint::Fingerprint::new(config)?),
     };
 
     Ok(api)
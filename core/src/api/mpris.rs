@@ -1,9 +1,17 @@
-use crate::api::APIBase;
+use crate::api::{APIBase, Capabilities, TrackInfo};
 use crate::config::Config;
 use crate::error::{Result, Error};
 
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::Path;
 use std::time;
 
+use dbus::arg::{IterAppend, RefArg, Variant};
+use dbus::stdintf::org_freedesktop_dbus::Properties;
+use dbus::{BusType, Connection};
+use log::info;
 use mpris::{Player, PlayerFinder};
 
 impl From<mpris::DBusError> for Error {
@@ -18,17 +26,135 @@ impl From<mpris::FindingError> for Error {
     }
 }
 
+impl From<dbus::Error> for Error {
+    fn from(err: dbus::Error) -> Self {
+        Error::FailedConnection(
+            err.message().unwrap_or("unknown error").to_string(),
+        )
+    }
+}
+
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACKLIST_INTERFACE: &str = "org.mpris.MediaPlayer2.TrackList";
+
+/// The metadata of a track, as sent through D-Bus.
+type RawMetadata = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+/// The bus in which the MPRIS players will be looked for. It's usually the
+/// session bus, but players inside containers or in another seat may need
+/// a custom one.
+#[derive(Debug, PartialEq)]
+enum Bus {
+    Session,
+    System,
+    Address(String),
+}
+
+impl Bus {
+    /// Parses the `dbus_address` configuration option.
+    fn from_config(address: Option<&str>) -> Bus {
+        match address.map(str::trim) {
+            None | Some("") | Some("session") => Bus::session(),
+            Some("system") => Bus::System,
+            Some(address) => Bus::Address(address.to_string()),
+        }
+    }
+
+    /// `libdbus` only knows where the session bus is with the
+    /// `DBUS_SESSION_BUS_ADDRESS` environment variable. When it's not set
+    /// (e.g. outside of a graphical session), the standard location under
+    /// `XDG_RUNTIME_DIR` will be tried instead.
+    fn session() -> Bus {
+        if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+            if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+                let path = Path::new(&dir).join("bus");
+                if path.exists() {
+                    return Bus::Address(format!(
+                        "unix:path={}",
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        Bus::Session
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        info!("Connecting to the {} D-Bus", self);
+        let conn = match self {
+            Bus::Session => Connection::get_private(BusType::Session),
+            Bus::System => Connection::get_private(BusType::System),
+            // Connections opened with a custom address have to be
+            // registered manually in the bus.
+            Bus::Address(address) => Connection::open_private(address)
+                .and_then(|conn| conn.register().map(|_| conn)),
+        };
+
+        conn.map_err(|e| {
+            Error::FailedConnection(format!(
+                "couldn't open the {} D-Bus: {}",
+                self,
+                e.message().unwrap_or("unknown error")
+            ))
+        })
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bus::Session => match env::var("DBUS_SESSION_BUS_ADDRESS") {
+                Ok(address) => write!(f, "session ({})", address),
+                Err(_) => write!(f, "session"),
+            },
+            Bus::System => write!(f, "system"),
+            Bus::Address(address) => write!(f, "'{}'", address),
+        }
+    }
+}
+
 pub struct MPRIS<'a> {
     player: Player<'a>,
+    /// The `mpris` crate doesn't support the optional `TrackList`
+    /// interface, so it's accessed with a separate connection.
+    conn: Connection,
+}
+
+impl<'a> MPRIS<'a> {
+    /// Calls a method of the `TrackList` interface, which the `mpris` crate
+    /// doesn't implement.
+    fn tracks_metadata(
+        &self,
+        tracks: Vec<dbus::Path<'static>>,
+    ) -> Result<Vec<RawMetadata>> {
+        let path = self.conn.with_path(
+            self.player.unique_name(),
+            MPRIS_PATH,
+            self.player.dbus_timeout_ms(),
+        );
+        let mut reply = path.method_call_with_args(
+            &TRACKLIST_INTERFACE.into(),
+            &"GetTracksMetadata".into(),
+            |msg| IterAppend::new(msg).append(tracks),
+        )?;
+        reply.as_result()?;
+
+        reply
+            .read1()
+            .map_err(|e| Error::FailedRequest(e.to_string()))
+    }
 }
 
-// TODO: check `player.can_play` and similars?
 impl<'a> APIBase for MPRIS<'a> {
     fn new(config: &Config) -> Result<Self> {
-        let player = PlayerFinder::new()?.find_active()?;
+        let bus = Bus::from_config(config.dbus_address.as_deref());
+        let finder = PlayerFinder::for_connection(bus.connect()?);
+        let player = finder.find_active()?;
 
         Ok(MPRIS {
-            player
+            player,
+            conn: bus.connect()?,
         })
     }
 
@@ -56,6 +182,61 @@ impl<'a> APIBase for MPRIS<'a> {
         )
     }
 
+    fn track_info(&self) -> TrackInfo {
+        let metadata = match self.player.get_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => return TrackInfo::default(),
+        };
+        let artist = metadata
+            .artists()
+            .or_else(|| metadata.album_artists())
+            .and_then(|artists| artists.first().cloned());
+        let id =
+            Some(metadata.track_id().to_string()).filter(|id| !id.is_empty());
+
+        TrackInfo {
+            artist,
+            title: metadata.title().map(String::from),
+            album: metadata.album_name().map(String::from),
+            duration: metadata.length(),
+            art_url: metadata.art_url().map(String::from),
+            id,
+            isrc: None,
+            url: metadata.url().map(String::from),
+        }
+    }
+
+    /// The track list is optional in MPRIS, and only some players like VLC
+    /// or Clementine implement it.
+    fn upcoming_track(&self) -> Result<Option<TrackInfo>> {
+        let path = self.conn.with_path(
+            self.player.unique_name(),
+            MPRIS_PATH,
+            self.player.dbus_timeout_ms(),
+        );
+        let has_list: bool = path
+            .get("org.mpris.MediaPlayer2", "HasTrackList")
+            .unwrap_or(false);
+        if !has_list {
+            return Err(Error::Unsupported);
+        }
+
+        let tracks: Vec<dbus::Path<'static>> =
+            path.get(TRACKLIST_INTERFACE, "Tracks")?;
+        let current = self.player.get_metadata()?;
+        let next = tracks
+            .iter()
+            .position(|track| &**track == current.track_id())
+            .and_then(|pos| tracks.get(pos + 1));
+        let next = match next {
+            Some(next) => next.clone(),
+            None => return Ok(None),
+        };
+
+        let metadata = self.tracks_metadata(vec![next])?;
+        Ok(metadata.first().map(track_from_metadata))
+    }
+
     // TODO: return std::time::Duration, u128 or a more appropiate data type
     // to avoid `as`.
     fn position(&self) -> Option<time::Duration> {
@@ -69,4 +250,161 @@ impl<'a> APIBase for MPRIS<'a> {
     fn event_loop(&mut self) {
         unimplemented!();
     }
+
+    fn capabilities(&self) -> Capabilities {
+        // The player may refuse to be controlled at all, in which case the
+        // rest of the properties should be ignored.
+        if !self.player.can_control().unwrap_or(false) {
+            return Capabilities::default();
+        }
+
+        Capabilities {
+            play: self.player.can_play().unwrap_or(false),
+            pause: self.player.can_pause().unwrap_or(false),
+            seek: self.player.can_seek().unwrap_or(false),
+            next: self.player.can_go_next().unwrap_or(false),
+            previous: self.player.can_go_previous().unwrap_or(false),
+        }
+    }
+
+    fn play(&mut self) -> Result<()> {
+        Ok(self.player.play()?)
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        Ok(self.player.pause()?)
+    }
+
+    fn seek(&mut self, position: time::Duration) -> Result<()> {
+        // `SetPosition` requires the current track ID to avoid race
+        // conditions. Some players don't provide one, so a relative seek
+        // from the current position is used for those instead.
+        let metadata = self.player.get_metadata()?;
+        let track_id = metadata.track_id();
+        if track_id.is_empty() {
+            let current = self.player.get_position()?.as_micros() as i64;
+            let offset = position.as_micros() as i64 - current;
+            self.player.seek(offset)?;
+        } else {
+            self.player.set_position(track_id.to_string(), &position)?;
+        }
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        Ok(self.player.next()?)
+    }
+
+    fn previous(&mut self) -> Result<()> {
+        Ok(self.player.previous()?)
+    }
+}
+
+/// Reads the standard `xesam` and `mpris` fields of the metadata of a
+/// track. The `mpris` crate already does this for the current track, but
+/// not for the ones in the track list.
+fn track_from_metadata(metadata: &RawMetadata) -> TrackInfo {
+    let string = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(String::from)
+    };
+    // The artists are a list, but some players send a single string.
+    let first = |key: &str| {
+        let value = metadata.get(key)?;
+        value.as_str().map(String::from).or_else(|| {
+            value
+                .0
+                .as_iter()?
+                .next()
+                .and_then(|artist| artist.as_str())
+                .map(String::from)
+        })
+    };
+    let duration = metadata
+        .get("mpris:length")
+        .and_then(|value| {
+            // It should be signed, but some players send it unsigned.
+            value.as_u64().or_else(|| {
+                value.as_i64().filter(|len| *len >= 0).map(|len| len as u64)
+            })
+        })
+        .map(time::Duration::from_micros);
+
+    TrackInfo {
+        artist: first("xesam:artist").or_else(|| first("xesam:albumArtist")),
+        title: string("xesam:title"),
+        album: string("xesam:album"),
+        duration,
+        art_url: string("mpris:artUrl"),
+        id: string("mpris:trackid").filter(|id| !id.is_empty()),
+        isrc: None,
+        url: string("xesam:url"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bus_from_config() {
+        assert_eq!(Bus::from_config(Some("system")), Bus::System);
+        assert_eq!(Bus::from_config(Some(" system ")), Bus::System);
+        assert_eq!(
+            Bus::from_config(Some("unix:path=/run/user/1000/bus")),
+            Bus::Address(String::from("unix:path=/run/user/1000/bus"))
+        );
+        assert_eq!(
+            Bus::from_config(Some("tcp:host=localhost,port=1234")),
+            Bus::Address(String::from("tcp:host=localhost,port=1234"))
+        );
+        assert_eq!(Bus::from_config(None), Bus::from_config(Some("session")));
+        assert_eq!(Bus::from_config(Some("")), Bus::from_config(None));
+    }
+
+    #[test]
+    fn tracklist_metadata() {
+        fn value<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+            Variant(Box::new(value))
+        }
+
+        let mut metadata = RawMetadata::new();
+        metadata.insert(
+            "mpris:trackid".into(),
+            value(dbus::Path::from("/org/videolan/vlc/playlist/5")),
+        );
+        metadata.insert(
+            "xesam:artist".into(),
+            value(vec![String::from("Queen"), String::from("David Bowie")]),
+        );
+        metadata.insert("xesam:title".into(), value(String::from("Pressure")));
+        metadata.insert("mpris:length".into(), value(248_000_000i64));
+        metadata.insert(
+            "xesam:url".into(),
+            value(String::from("file:///music/pressure.flac")),
+        );
+        assert_eq!(
+            track_from_metadata(&metadata),
+            TrackInfo {
+                artist: Some(String::from("Queen")),
+                title: Some(String::from("Pressure")),
+                duration: Some(time::Duration::from_secs(248)),
+                id: Some(String::from("/org/videolan/vlc/playlist/5")),
+                url: Some(String::from("file:///music/pressure.flac")),
+                ..TrackInfo::default()
+            }
+        );
+
+        // Some players send a single artist, and an unsigned length.
+        metadata.insert("xesam:artist".into(), value(String::from("Queen")));
+        metadata.insert("mpris:length".into(), value(1_000_000u64));
+        let track = track_from_metadata(&metadata);
+        assert_eq!(track.artist.as_deref(), Some("Queen"));
+        assert_eq!(track.duration, Some(time::Duration::from_secs(1)));
+        let empty = RawMetadata::new();
+        assert_eq!(track_from_metadata(&empty), TrackInfo::default());
+    }
 }
@@ -4,8 +4,14 @@
 //!     * The user has to sign in and manually set it up
 //!     * Only Spotify Premium users are able to use some functions
 //!     * API calls are limited, so it's not as responsive
+//!
+//! Controlling the playback (play, pause, seek...) is also supported, but
+//! the endpoints will fail for users without Spotify Premium.
+//!
+//! `rspotify` doesn't implement the queue endpoint yet, so it's requested
+//! directly with the access token.
 
-use crate::api::APIBase;
+use crate::api::{APIBase, Capabilities, TrackInfo};
 use crate::config::Config;
 use crate::error::{Error, Result};
 
@@ -21,10 +27,24 @@ use rspotify::blocking::oauth2::{
     SpotifyClientCredentials, SpotifyOAuth, TokenInfo,
 };
 use rspotify::model::playing::Playing;
+use rspotify::model::track::FullTrack;
+use rspotify::senum::DisallowKey;
+use serde::Deserialize;
+
+const QUEUE_URL: &str = "https://api.spotify.com/v1/me/player/queue";
 
 pub struct SpotifyWeb {
     spotify: Spotify,
     playing: Playing,
+    /// Used for the endpoints not available in `rspotify`.
+    access_token: String,
+}
+
+/// The response of the queue endpoint. Its items may also be podcast
+/// episodes, so they're parsed later on.
+#[derive(Deserialize)]
+struct Queue {
+    queue: Vec<serde_json::Value>,
 }
 
 impl APIBase for SpotifyWeb {
@@ -35,20 +55,24 @@ impl APIBase for SpotifyWeb {
                 &config.client_secret.clone().ok_or(Error::SpotifyWebAuth)?,
             )
             .redirect_uri(&config.redirect_uri)
-            .scope("user-read-currently-playing user-read-playback-state")
+            .scope(
+                "user-read-currently-playing user-read-playback-state \
+                 user-modify-playback-state",
+            )
             .build();
 
         // The refresh token is attempted to be reused from previous
         // sessions.
         let token = match &config.refresh_token {
             Some(token) => oauth
-                .refresh_access_token_without_cache(&token)
+                .refresh_access_token_without_cache(token)
                 .ok_or(Error::SpotifyWebAuth)?,
             // TODO: use the GUI for this once it's finished
             None => get_token(&mut oauth)?,
         };
 
         // Once the access token is ready, the Spotify API can be initialized.
+        let access_token = token.access_token.clone();
         let creds = SpotifyClientCredentials::default()
             .token_info(token)
             .build();
@@ -64,6 +88,7 @@ impl APIBase for SpotifyWeb {
                 .map_err(|e| Error::FailedRequest(e.to_string()))?
                 .ok_or(Error::NoTrackPlaying)?,
             spotify,
+            access_token,
         })
     }
 
@@ -82,7 +107,7 @@ impl APIBase for SpotifyWeb {
 
     fn artist(&self) -> Option<String> {
         if let Some(item) = &self.playing.item {
-            if let Some(artist) = item.artists.get(0) {
+            if let Some(artist) = item.artists.first() {
                 return Some(artist.name.clone());
             }
         }
@@ -94,6 +119,24 @@ impl APIBase for SpotifyWeb {
         Some(self.playing.item.as_ref()?.name.clone())
     }
 
+    fn track_info(&self) -> TrackInfo {
+        match &self.playing.item {
+            Some(item) => track_from_full(item),
+            None => TrackInfo::default(),
+        }
+    }
+
+    fn upcoming_track(&self) -> Result<Option<TrackInfo>> {
+        let data = reqwest::blocking::Client::new()
+            .get(QUEUE_URL)
+            .bearer_auth(&self.access_token)
+            .send()?
+            .error_for_status()?
+            .text()?;
+
+        parse_next_track(&data)
+    }
+
     fn position(&self) -> Option<time::Duration> {
         Some(time::Duration::from_millis(self.playing.progress_ms? as u64))
     }
@@ -105,8 +148,85 @@ impl APIBase for SpotifyWeb {
     fn event_loop(&mut self) {
         unimplemented!();
     }
+
+    fn capabilities(&self) -> Capabilities {
+        // The currently playing track doesn't include the disallowed
+        // actions, so the full playback context has to be requested.
+        let context = match self.spotify.current_playback(None, None) {
+            Ok(Some(context)) => context,
+            _ => return Capabilities::default(),
+        };
+        let disallows = &context.actions.disallows;
+        let allowed = |key| !disallows.get(&key).cloned().unwrap_or(false);
+
+        Capabilities {
+            play: allowed(DisallowKey::Resuming),
+            pause: allowed(DisallowKey::Pausing),
+            seek: allowed(DisallowKey::Seeking),
+            next: allowed(DisallowKey::SkippingNext),
+            previous: allowed(DisallowKey::SkippingPrev),
+        }
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.spotify
+            .start_playback(None, None, None, None, None)
+            .map_err(|e| Error::FailedRequest(e.to_string()))
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.spotify
+            .pause_playback(None)
+            .map_err(|e| Error::FailedRequest(e.to_string()))
+    }
+
+    fn seek(&mut self, position: time::Duration) -> Result<()> {
+        self.spotify
+            .seek_track(position.as_millis() as u32, None)
+            .map_err(|e| Error::FailedRequest(e.to_string()))
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.spotify
+            .next_track(None)
+            .map_err(|e| Error::FailedRequest(e.to_string()))
+    }
+
+    fn previous(&mut self) -> Result<()> {
+        self.spotify
+            .previous_track(None)
+            .map_err(|e| Error::FailedRequest(e.to_string()))
+    }
 }
 
+fn track_from_full(item: &FullTrack) -> TrackInfo {
+    TrackInfo {
+        artist: item.artists.first().map(|artist| artist.name.clone()),
+        title: Some(item.name.clone()),
+        album: Some(item.album.name.clone()),
+        duration: Some(time::Duration::from_millis(item.duration_ms as u64)),
+        // The images are ordered by size, the widest first.
+        art_url: item.album.images.first().map(|img| img.url.clone()),
+        id: Some(item.uri.clone()),
+        isrc: item.external_ids.get("isrc").cloned(),
+        url: None,
+    }
+}
+
+/// Obtains the next track from the user's queue, which is its first item.
+/// The rest of the queue isn't needed, since only the next video is
+/// prefetched. Podcast episodes don't have music videos, so a next
+/// episode is ignored.
+fn parse_next_track(data: &str) -> Result<Option<TrackInfo>> {
+    let queue: Queue = serde_json::from_str(data)?;
+    let next = queue
+        .queue
+        .into_iter()
+        .next()
+        .and_then(|item| serde_json::from_value::<FullTrack>(item).ok());
+
+    Ok(next.as_ref().map(track_from_full))
+}
 
 /// A small server will be ran to obtain the access token without user
 /// interaction, besides logging in to Spotify in the browser.
@@ -172,7 +292,7 @@ fn get_code(mut stream: TcpStream) -> Result<String> {
         include_str!("spotifyweb_response.html")
     )?;
 
-    Ok(extract_code(&req_data)?)
+    extract_code(&req_data)
 }
 
 /// Obtaining the code from the HTTP request format without the need for a
@@ -220,4 +340,70 @@ mod test {
     fn incorrect_bind_format() {
         to_bind_format("localhost:8888");
     }
+
+    const QUEUE: &str = r#"{
+        "currently_playing": null,
+        "queue": [
+            {
+                "album": {
+                    "album_type": "album",
+                    "artists": [],
+                    "external_urls": {},
+                    "href": null,
+                    "id": "2noRn2Aes5aoNVsU6iWThc",
+                    "images": [
+                        {"height": 640, "width": 640, "url": "https://i.scdn.co/image/ab67616d0000b273discovery640"},
+                        {"height": 300, "width": 300, "url": "https://i.scdn.co/image/ab67616d00001e02discovery300"}
+                    ],
+                    "name": "Discovery",
+                    "type": "album",
+                    "uri": "spotify:album:2noRn2Aes5aoNVsU6iWThc"
+                },
+                "artists": [
+                    {"external_urls": {}, "href": null, "id": "4tZwfgrHOc3mvqYlEYSvVi", "name": "Daft Punk", "type": "artist", "uri": "spotify:artist:4tZwfgrHOc3mvqYlEYSvVi"}
+                ],
+                "disc_number": 1,
+                "duration_ms": 320357,
+                "explicit": false,
+                "external_ids": {"isrc": "GBDUW0000053"},
+                "external_urls": {},
+                "href": null,
+                "id": "0DiWol3AO6WpXZgp0goxAV",
+                "is_local": false,
+                "name": "One More Time",
+                "popularity": 80,
+                "preview_url": null,
+                "track_number": 1,
+                "type": "track",
+                "uri": "spotify:track:0DiWol3AO6WpXZgp0goxAV"
+            },
+            {"type": "episode", "name": "News"}
+        ]
+    }"#;
+
+    #[test]
+    fn queue() {
+        let track = parse_next_track(QUEUE).unwrap().unwrap();
+        assert_eq!(
+            track,
+            TrackInfo {
+                artist: Some(String::from("Daft Punk")),
+                title: Some(String::from("One More Time")),
+                album: Some(String::from("Discovery")),
+                duration: Some(time::Duration::from_millis(320357)),
+                art_url: Some(String::from(
+                    "https://i.scdn.co/image/ab67616d0000b273discovery640"
+                )),
+                id: Some(String::from("spotify:track:0DiWol3AO6WpXZgp0goxAV")),
+                isrc: Some(String::from("GBDUW0000053")),
+                url: None,
+            }
+        );
+
+        let empty = r#"{"currently_playing": null, "queue": []}"#;
+        assert_eq!(parse_next_track(empty).unwrap(), None);
+        let episode = r#"{"queue": [{"type": "episode", "name": "News"}]}"#;
+        assert_eq!(parse_next_track(episode).unwrap(), None);
+        assert!(parse_next_track("{}").is_err());
+    }
 }
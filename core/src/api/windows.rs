@@ -7,7 +7,7 @@ use std::time;
 pub struct Windows {}
 
 impl APIBase for Windows {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(_config: &Config) -> Result<Self> {
         Ok(Windows {})
     }
 
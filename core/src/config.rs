@@ -1,10 +1,13 @@
 use crate::api::API;
 use crate::data::{Res, ResKind};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::lyrics::Lyrics;
 use crate::player::Player;
+use crate::video::score::Scorer;
+use crate::video::Provider;
 
 use clap::App;
+use ini::Ini;
 use structconf::StructConf;
 
 /// The config file saves the app's state and configuration in a config file,
@@ -21,6 +24,29 @@ pub struct Config {
     #[conf(no_file, help = "The config file path")]
     pub conf_file: Option<String>,
 
+    /// Merges a file with video overrides into the saved ones, instead of
+    /// starting the app.
+    #[conf(
+        no_file,
+        no_short,
+        help = "Import the pinned and blocked videos from a file"
+    )]
+    pub import_overrides: Option<String>,
+
+    #[conf(
+        no_file,
+        no_short,
+        help = "Export the pinned and blocked videos to a file"
+    )]
+    pub export_overrides: Option<String>,
+
+    #[conf(
+        no_file,
+        no_short,
+        help = "Remove the cached videos instead of starting the app"
+    )]
+    pub clear_cache: bool,
+
     /// Showing the lyrics. For the argument parser, it's a negated option,
     /// meaning that it has to be set to False in the config file to be
     /// equivalent.
@@ -48,10 +74,287 @@ pub struct Config {
     /// The Player used. The player names are exactly the ones found in the
     /// `core::player::Player` enum, case sensitive.
     #[conf(
-        help = "The output video player. Read the installation guide for \
-           a list with the available players"
+        help = "The output video player, MpvIpc by default. Read the \
+           installation guide for a list with the available players"
+    )]
+    pub player: Player,
+
+    /// The video provider used. The provider names are exactly the ones
+    /// found in the `core::video::Provider` enum, case sensitive.
+    #[conf(
+        no_short,
+        help = "The provider used to look for the music videos. Read the \
+           installation guide for a list with the available providers"
+    )]
+    pub video_provider: Provider,
+
+    #[conf(
+        no_short,
+        help = "The maximum number of videos obtained in each search",
+        default = "5"
+    )]
+    pub video_results: u32,
+
+    #[conf(
+        no_short,
+        help = "The maximum number of videos tried for a track when they \
+           fail to play",
+        default = "3"
+    )]
+    pub video_attempts: u32,
+
+    /// The template used to search for the music videos, like `{artist} -
+    /// {title} official video`. The available placeholders are `{artist}`,
+    /// `{title}`, `{album}` and `{featured}`, which are replaced with the
+    /// metadata of the track after removing decorations like `Remastered`.
+    #[conf(
+        no_short,
+        help = "The template used to search for the music videos, with the \
+           placeholders {artist}, {title}, {album} and {featured}",
+        default = "String::from(\"{artist} - {title}\")"
+    )]
+    pub video_query: String,
+
+    #[conf(
+        no_short,
+        help = "The template used to search for the music videos when the \
+           artist is unknown",
+        default = "String::from(\"{title}\")"
+    )]
+    pub video_query_title: String,
+
+    #[conf(
+        no_short,
+        help = "Don't look for the video of the next track in the queue \
+           before it starts"
+    )]
+    pub no_prefetch: bool,
+
+    #[conf(
+        no_short,
+        help = "Load the video of the next track in the player before it \
+           starts, if supported"
+    )]
+    pub preload: bool,
+
+    #[conf(
+        no_short,
+        help = "The path to the yt-dlp binary",
+        section = "YtDlp",
+        default = "String::from(\"yt-dlp\")"
+    )]
+    pub ytdlp_path: String,
+
+    /// The `--format` selector passed to yt-dlp. It should select a single
+    /// file with both video and audio.
+    #[conf(
+        no_short,
+        help = "The yt-dlp format selector used for the stream URLs",
+        section = "YtDlp",
+        default = "String::from(\"best\")"
+    )]
+    pub ytdlp_format: String,
+
+    #[conf(
+        no_short,
+        help = "The Invidious instances used, separated by spaces. The \
+           next one is tried whenever a request fails",
+        section = "Invidious",
+        default = "String::from(\"https://yewtu.be https://inv.nadeko.net\")"
+    )]
+    pub invidious_instances: String,
+
+    #[conf(
+        no_short,
+        help = "The Piped API instances used, separated by spaces. The \
+           next one is tried whenever a request fails",
+        section = "Piped",
+        default = "String::from(\"https://pipedapi.kavin.rocks\")"
+    )]
+    pub piped_instances: String,
+
+    /// The directories with the downloaded music videos, separated like the
+    /// `PATH` environment variable.
+    #[conf(
+        no_short,
+        help = "The directories with the local music videos, separated like \
+           the PATH variable",
+        section = "Library"
+    )]
+    pub video_library: Option<String>,
+
+    /// Used to read the embedded tags of the local music videos. If it's not
+    /// available, only the file names are used.
+    #[conf(
+        no_short,
+        help = "The path to the ffprobe binary, used to read the tags of \
+           the local music videos",
+        section = "Library",
+        default = "String::from(\"ffprobe\")"
+    )]
+    pub ffprobe_path: String,
+
+    /// The weights used to score the videos found. They can also be
+    /// negative, or zero to be ignored.
+    #[conf(
+        no_short,
+        help = "The score for a video with the same duration as the song",
+        section = "Scoring",
+        default = "Scorer::default().duration"
+    )]
+    pub score_duration: f32,
+
+    #[conf(
+        no_short,
+        help = "The score for a video uploaded by the artist, or by an \
+           official or VEVO channel",
+        section = "Scoring",
+        default = "Scorer::default().official_channel"
+    )]
+    pub score_official_channel: f32,
+
+    #[conf(
+        no_short,
+        help = "The score for a video with \"official video\" in its title",
+        section = "Scoring",
+        default = "Scorer::default().official_title"
+    )]
+    pub score_official_title: f32,
+
+    #[conf(
+        no_short,
+        help = "The penalty for live, cover, lyrics, reaction, karaoke or \
+           nightcore videos",
+        section = "Scoring",
+        default = "Scorer::default().unwanted"
+    )]
+    pub score_unwanted: f32,
+
+    #[conf(
+        no_short,
+        help = "The minimum score of a video to be played",
+        section = "Scoring",
+        default = "Scorer::default().min_score"
+    )]
+    pub min_score: f32,
+
+    #[conf(
+        no_short,
+        help = "The hours for which the video chosen for a song is cached",
+        section = "Cache",
+        default = "720"
+    )]
+    pub cache_ttl: u64,
+
+    /// The stream URLs usually stop working after a few hours.
+    #[conf(
+        no_short,
+        help = "The minutes for which the stream URL of a video is cached",
+        section = "Cache",
+        default = "300"
+    )]
+    pub cache_stream_ttl: u64,
+
+    /// The failures are usually temporary, like a video that's still
+    /// being processed or a network error.
+    #[conf(
+        no_short,
+        help = "The hours for which a video that failed to play is avoided",
+        section = "Cache",
+        default = "6"
+    )]
+    pub cache_failure_ttl: u64,
+
+    #[conf(
+        no_short,
+        help = "The maximum number of songs cached, or zero to disable the \
+           cache",
+        section = "Cache",
+        default = "1000"
+    )]
+    pub cache_size: usize,
+
+    #[conf(
+        no_short,
+        help = "Download the videos played to watch them offline",
+        section = "Downloads"
+    )]
+    pub download: bool,
+
+    #[conf(
+        no_short,
+        help = "The maximum size of the downloaded videos in megabytes",
+        section = "Downloads",
+        default = "2048"
+    )]
+    pub download_size: u64,
+
+    #[conf(
+        no_short,
+        help = "Only play the videos already downloaded",
+        section = "Downloads"
+    )]
+    pub offline: bool,
+
+    /// What's shown when no music video is found, from `Artwork`, `Artist`
+    /// and `Screensaver`. The next one is tried if the previous one isn't
+    /// available.
+    #[conf(
+        no_short,
+        help = "What to show when no video is found, separated by spaces: \
+           Artwork, Artist or Screensaver",
+        section = "Fallback",
+        default = "String::from(\"Artwork Screensaver\")"
+    )]
+    pub fallback: String,
+
+    #[conf(
+        no_short,
+        help = "The directory with the clips used as a screensaver",
+        section = "Fallback"
+    )]
+    pub fallback_clips: Option<String>,
+
+    #[conf(
+        no_short,
+        help = "How often to look for the video again while the fallback is \
+           shown, in seconds",
+        section = "Fallback",
+        default = "60"
+    )]
+    pub fallback_retry: u64,
+
+    #[conf(
+        no_short,
+        help = "Don't use SponsorBlock to skip the parts of the videos \
+           without music",
+        section = "SponsorBlock"
+    )]
+    pub no_sponsorblock: bool,
+
+    #[conf(
+        no_short,
+        help = "The URL of the SponsorBlock instance",
+        section = "SponsorBlock",
+        default = "String::from(\"https://sponsor.ajay.app\")"
+    )]
+    pub sponsorblock_url: String,
+
+    #[conf(
+        no_short,
+        help = "Look for the official music videos in MusicBrainz, which \
+           makes the search a few seconds slower",
+        section = "MusicBrainz"
     )]
-    pub player: Option<Player>,
+    pub musicbrainz: bool,
+
+    #[conf(
+        no_short,
+        help = "The URL of the MusicBrainz server",
+        section = "MusicBrainz",
+        default = "String::from(\"https://musicbrainz.org\")"
+    )]
+    pub musicbrainz_url: String,
 
     #[conf(
         no_short,
@@ -69,11 +372,78 @@ pub struct Config {
 
     #[conf(
         no_short,
-        help = "Custom boolean flags used when opening mpv, with dashes and \
-           separated by spaces"
+        help = "Custom flags used when opening mpv, like `--fs \
+           --volume=50`, separated by spaces"
     )]
     pub mpv_flags: String,
 
+    /// The profiles are sections named `mpv.<name>` in the config file,
+    /// with the flags in the `flags` key.
+    #[conf(
+        no_short,
+        help = "The profile with more mpv flags to use, defined in the \
+           config file as a [mpv.<name>] section with the flags in its \
+           'flags' key"
+    )]
+    pub mpv_profile: Option<String>,
+
+    #[conf(
+        no_short,
+        default = "String::from(\"mpv\")",
+        help = "The mpv binary started by the MpvIpc player"
+    )]
+    pub mpv_path: String,
+
+    #[conf(
+        no_short,
+        default = "String::from(\"127.0.0.1:8786\")",
+        help = "The address where the External player waits for the apps \
+           that play the videos. Use 0.0.0.0 to accept the ones in other \
+           devices, like a TV in the same network",
+        section = "External"
+    )]
+    pub external_address: String,
+
+    #[conf(
+        no_short,
+        default = "String::from(\"0.0.0.0:8787\")",
+        help = "The address where the Browser player serves its page, which \
+           can be opened in any device in the same network",
+        section = "Browser"
+    )]
+    pub browser_address: String,
+
+    #[conf(
+        no_short,
+        default = "String::from(\"http://localhost:8080\")",
+        help = "The URL of the web server in Kodi, used by the Kodi player",
+        section = "Kodi"
+    )]
+    pub kodi_url: String,
+
+    #[conf(
+        no_short,
+        help = "The username for the web server in Kodi",
+        section = "Kodi"
+    )]
+    pub kodi_username: Option<String>,
+
+    #[conf(
+        no_short,
+        help = "The password for the web server in Kodi",
+        section = "Kodi"
+    )]
+    pub kodi_password: Option<String>,
+
+    #[conf(
+        no_short,
+        help = "The URL of the description of the DLNA renderer, like \
+           `http://192.168.1.20:49152/description.xml`. The first one found \
+           in the network is used if it's not set",
+        section = "DLNA"
+    )]
+    pub dlna_renderer: Option<String>,
+
     #[conf(
         no_short,
         help = "The client ID for the Spotify Web API. Check the guide to \
@@ -100,6 +470,61 @@ pub struct Config {
 
     #[conf(no_short, no_long, section = "SpotifyWeb")]
     pub refresh_token: Option<String>,
+
+    /// The D-Bus used by MPRIS. It can either be `session` (the default),
+    /// `system`, or a full address like `unix:path=/run/user/1000/bus`.
+    #[conf(
+        no_short,
+        help = "The D-Bus used for MPRIS: \"session\", \"system\", or a \
+           custom address like \"unix:path=/run/user/1000/bus\"",
+        section = "MPRIS"
+    )]
+    pub dbus_address: Option<String>,
+
+    /// The audio used to identify the song with fingerprinting. It may be a
+    /// WAV file, or raw PCM like a FIFO with a loopback capture.
+    #[conf(
+        no_short,
+        help = "The audio input for fingerprinting: a WAV file, or raw \
+           signed 16 bit little endian PCM, like a FIFO",
+        section = "Fingerprint"
+    )]
+    pub fingerprint_input: Option<String>,
+
+    /// The directories with the music library, separated like the `PATH`
+    /// environment variable. Only the WAV files in them are indexed.
+    #[conf(
+        no_short,
+        help = "The directories with the WAV files to be indexed for \
+           fingerprinting, separated like the PATH variable; other \
+           formats like MP3 or FLAC are ignored",
+        section = "Fingerprint"
+    )]
+    pub fingerprint_library: Option<String>,
+
+    #[conf(
+        no_short,
+        help = "The sample rate of the raw PCM fingerprinting input",
+        section = "Fingerprint",
+        default = "44100"
+    )]
+    pub fingerprint_sample_rate: u32,
+
+    #[conf(
+        no_short,
+        help = "The number of channels of the raw PCM fingerprinting input",
+        section = "Fingerprint",
+        default = "2"
+    )]
+    pub fingerprint_channels: u16,
+
+    #[conf(
+        no_short,
+        help = "Seconds of audio used to identify the song",
+        section = "Fingerprint",
+        default = "10"
+    )]
+    pub fingerprint_window: u32,
 }
 
 /// Initializes the application's configuration structure. The config file
@@ -117,6 +542,60 @@ pub fn init_config() -> Result<Config> {
         None => Res::new(ResKind::Config(String::from("config.ini")))?,
     };
 
-    let conf = Config::parse_file(&args, &path)?;
+    let mut conf = Config::parse_file(&args, &path)?;
+    if let Some(profile) = &conf.mpv_profile {
+        // The profile's flags override the common ones.
+        let flags = mpv_profile_flags(&path, profile)?;
+        conf.mpv_flags = format!("{} {}", conf.mpv_flags, flags);
+    }
+
     Ok(conf)
 }
+
+/// The mpv flags in a profile of the config file.
+pub fn mpv_profile_flags(path: &str, profile: &str) -> Result<String> {
+    let file = Ini::load_from_file(path).map_err(|e| {
+        Error::InvalidConfig(format!("couldn't read {}: {}", path, e))
+    })?;
+    let section =
+        file.section(Some(format!("mpv.{}", profile)))
+            .ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "the mpv profile '{}' doesn't exist",
+                    profile
+                ))
+            })?;
+
+    Ok(section.get("flags").unwrap_or("").to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::temp_dir;
+
+    use std::fs;
+
+    #[test]
+    fn mpv_profiles() {
+        let dir = temp_dir("config");
+        let path = dir.join("config.ini").to_string_lossy().into_owned();
+        fs::write(
+            &path,
+            "mpv_flags = --no-border\n\n\
+             [mpv.tv]\n\
+             flags = --fs --title=\"Living room\"\n\n\
+             [mpv.empty]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            mpv_profile_flags(&path, "tv").unwrap(),
+            "--fs --title=\"Living room\""
+        );
+        assert_eq!(mpv_profile_flags(&path, "empty").unwrap(), "");
+        let error = mpv_profile_flags(&path, "phone").unwrap_err();
+        assert!(error.to_string().contains("'phone'"));
+    }
+}
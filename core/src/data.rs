@@ -1,8 +1,9 @@
 use crate::error::Result;
 
-use std::fs::create_dir;
+use std::fs::{self, create_dir};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time;
 
 use dirs::*;
 
@@ -67,3 +68,81 @@ impl Deref for Res {
         &self.path
     }
 }
+
+/// Recursively looks for the files with any of the given extensions inside
+/// a directory, saving their path and their last modification time (in
+/// seconds since the UNIX epoch).
+pub fn find_files(
+    dir: &Path,
+    extensions: &[&str],
+    files: &mut Vec<(String, u64)>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, extensions, files)?;
+            continue;
+        }
+
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        if ext.is_some_and(|ext| extensions.contains(&ext.as_str())) {
+            files
+                .push((path.to_string_lossy().into_owned(), modified(&path)?));
+        }
+    }
+
+    Ok(())
+}
+
+/// The last modification time of a file, in seconds since the UNIX epoch.
+pub fn modified(path: &Path) -> Result<u64> {
+    let secs = fs::metadata(path)?
+        .modified()?
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    Ok(secs)
+}
+
+/// Obtains the artist and title from a file name with the format
+/// `Artist - Title.ext`. If there's no separator, the full name is
+/// considered the title.
+pub fn parse_file_name(path: &Path) -> (Option<String>, String) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    match stem.find(" - ") {
+        Some(pos) => (
+            Some(stem[..pos].trim().to_string()),
+            stem[pos + 3..].trim().to_string(),
+        ),
+        None => (None, stem.trim().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            parse_file_name(Path::new("/music/Daft Punk - One More Time.wav")),
+            (Some("Daft Punk".into()), "One More Time".into())
+        );
+        assert_eq!(
+            parse_file_name(Path::new("AC-DC - Back In Black - Live.mkv")),
+            (Some("AC-DC".into()), "Back In Black - Live".into())
+        );
+        assert_eq!(
+            parse_file_name(Path::new("/music/untitled.wav")),
+            (None, "untitled".into())
+        );
+    }
+}
@@ -8,11 +8,19 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     ConfigParse(structconf::Error),
+    InvalidConfig(String),
     IO(std::io::Error),
+    Json(serde_json::Error),
     FailedRequest(String),
     NoTrackPlaying,
+    NotDownloaded,
     SpotifyWebAuth,
     FailedConnection(String),
+    Unsupported,
+    InvalidAudio(String),
+    MissingBinary(String),
+    FailedCommand(String),
+    Player(String),
 }
 
 impl fmt::Display for Error {
@@ -22,13 +30,21 @@ impl fmt::Display for Error {
             ConfigParse(e) => {
                 write!(f, "Failed parsing the configuration: {}", e)
             }
+            InvalidConfig(e) => write!(f, "Invalid configuration: {}", e),
             IO(e) => write!(f, "IO error: {}", e),
+            Json(e) => write!(f, "JSON error: {}", e),
             FailedRequest(e) => write!(f, "Failed request: {}", e),
             NoTrackPlaying => write!(f, "No track currently playing"),
+            NotDownloaded => write!(f, "The track's video isn't downloaded"),
             SpotifyWebAuth => {
                 write!(f, "Couldn't authenticate Spotify Web API")
             }
             FailedConnection(e) => write!(f, "Failed to connect: {}", e),
+            Unsupported => write!(f, "Operation not supported"),
+            InvalidAudio(e) => write!(f, "Invalid audio: {}", e),
+            MissingBinary(e) => write!(f, "Couldn't find the binary '{}'", e),
+            FailedCommand(e) => write!(f, "Command failed: {}", e),
+            Player(e) => write!(f, "Player error: {}", e),
         }
     }
 }
@@ -44,3 +60,21 @@ impl From<std::io::Error> for Error {
         Error::IO(err)
     }
 }
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::FailedRequest(err.to_string())
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        Error::FailedConnection(err.to_string())
+    }
+}
@@ -4,3 +4,6 @@ pub mod data;
 pub mod error;
 pub mod lyrics;
 pub mod player;
+#[cfg(test)]
+mod testing;
+pub mod video;
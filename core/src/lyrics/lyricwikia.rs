@@ -11,7 +11,7 @@ impl LyricsBase for LyricWikia {
         Ok(LyricWikia {})
     }
 
-    fn get_lyrics(&self, artist: &str, title: &str) -> &str {
+    fn get_lyrics(&self, _artist: &str, _title: &str) -> &str {
         "some lyrics go here"
     }
 }
@@ -8,18 +8,13 @@ use crate::lyrics::lyricwikia::LyricWikia;
 
 use strum_macros::{Display, EnumString};
 
-#[derive(Clone, Debug, Display, EnumString)]
+#[derive(Clone, Debug, Default, Display, EnumString)]
 pub enum Lyrics {
     None,
+    #[default]
     LyricWikia,
 }
 
-impl Default for Lyrics {
-    fn default() -> Self {
-        Lyrics::LyricWikia
-    }
-}
-
 pub trait LyricsBase {
     fn new() -> Result<Self>
     where
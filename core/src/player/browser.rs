@@ -0,0 +1,166 @@
+//! A player that serves a page with a `<video>` element, so that any device
+//! in the same network with a browser, like a smart TV, can play the
+//! videos. The page is controlled with the protocol of the External player,
+//! in the same address.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::player::external::External;
+use crate::player::{Event, PlayerBase, VideoSource};
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use log::info;
+
+const PAGE: &str = include_str!("browser.html");
+
+pub struct Browser {
+    external: External,
+}
+
+impl Browser {
+    /// Serves the page in the address, like `0.0.0.0:8787`.
+    pub fn bind(address: &str) -> Result<Browser> {
+        let external = External::with_page(address, PAGE)?;
+        info!(
+            "Open http://{} in a browser to watch the videos",
+            external.address()
+        );
+        Ok(Browser { external })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.external.address()
+    }
+}
+
+impl PlayerBase for Browser {
+    fn new(config: &Config) -> Result<Self> {
+        Browser::bind(&config.browser_address)
+    }
+
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        self.external.load(source)
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.external.play()
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.external.pause()
+    }
+
+    fn is_paused(&self) -> bool {
+        self.external.is_paused()
+    }
+
+    fn position(&self) -> Duration {
+        self.external.position()
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        self.external.seek(position)
+    }
+
+    fn volume(&self) -> u32 {
+        self.external.volume()
+    }
+
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        self.external.set_volume(volume)
+    }
+
+    fn is_muted(&self) -> bool {
+        self.external.is_muted()
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.external.set_muted(muted)
+    }
+
+    fn speed(&self) -> f64 {
+        self.external.speed()
+    }
+
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        self.external.set_speed(speed)
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        self.external.poll_event()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::player::external::client::ExternalClient;
+    use crate::player::external::protocol::{
+        Capability, ClientMessage, ServerMessage,
+    };
+
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn page() {
+        let player = Browser::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", player.address());
+        let page = reqwest::blocking::get(&url).unwrap();
+        assert!(page.status().is_success());
+        assert!(page.text().unwrap().contains("<video"));
+
+        let missing = reqwest::blocking::get(&format!("{}/missing", url));
+        assert_eq!(missing.unwrap().status().as_u16(), 404);
+    }
+
+    /// The page is replaced by a client that speaks the same protocol.
+    #[test]
+    fn headless() {
+        let mut player = Browser::bind("127.0.0.1:0").unwrap();
+        let address = player.address().to_string();
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut client = ExternalClient::connect(
+                &address,
+                "Headless",
+                &[Capability::Load],
+            )
+            .unwrap();
+            while let Ok(command) = client.recv() {
+                client.ack(command.id().unwrap()).unwrap();
+                if let ServerMessage::Load { .. } = command {
+                    client.send(&ClientMessage::Loaded).unwrap();
+                }
+                sender.send(command).unwrap();
+            }
+        });
+
+        // The state is sent once it's connected.
+        let start = Instant::now();
+        while received.try_recv().is_err() {
+            player.poll_event();
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        player
+            .load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+        match received.recv().unwrap() {
+            ServerMessage::Load { url, .. } => {
+                assert_eq!(url, "https://videos/song.mp4")
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+        let start = Instant::now();
+        while player.poll_event() != Some(Event::Loaded) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
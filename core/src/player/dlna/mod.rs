@@ -0,0 +1,910 @@
+//! A player that sends the videos to a DLNA/UPnP MediaRenderer, like most
+//! smart TVs, with the SOAP actions of its AVTransport service. The volume
+//! is controlled with its RenderingControl service, if any. The renderer
+//! doesn't notify the changes unless it's subscribed to, so the events are
+//! obtained by checking its state once in a while. A video is only
+//! considered loaded once the renderer has its URL, since the previous one
+//! may still be playing right after it's sent.
+//!
+//! The services are documented in
+//! <https://openconnectivity.org/developer/specifications/upnp-resources/upnp>
+
+pub mod proxy;
+pub mod ssdp;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::player::{check_speed, Event, PlayerBase, VideoSource, MAX_VOLUME};
+use proxy::Proxy;
+
+use std::cell::Cell;
+use std::net::{IpAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::Url;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the renderers to reply to the discovery.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often the renderer is asked for its state in `poll_event`.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the renderer has to start playing a video before it's
+/// considered broken.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(20);
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:";
+const RENDERING_CONTROL: &str =
+    "urn:schemas-upnp-org:service:RenderingControl:";
+
+/// A service in the description of the renderer.
+#[derive(Clone, Debug, PartialEq)]
+struct Service {
+    /// Its full type, with the version.
+    kind: String,
+    control_url: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// The video with the URL was sent at the given time, but it isn't
+    /// playing yet.
+    Opening(String, Instant),
+    Open,
+}
+
+pub struct Dlna {
+    client: Client,
+    av_transport: Service,
+    rendering_control: Option<Service>,
+    proxy: Proxy,
+    state: State,
+    /// Applied once the video is playing, since most renderers can't seek
+    /// before that.
+    start: Option<Duration>,
+    last_poll: Option<Instant>,
+    buffering: bool,
+    paused: bool,
+    volume: u32,
+    muted: bool,
+    /// The last position, used while the renderer is buffering.
+    position: Cell<Duration>,
+}
+
+impl Dlna {
+    /// Uses the renderer with the description in the URL, like
+    /// `http://192.168.1.20:49152/description.xml`.
+    pub fn connect(location: &str) -> Result<Dlna> {
+        let client = Client::builder().timeout(TIMEOUT).build()?;
+        let description = client.get(location).send()?.error_for_status()?;
+        let (av_transport, rendering_control) =
+            services(location, &description.text()?)?;
+
+        let mut dlna = Dlna {
+            client,
+            av_transport,
+            rendering_control,
+            proxy: Proxy::bind(local_ip(location)?)?,
+            state: State::Idle,
+            start: None,
+            last_poll: None,
+            buffering: false,
+            paused: false,
+            volume: MAX_VOLUME,
+            muted: false,
+            position: Cell::new(Duration::default()),
+        };
+        if let Some(service) = &dlna.rendering_control {
+            let args = &[("InstanceID", "0"), ("Channel", "Master")];
+            let volume = dlna.action(service, "GetVolume", args)?;
+            let muted = dlna.action(service, "GetMute", args)?;
+            dlna.volume = value(&volume, "CurrentVolume")?
+                .parse()
+                .unwrap_or(MAX_VOLUME);
+            dlna.muted = value(&muted, "CurrentMute")? == "1";
+        }
+
+        info!("Connected to the DLNA renderer in {}", location);
+        Ok(dlna)
+    }
+
+    /// Uses the first renderer found in the network.
+    pub fn discover() -> Result<Dlna> {
+        for location in ssdp::discover(DISCOVERY_TIMEOUT)? {
+            match Dlna::connect(&location) {
+                Ok(dlna) => return Ok(dlna),
+                Err(e) => {
+                    warn!("Couldn't use the renderer {}: {}", location, e)
+                }
+            }
+        }
+
+        Err(Error::FailedConnection(String::from(
+            "no DLNA renderers were found in the network",
+        )))
+    }
+
+    /// Runs an action of the service, returning the response.
+    fn action(
+        &self,
+        service: &Service,
+        name: &str,
+        args: &[(&str, &str)],
+    ) -> Result<String> {
+        let args = args
+            .iter()
+            .map(|(arg, value)| format!("<{0}>{1}</{0}>", arg, escape(value)))
+            .collect::<String>();
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:{0} xmlns:u=\"{1}\">{2}</u:{0}></s:Body>\
+             </s:Envelope>",
+            name, service.kind, args
+        );
+
+        let res = self
+            .client
+            .post(&service.control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#{}\"", service.kind, name))
+            .body(body)
+            .send()?;
+        let status = res.status();
+        let text = res.text()?;
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        // The errors are described in the fault, if possible.
+        let code = value(&text, "errorCode").unwrap_or_default();
+        let description = value(&text, "errorDescription")
+            .unwrap_or_else(|_| status.to_string());
+        Err(Error::Player(format!(
+            "the renderer failed {} ({}): {}",
+            name, code, description
+        )))
+    }
+
+    fn transport(&self, name: &str, args: &[(&str, &str)]) -> Result<String> {
+        let mut all = vec![("InstanceID", "0")];
+        all.extend_from_slice(args);
+        self.action(&self.av_transport, name, &all)
+    }
+
+    /// Whether the renderer has the URL as its current one. The renderers
+    /// that can't tell are trusted to have it.
+    fn has_uri(&self, url: &str) -> bool {
+        let uri = self
+            .transport("GetMediaInfo", &[])
+            .and_then(|res| value(&res, "CurrentURI"));
+        match uri {
+            Ok(uri) => uri.trim() == url,
+            Err(e) => {
+                warn!("Couldn't get the URL in the renderer: {}", e);
+                true
+            }
+        }
+    }
+
+    fn rendering(&self, name: &str, args: &[(&str, &str)]) -> Result<()> {
+        let service =
+            self.rendering_control.as_ref().ok_or(Error::Unsupported)?;
+        let mut all = vec![("InstanceID", "0"), ("Channel", "Master")];
+        all.extend_from_slice(args);
+        self.action(service, name, &all)?;
+        Ok(())
+    }
+}
+
+/// The AVTransport and the RenderingControl services in the description,
+/// with their absolute URLs.
+fn services(
+    location: &str,
+    description: &str,
+) -> Result<(Service, Option<Service>)> {
+    let invalid = |e: &dyn ToString| {
+        Error::FailedConnection(format!(
+            "invalid renderer description: {}",
+            e.to_string()
+        ))
+    };
+    let doc =
+        roxmltree::Document::parse(description).map_err(|e| invalid(&e))?;
+    let base = doc
+        .descendants()
+        .find(|node| node.has_tag_name("URLBase"))
+        .and_then(|node| node.text())
+        .unwrap_or(location);
+    let base = Url::parse(base.trim()).map_err(|e| invalid(&e))?;
+
+    let mut av_transport = None;
+    let mut rendering_control = None;
+    for node in doc
+        .descendants()
+        .filter(|node| node.has_tag_name("service"))
+    {
+        let child = |name| {
+            node.children()
+                .find(|child| child.has_tag_name(name))
+                .and_then(|child| child.text())
+                .map(str::trim)
+        };
+        let (kind, control) = match (child("serviceType"), child("controlURL"))
+        {
+            (Some(kind), Some(control)) => (kind, control),
+            _ => continue,
+        };
+        let service = Service {
+            kind: kind.to_string(),
+            control_url: base
+                .join(control)
+                .map_err(|e| invalid(&e))?
+                .to_string(),
+        };
+        if kind.starts_with(AV_TRANSPORT) {
+            av_transport = Some(service);
+        } else if kind.starts_with(RENDERING_CONTROL) {
+            rendering_control = Some(service);
+        }
+    }
+
+    match av_transport {
+        Some(av_transport) => Ok((av_transport, rendering_control)),
+        None => Err(invalid(&"it doesn't have an AVTransport service")),
+    }
+}
+
+/// The text of the first element with the name in a response.
+fn value(xml: &str, name: &str) -> Result<String> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| {
+        Error::Player(format!("invalid response from the renderer: {}", e))
+    })?;
+    doc.descendants()
+        .find(|node| node.has_tag_name(name))
+        .map(|node| node.text().unwrap_or_default().to_string())
+        .ok_or_else(|| Error::Player(format!("the response has no {}", name)))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The IP of this device in the network of the renderer, so that it can
+/// reach the proxy. Nothing is sent to find it.
+fn local_ip(location: &str) -> Result<IpAddr> {
+    let url = Url::parse(location)
+        .map_err(|e| Error::FailedConnection(e.to_string()))?;
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((host.trim_matches(|c| c == '[' || c == ']'), port))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// Many renderers need the metadata of the video to play it, in DIDL-Lite.
+fn metadata(url: &str) -> String {
+    let content_type = proxy::content_type(url).unwrap_or("*");
+    format!(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">\
+         <item id=\"0\" parentID=\"-1\" restricted=\"1\">\
+         <dc:title>Vidify</dc:title>\
+         <upnp:class>object.item.videoItem</upnp:class>\
+         <res protocolInfo=\"http-get:*:{}:*\">{}</res>\
+         </item></DIDL-Lite>",
+        content_type,
+        escape(url)
+    )
+}
+
+/// The times are formatted as `H:MM:SS`, rounded to the nearest second,
+/// since most renderers don't support the fractions of a second.
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs_f64().round() as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Parses a time like `1:02:03` or `0:01:03.250`.
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut parts = time.trim().splitn(3, ':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let secs = (hours * 3600 + minutes * 60) as f64 + seconds;
+    Some(Duration::from_secs_f64(secs))
+}
+
+impl PlayerBase for Dlna {
+    fn new(config: &Config) -> Result<Dlna> {
+        match &config.dlna_renderer {
+            Some(location) => Dlna::connect(location),
+            None => Dlna::discover(),
+        }
+    }
+
+    /// The video is started right away so that the renderer shows it, and
+    /// it's paused once it's playing if needed.
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        let url = if Proxy::is_needed(source) {
+            self.proxy.serve(source)
+        } else {
+            source.url.clone()
+        };
+
+        info!("Sending {} to the DLNA renderer", url);
+        self.transport(
+            "SetAVTransportURI",
+            &[
+                ("CurrentURI", &url),
+                ("CurrentURIMetaData", &metadata(&url)),
+            ],
+        )?;
+        self.transport("Play", &[("Speed", "1")])?;
+        self.state = State::Opening(url, Instant::now());
+        self.start = source.start;
+        self.last_poll = None;
+        self.position.set(source.start.unwrap_or_default());
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        if self.state == State::Open {
+            self.transport("Play", &[("Speed", "1")])?;
+        }
+        self.paused = false;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        if self.state == State::Open {
+            self.transport("Pause", &[])?;
+        }
+        self.paused = true;
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn position(&self) -> Duration {
+        match self.state {
+            State::Open => {}
+            State::Opening(..) => return self.position.get(),
+            State::Idle => return Duration::default(),
+        }
+
+        let time = self
+            .transport("GetPositionInfo", &[])
+            .and_then(|res| value(&res, "RelTime"));
+        match time.map(|time| parse_time(&time)) {
+            Ok(Some(position)) => self.position.set(position),
+            // The renderer may not know it yet, like while it's buffering.
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Couldn't get the position in the renderer: {}", e)
+            }
+        }
+
+        self.position.get()
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        match self.state {
+            State::Open => {
+                self.transport(
+                    "Seek",
+                    &[
+                        ("Unit", "REL_TIME"),
+                        ("Target", &format_time(position)),
+                    ],
+                )?;
+                self.position.set(position);
+            }
+            State::Opening(..) => {
+                self.start = Some(position);
+                self.position.set(position);
+            }
+            State::Idle => {}
+        }
+        Ok(())
+    }
+
+    fn volume(&self) -> u32 {
+        self.volume
+    }
+
+    /// The volume is only supported if the renderer has a RenderingControl
+    /// service.
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        let volume = volume.min(MAX_VOLUME);
+        self.rendering(
+            "SetVolume",
+            &[("DesiredVolume", &volume.to_string())],
+        )?;
+        self.volume = volume;
+        Ok(())
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        let desired = if muted { "1" } else { "0" };
+        self.rendering("SetMute", &[("DesiredMute", desired)])?;
+        self.muted = muted;
+        Ok(())
+    }
+
+    fn speed(&self) -> f64 {
+        1.0
+    }
+
+    /// Most renderers can only play the videos at the normal speed.
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        check_speed(speed)?;
+        if (speed - 1.0).abs() > f64::EPSILON {
+            return Err(Error::Unsupported);
+        }
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        if self.state == State::Idle {
+            return None;
+        }
+        if let Some(last) = self.last_poll {
+            if last.elapsed() < POLL_INTERVAL {
+                return None;
+            }
+        }
+        self.last_poll = Some(Instant::now());
+
+        let info = self.transport("GetTransportInfo", &[]).and_then(|res| {
+            Ok((
+                value(&res, "CurrentTransportState")?,
+                value(&res, "CurrentTransportStatus")?,
+            ))
+        });
+        let (state, status) = match info {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Couldn't get the state of the renderer: {}", e);
+                return None;
+            }
+        };
+
+        if status == "ERROR_OCCURRED" {
+            self.state = State::Idle;
+            return Some(Event::Error(String::from(
+                "the renderer couldn't play the video",
+            )));
+        }
+        // The previous video may still be playing while it's opening.
+        let playing = match &self.state {
+            State::Opening(url, _) => {
+                matches!(state.as_str(), "PLAYING" | "PAUSED_PLAYBACK")
+                    && self.has_uri(url)
+            }
+            _ => false,
+        };
+        match (&self.state, state.as_str()) {
+            (State::Opening(..), _) if playing => {
+                self.state = State::Open;
+                if let Some(start) = self.start.take() {
+                    if let Err(e) = self.seek(start) {
+                        warn!("Couldn't seek in the renderer: {}", e);
+                    }
+                }
+                if self.paused {
+                    if let Err(e) = self.transport("Pause", &[]) {
+                        warn!("Couldn't pause the renderer: {}", e);
+                    }
+                }
+                Some(Event::Loaded)
+            }
+            (State::Opening(_, sent), _) if sent.elapsed() > OPEN_TIMEOUT => {
+                self.state = State::Idle;
+                Some(Event::Error(String::from(
+                    "the renderer didn't start playing the video",
+                )))
+            }
+            (State::Open, "STOPPED") | (State::Open, "NO_MEDIA_PRESENT") => {
+                self.state = State::Idle;
+                Some(Event::Ended)
+            }
+            (State::Open, "TRANSITIONING") if !self.buffering => {
+                self.buffering = true;
+                Some(Event::Buffering(true))
+            }
+            (State::Open, _) if self.buffering && state != "TRANSITIONING" => {
+                self.buffering = false;
+                Some(Event::Buffering(false))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{check_player, MockServer, Request};
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <specVersion><major>1</major><minor>0</minor></specVersion>
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType>
+    <friendlyName>Living room TV</friendlyName>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:RenderingControl:1</serviceType>
+        <serviceId>urn:upnp-org:serviceId:RenderingControl</serviceId>
+        <controlURL>/RenderingControl/control</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:AVTransport:1</serviceType>
+        <serviceId>urn:upnp-org:serviceId:AVTransport</serviceId>
+        <controlURL>AVTransport/control</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+    const FAULT: &str = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+  <s:Body>
+    <s:Fault>
+      <faultcode>s:Client</faultcode>
+      <faultstring>UPnPError</faultstring>
+      <detail>
+        <UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+          <errorCode>711</errorCode>
+          <errorDescription>Illegal seek target</errorDescription>
+        </UPnPError>
+      </detail>
+    </s:Fault>
+  </s:Body>
+</s:Envelope>"#;
+
+    /// What the fake renderer plays. A video replaces the previous one
+    /// after the second check of the state, like if it took a while to
+    /// open.
+    struct Renderer {
+        state: &'static str,
+        uri: String,
+        next: Option<String>,
+        checks: usize,
+        position: String,
+    }
+
+    impl Renderer {
+        fn new(state: &'static str) -> Arc<Mutex<Self>> {
+            Arc::new(Mutex::new(Renderer {
+                state,
+                uri: String::new(),
+                next: None,
+                checks: 0,
+                position: String::from("0:00:00"),
+            }))
+        }
+    }
+
+    /// Replies to the actions like a renderer. Its state can be changed
+    /// from the tests.
+    fn fake_renderer(renderer: Arc<Mutex<Renderer>>) -> MockServer {
+        MockServer::new(move |req: &Request| {
+            if req.method == "GET" {
+                return (200, "text/xml", DESCRIPTION.to_string());
+            }
+
+            let action = req.header("SOAPAction").unwrap().trim_matches('"');
+            let name = action.split('#').nth(1).unwrap();
+            let mut renderer = renderer.lock().unwrap();
+            let args = match name {
+                "GetVolume" => "<CurrentVolume>35</CurrentVolume>".to_string(),
+                "GetMute" => "<CurrentMute>0</CurrentMute>".to_string(),
+                "SetAVTransportURI" => {
+                    renderer.next = value(&req.body, "CurrentURI").ok();
+                    renderer.checks = 0;
+                    String::new()
+                }
+                "Play" => {
+                    renderer.state = "PLAYING";
+                    String::new()
+                }
+                "Pause" => {
+                    renderer.state = "PAUSED_PLAYBACK";
+                    String::new()
+                }
+                "GetTransportInfo" => {
+                    renderer.checks += 1;
+                    if renderer.checks > 1 {
+                        if let Some(next) = renderer.next.take() {
+                            renderer.uri = next;
+                            renderer.position = String::from("0:00:00");
+                        }
+                    }
+                    format!(
+                        "<CurrentTransportState>{}</CurrentTransportState>\
+                         <CurrentTransportStatus>OK</CurrentTransportStatus>\
+                         <CurrentSpeed>1</CurrentSpeed>",
+                        renderer.state
+                    )
+                }
+                "GetMediaInfo" => format!(
+                    "<NrTracks>1</NrTracks><CurrentURI>{}</CurrentURI>",
+                    escape(&renderer.uri)
+                ),
+                "GetPositionInfo" => format!(
+                    "<Track>1</Track><RelTime>{}</RelTime>",
+                    renderer.position
+                ),
+                "Seek" => {
+                    let target = value(&req.body, "Target").unwrap();
+                    if target == "9:00:00" {
+                        return (500, "text/xml", FAULT.to_string());
+                    }
+                    renderer.position = target;
+                    String::new()
+                }
+                _ => String::new(),
+            };
+            let res = format!(
+                "<?xml version=\"1.0\"?>\
+                 <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\">\
+                 <s:Body><u:{0}Response xmlns:u=\"{1}\">{2}</u:{0}Response>\
+                 </s:Body></s:Envelope>",
+                name,
+                action.split('#').next().unwrap(),
+                args
+            );
+            (200, "text/xml", res)
+        })
+    }
+
+    /// The actions sent to the renderer, with their arguments.
+    fn actions(server: &MockServer) -> Vec<(String, Vec<(String, String)>)> {
+        server
+            .requests()
+            .iter()
+            .filter(|req| req.method == "POST")
+            .map(|req| {
+                let doc = roxmltree::Document::parse(&req.body).unwrap();
+                let action = doc
+                    .descendants()
+                    .find(|node| node.has_tag_name("Body"))
+                    .and_then(|body| body.first_element_child())
+                    .unwrap();
+                let args = action
+                    .children()
+                    .filter(|node| node.is_element())
+                    .map(|arg| {
+                        let text = arg.text().unwrap_or_default();
+                        (arg.tag_name().name().to_string(), text.to_string())
+                    })
+                    .collect();
+                (action.tag_name().name().to_string(), args)
+            })
+            .filter(|(name, _)| !name.starts_with("Get"))
+            .collect()
+    }
+
+    fn args(args: &[(&str, &str)]) -> Vec<(String, String)> {
+        args.iter()
+            .map(|(arg, value)| (arg.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn wait_event(dlna: &mut Dlna) -> Event {
+        let start = Instant::now();
+        loop {
+            if let Some(event) = dlna.poll_event() {
+                return event;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn description() {
+        let location = "http://192.168.1.20:49152/dmr/description.xml";
+        let (av_transport, rendering_control) =
+            services(location, DESCRIPTION).unwrap();
+        assert_eq!(
+            av_transport,
+            Service {
+                kind: String::from(
+                    "urn:schemas-upnp-org:service:AVTransport:1"
+                ),
+                control_url: String::from(
+                    "http://192.168.1.20:49152/dmr/AVTransport/control"
+                ),
+            }
+        );
+        assert_eq!(
+            rendering_control.unwrap().control_url,
+            "http://192.168.1.20:49152/RenderingControl/control"
+        );
+
+        let with_base = DESCRIPTION.replace(
+            "<device>",
+            "<URLBase>http://192.168.1.21:8080/</URLBase><device>",
+        );
+        let (av_transport, _) = services(location, &with_base).unwrap();
+        assert_eq!(
+            av_transport.control_url,
+            "http://192.168.1.21:8080/AVTransport/control"
+        );
+
+        let without =
+            DESCRIPTION.replace("AVTransport:1", "ConnectionManager:1");
+        assert!(services(location, &without).is_err());
+    }
+
+    #[test]
+    fn playback() {
+        let renderer = Renderer::new("NO_MEDIA_PRESENT");
+        let server = fake_renderer(Arc::clone(&renderer));
+        let mut dlna =
+            Dlna::connect(&format!("{}/description.xml", server.url)).unwrap();
+        assert_eq!(dlna.volume(), 35);
+        assert!(!dlna.is_muted());
+
+        dlna.pause().unwrap();
+        let url = "https://videos/song.mp4?id=1&itag=22";
+        let source = VideoSource::new(url).with_start(Duration::from_secs(62));
+        dlna.load(&source).unwrap();
+        assert_eq!(dlna.position(), Duration::from_secs(62));
+        assert_eq!(wait_event(&mut dlna), Event::Loaded);
+        assert!(dlna.is_paused());
+        assert_eq!(dlna.position(), Duration::from_secs(62));
+        dlna.seek(Duration::from_secs(3723)).unwrap();
+        dlna.play().unwrap();
+        dlna.set_volume(MAX_VOLUME + 50).unwrap();
+        dlna.set_muted(true).unwrap();
+        assert!(matches!(dlna.set_speed(1.5), Err(Error::Unsupported)));
+
+        assert_eq!(
+            actions(&server),
+            [
+                (
+                    String::from("SetAVTransportURI"),
+                    args(&[
+                        ("InstanceID", "0"),
+                        ("CurrentURI", url),
+                        ("CurrentURIMetaData", &metadata(url)),
+                    ])
+                ),
+                (
+                    String::from("Play"),
+                    args(&[("InstanceID", "0"), ("Speed", "1")])
+                ),
+                (
+                    String::from("Seek"),
+                    args(&[
+                        ("InstanceID", "0"),
+                        ("Unit", "REL_TIME"),
+                        ("Target", "0:01:02"),
+                    ])
+                ),
+                (String::from("Pause"), args(&[("InstanceID", "0")])),
+                (
+                    String::from("Seek"),
+                    args(&[
+                        ("InstanceID", "0"),
+                        ("Unit", "REL_TIME"),
+                        ("Target", "1:02:03"),
+                    ])
+                ),
+                (
+                    String::from("Play"),
+                    args(&[("InstanceID", "0"), ("Speed", "1")])
+                ),
+                (
+                    String::from("SetVolume"),
+                    args(&[
+                        ("InstanceID", "0"),
+                        ("Channel", "Master"),
+                        ("DesiredVolume", "100"),
+                    ])
+                ),
+                (
+                    String::from("SetMute"),
+                    args(&[
+                        ("InstanceID", "0"),
+                        ("Channel", "Master"),
+                        ("DesiredMute", "1"),
+                    ])
+                ),
+            ]
+        );
+
+        renderer.lock().unwrap().state = "STOPPED";
+        assert_eq!(wait_event(&mut dlna), Event::Ended);
+        assert_eq!(dlna.position(), Duration::default());
+    }
+
+    #[test]
+    fn proxied() {
+        let server = fake_renderer(Renderer::new("NO_MEDIA_PRESENT"));
+        let mut dlna =
+            Dlna::connect(&format!("{}/description.xml", server.url)).unwrap();
+
+        let source = VideoSource::new("https://videos/song.webm")
+            .with_header("User-Agent", "vidify");
+        dlna.load(&source).unwrap();
+        let (_, args) = &actions(&server)[0];
+        assert!(args[1].1.starts_with("http://127.0.0.1:"));
+        assert!(args[1].1.contains("/video/"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_time("0:01:03.250"),
+            Some(Duration::from_millis(63_250))
+        );
+        assert_eq!(parse_time("NOT_IMPLEMENTED"), None);
+        assert_eq!(format_time(Duration::from_millis(3_723_400)), "1:02:03");
+        assert_eq!(format_time(Duration::from_millis(3_723_900)), "1:02:04");
+
+        let server = fake_renderer(Renderer::new("NO_MEDIA_PRESENT"));
+        let mut dlna =
+            Dlna::connect(&format!("{}/description.xml", server.url)).unwrap();
+        dlna.load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+        assert_eq!(wait_event(&mut dlna), Event::Loaded);
+        let error = dlna.seek(Duration::from_secs(9 * 3600));
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "Player error: the renderer failed Seek (711): Illegal seek target"
+        );
+    }
+
+    #[test]
+    fn semantics() {
+        let server = fake_renderer(Renderer::new("NO_MEDIA_PRESENT"));
+        let mut dlna =
+            Dlna::connect(&format!("{}/description.xml", server.url)).unwrap();
+        let source = VideoSource::new("https://videos/song.mp4")
+            .with_start(Duration::from_secs(1));
+        check_player(&mut dlna, &source);
+    }
+
+    #[test]
+    fn replaced() {
+        let renderer = Renderer::new("PLAYING");
+        renderer.lock().unwrap().uri = String::from("https://videos/old.mp4");
+        let server = fake_renderer(renderer);
+        let mut dlna =
+            Dlna::connect(&format!("{}/description.xml", server.url)).unwrap();
+        dlna.load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+
+        // The previous video is still playing on the first check.
+        assert_eq!(dlna.poll_event(), None);
+        assert_eq!(wait_event(&mut dlna), Event::Loaded);
+        let media = server
+            .requests()
+            .iter()
+            .filter(|req| req.body.contains("GetMediaInfo"))
+            .count();
+        assert_eq!(media, 2);
+    }
+}
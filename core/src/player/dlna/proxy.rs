@@ -0,0 +1,438 @@
+//! Serves the videos that the renderers can't get by themselves: the local
+//! files, and the streams that need HTTP headers, since only the URL is
+//! sent to the renderer. The ranges are passed through, so that the
+//! renderer can still seek.
+
+use crate::error::{Error, Result};
+use crate::player::VideoSource;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::Url;
+
+/// The response headers of the streams passed to the renderer.
+const HEADERS: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-range",
+    "accept-ranges",
+];
+
+pub struct Proxy {
+    address: SocketAddr,
+    closed: Arc<AtomicBool>,
+    /// The source being served, with a different ID each time so that the
+    /// renderers don't confuse it with the previous one.
+    source: Arc<Mutex<Option<(u64, VideoSource)>>>,
+    next_id: u64,
+}
+
+impl Proxy {
+    /// Listens in the IP, which must be reachable by the renderer.
+    pub fn bind(ip: IpAddr) -> Result<Proxy> {
+        let listener = TcpListener::bind(SocketAddr::new(ip, 0))?;
+        let address = listener.local_addr()?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let source = Arc::new(Mutex::new(None));
+        // The default timeout is too short for a full video.
+        let client = Client::builder().timeout(None).build()?;
+
+        let stop = Arc::clone(&closed);
+        let served = Arc::clone(&source);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                let source = served.lock().unwrap().clone();
+                let client = client.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle(stream, source, &client) {
+                        warn!("Couldn't proxy the video: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Proxy {
+            address,
+            closed,
+            source,
+            next_id: 1,
+        })
+    }
+
+    /// Whether the renderer can't play the source from its URL.
+    pub fn is_needed(source: &VideoSource) -> bool {
+        let url = match Url::parse(&source.url) {
+            Ok(url) => url,
+            Err(_) => return true,
+        };
+        let local = match url.host_str() {
+            Some(host) => {
+                host == "localhost"
+                    || host
+                        .trim_matches(|c| c == '[' || c == ']')
+                        .parse::<IpAddr>()
+                        .is_ok_and(|ip| ip.is_loopback())
+            }
+            None => true,
+        };
+
+        let remote = matches!(url.scheme(), "http" | "https");
+        !source.headers.is_empty() || !remote || local
+    }
+
+    /// Starts serving the source instead of the previous one, returning
+    /// its URL.
+    pub fn serve(&mut self, source: &VideoSource) -> String {
+        let id = self.next_id;
+        self.next_id += 1;
+        *self.source.lock().unwrap() = Some((id, source.clone()));
+        let url = format!("http://{}/video/{}", self.address, id);
+        info!("Serving {} in {}", source.url, url);
+        url
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+/// The MIME type of a video by its extension, if known.
+pub fn content_type(path: &str) -> Option<&'static str> {
+    let path = path.split(&['?', '#'][..]).next()?;
+    let extension = path.rsplit('.').next()?.to_lowercase();
+    match extension.as_str() {
+        "mp4" | "m4v" => Some("video/mp4"),
+        "webm" => Some("video/webm"),
+        "mkv" => Some("video/x-matroska"),
+        "avi" => Some("video/x-msvideo"),
+        "ts" => Some("video/mp2t"),
+        _ => None,
+    }
+}
+
+fn handle(
+    mut stream: TcpStream,
+    source: Option<(u64, VideoSource)>,
+    client: &Client,
+) -> Result<()> {
+    let (method, path, headers) = read_request(&stream)?;
+    let source = match source {
+        Some((id, source)) if path == format!("/video/{}", id) => source,
+        _ => return respond_error(&mut stream, "404 Not Found"),
+    };
+    let head = match method.as_str() {
+        "GET" => false,
+        "HEAD" => true,
+        _ => return respond_error(&mut stream, "405 Method Not Allowed"),
+    };
+    let range = headers.get("range").map(String::as_str);
+
+    if source.url.starts_with("file://") {
+        serve_file(&mut stream, &source.url, range, head)
+    } else {
+        serve_stream(&mut stream, &source, range, head, client)
+    }
+}
+
+/// The method, the path and the headers with their names in lowercase.
+fn read_request(
+    stream: &TcpStream,
+) -> Result<(String, String, HashMap<String, String>)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(pos) = line.find(':') {
+            headers.insert(
+                line[..pos].trim().to_lowercase(),
+                line[pos + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    Ok((method, path, headers))
+}
+
+fn respond_error(stream: &mut TcpStream, status: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )?;
+    Ok(())
+}
+
+fn serve_stream(
+    stream: &mut TcpStream,
+    source: &VideoSource,
+    range: Option<&str>,
+    head: bool,
+    client: &Client,
+) -> Result<()> {
+    let mut req = if head {
+        client.head(&source.url)
+    } else {
+        client.get(&source.url)
+    };
+    for (name, value) in &source.headers {
+        req = req.header(name.as_str(), value.as_str());
+    }
+    if let Some(range) = range {
+        req = req.header("Range", range);
+    }
+    let mut res = match req.send() {
+        Ok(res) => res,
+        Err(e) => {
+            respond_error(stream, "502 Bad Gateway")?;
+            return Err(e.into());
+        }
+    };
+
+    let status = res.status();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )?;
+    for name in HEADERS {
+        if let Some(value) = res.headers().get(*name) {
+            if let Ok(value) = value.to_str() {
+                write!(stream, "{}: {}\r\n", name, value)?;
+            }
+        }
+    }
+    write!(stream, "Connection: close\r\n\r\n")?;
+
+    if !head {
+        io::copy(&mut res, stream)?;
+    }
+    Ok(())
+}
+
+fn serve_file(
+    stream: &mut TcpStream,
+    url: &str,
+    range: Option<&str>,
+    head: bool,
+) -> Result<()> {
+    let path = Url::parse(url)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .ok_or_else(|| Error::Player(format!("invalid file URL {}", url)))?;
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            respond_error(stream, "404 Not Found")?;
+            return Err(e.into());
+        }
+    };
+    let size = file.metadata()?.len();
+
+    let (status, start, end) =
+        match range.and_then(|range| parse_range(range, size)) {
+            Some((start, end)) => ("206 Partial Content", start, end),
+            None if range.is_some() => {
+                write!(
+                    stream,
+                    "HTTP/1.1 416 Range Not Satisfiable\r\n\
+                 Content-Range: bytes */{}\r\n\
+                 Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    size
+                )?;
+                return Ok(());
+            }
+            None => ("200 OK", 0, size.saturating_sub(1)),
+        };
+    let len = if size == 0 { 0 } else { end - start + 1 };
+    let content_type = content_type(url).unwrap_or("application/octet-stream");
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Accept-Ranges: bytes\r\n",
+        status, content_type, len
+    )?;
+    if range.is_some() {
+        write!(
+            stream,
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start, end, size
+        )?;
+    }
+    write!(stream, "Connection: close\r\n\r\n")?;
+
+    if !head {
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut file.take(len), stream)?;
+    }
+    Ok(())
+}
+
+/// The first and last byte in a range like `bytes=100-199`, `bytes=100-`
+/// or `bytes=-100`. Only a single range is supported.
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let range = range.trim().strip_prefix("bytes=")?;
+    let pos = range.find('-')?;
+    let (start, end) = (range[..pos].trim(), range[pos + 1..].trim());
+    let last = size.checked_sub(1)?;
+
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) => (start, end.min(last)),
+        (Ok(start), Err(_)) if end.is_empty() => (start, last),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (size.saturating_sub(suffix), last)
+        }
+        _ => return None,
+    };
+
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{temp_dir, MockServer, Request};
+
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn localhost() -> IpAddr {
+        Ipv4Addr::LOCALHOST.into()
+    }
+
+    #[test]
+    fn needed() {
+        let remote = VideoSource::new("https://videos/song.mp4");
+        assert!(!Proxy::is_needed(&remote));
+        assert!(Proxy::is_needed(&remote.with_header("Referer", "vidify")));
+        for url in &[
+            "file:///videos/song.mkv",
+            "http://localhost:8000/song.mp4",
+            "http://127.0.0.1/song.mp4",
+            "http://[::1]/song.mp4",
+            "/videos/song.mkv",
+        ] {
+            assert!(Proxy::is_needed(&VideoSource::new(url)), "{}", url);
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let origin =
+            MockServer::new(|req: &Request| match req.header("Referer") {
+                Some("vidify") => (206, "video/webm", String::from("video")),
+                _ => (403, "text/plain", String::new()),
+            });
+
+        let mut proxy = Proxy::bind(localhost()).unwrap();
+        let source = VideoSource::new(&format!("{}/song.webm", origin.url))
+            .with_header("Referer", "vidify");
+        let url = proxy.serve(&source);
+        let res = Client::new()
+            .get(&url)
+            .header("Range", "bytes=0-4")
+            .send()
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 206);
+        assert_eq!(res.headers()["content-type"], "video/webm");
+        assert_eq!(res.text().unwrap(), "video");
+        assert_eq!(origin.requests()[0].header("Range"), Some("bytes=0-4"));
+
+        // Only the last source is served.
+        let previous = url;
+        proxy.serve(&VideoSource::new(&format!("{}/other", origin.url)));
+        let res = Client::new().get(&previous).send().unwrap();
+        assert_eq!(res.status().as_u16(), 404);
+    }
+
+    /// An origin that stops sending the video for longer than the default
+    /// timeout of the HTTP client, 30 seconds.
+    #[test]
+    fn slow_origin() {
+        let listener = TcpListener::bind((localhost(), 0)).unwrap();
+        let origin = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&stream).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\n\
+                 Content-Length: 10\r\nConnection: close\r\n\r\n01234"
+            )
+            .unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_secs(31));
+            write!(stream, "56789").unwrap();
+        });
+
+        let mut proxy = Proxy::bind(localhost()).unwrap();
+        let url = proxy
+            .serve(&VideoSource::new(&format!("http://{}/song.mp4", origin)));
+        let renderer = Client::builder().timeout(None).build().unwrap();
+        let res = renderer.get(&url).send().unwrap();
+        assert_eq!(res.text().unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn files() {
+        let dir = temp_dir("dlna-proxy");
+        let path = dir.join("song.mp4");
+        fs::write(&path, "0123456789").unwrap();
+        let file = Url::from_file_path(&path).unwrap().to_string();
+
+        let mut proxy = Proxy::bind(localhost()).unwrap();
+        let url = proxy.serve(&VideoSource::new(&file));
+        let get = |range: Option<&str>| {
+            let mut req = Client::new().get(&url);
+            if let Some(range) = range {
+                req = req.header("Range", range);
+            }
+            let res = req.send().unwrap();
+            (res.status().as_u16(), res.text().unwrap())
+        };
+
+        assert_eq!(get(None), (200, String::from("0123456789")));
+        assert_eq!(get(Some("bytes=2-4")), (206, String::from("234")));
+        assert_eq!(get(Some("bytes=7-")), (206, String::from("789")));
+        assert_eq!(get(Some("bytes=-2")), (206, String::from("89")));
+        assert_eq!(get(Some("bytes=20-")).0, 416);
+        let res = Client::new().head(&url).send().unwrap();
+        assert_eq!(res.headers()["content-type"], "video/mp4");
+        assert_eq!(res.headers()["content-length"], "10");
+    }
+}
@@ -0,0 +1,132 @@
+//! Finds the renderers in the network with SSDP: a search is sent to its
+//! multicast address, and the devices reply with the location of their
+//! description.
+
+use crate::error::Result;
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+/// Where the searches are sent.
+pub const MULTICAST: &str = "239.255.255.250:1900";
+const RENDERER: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+
+/// The locations of the renderers that replied before the timeout.
+pub fn discover(timeout: Duration) -> Result<Vec<String>> {
+    search(MULTICAST.parse().unwrap(), timeout)
+}
+
+/// Sends the search to the address, which is only different from the
+/// multicast one in the tests.
+pub fn search(target: SocketAddr, timeout: Duration) -> Result<Vec<String>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    // The devices wait up to this many seconds before replying.
+    let wait = timeout.as_secs().max(1);
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: {}\r\n\
+         ST: {}\r\n\r\n",
+        MULTICAST, wait, RENDERER
+    );
+    socket.send_to(request.as_bytes(), target)?;
+
+    let end = Instant::now() + timeout;
+    let mut locations = Vec::new();
+    let mut buf = [0; 2048];
+    loop {
+        let left = end.saturating_duration_since(Instant::now());
+        if left == Duration::default() {
+            break;
+        }
+        socket.set_read_timeout(Some(left))?;
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(location) = location(&buf[..len]) {
+            if !locations.contains(&location) {
+                info!("Found a DLNA renderer in {}", location);
+                locations.push(location);
+            }
+        }
+    }
+
+    Ok(locations)
+}
+
+/// The location in a successful reply to the search.
+fn location(reply: &[u8]) -> Option<String> {
+    let reply = String::from_utf8_lossy(reply);
+    let mut lines = reply.lines();
+    if !lines.next()?.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+
+    lines.find_map(|line| {
+        let pos = line.find(':')?;
+        if line[..pos].trim().eq_ignore_ascii_case("location") {
+            Some(line[pos + 1..].trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn discovery() {
+        let renderer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = renderer.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 2048];
+            let (len, sender) = renderer.recv_from(&mut buf).unwrap();
+            let search = String::from_utf8_lossy(&buf[..len]).into_owned();
+            assert!(search.starts_with("M-SEARCH * HTTP/1.1\r\n"));
+            assert!(search.contains(
+                "ST: urn:schemas-upnp-org:device:MediaRenderer:1\r\n"
+            ));
+
+            let replies = &[
+                "HTTP/1.1 200 OK\r\n\
+                 CACHE-CONTROL: max-age=1800\r\n\
+                 Location: http://192.168.1.20:49152/description.xml\r\n\r\n",
+                // The same renderer may reply more than once.
+                "HTTP/1.1 200 OK\r\n\
+                 LOCATION: http://192.168.1.20:49152/description.xml\r\n\r\n",
+                "HTTP/1.1 500 Internal Server Error\r\n\
+                 LOCATION: http://192.168.1.30/broken.xml\r\n\r\n",
+                "HTTP/1.1 200 OK\r\n\
+                 LOCATION: http://192.168.1.21:8080/dmr.xml\r\n\r\n",
+            ];
+            for reply in replies {
+                renderer.send_to(reply.as_bytes(), sender).unwrap();
+            }
+        });
+
+        let locations = search(address, Duration::from_millis(500)).unwrap();
+        assert_eq!(
+            locations,
+            [
+                "http://192.168.1.20:49152/description.xml",
+                "http://192.168.1.21:8080/dmr.xml"
+            ]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! A reference client for the protocol. It's used in the tests, and it's a
+//! starting point for the apps written in Rust.
+
+use crate::error::{Error, Result};
+use crate::player::external::protocol::{
+    Capability, ClientMessage, ServerMessage, VERSION,
+};
+
+use std::net::TcpStream;
+use std::time::Duration;
+
+use tungstenite::{Message, WebSocket};
+
+pub struct ExternalClient {
+    socket: WebSocket<TcpStream>,
+    capabilities: Vec<Capability>,
+}
+
+impl ExternalClient {
+    /// Connects to the server in the address, like `192.168.1.10:8786`.
+    pub fn connect(
+        address: &str,
+        name: &str,
+        capabilities: &[Capability],
+    ) -> Result<ExternalClient> {
+        ExternalClient::handshake(
+            address,
+            &ClientMessage::Hello {
+                version: VERSION,
+                name: name.to_string(),
+                capabilities: Capability::names(capabilities),
+            },
+        )
+    }
+
+    /// Connects with a custom `hello`, returning the reason as an error if
+    /// it's rejected.
+    pub fn handshake(
+        address: &str,
+        hello: &ClientMessage,
+    ) -> Result<ExternalClient> {
+        let stream = TcpStream::connect(address)?;
+        let url = format!("ws://{}/", address);
+        let (socket, _) = tungstenite::client(url.as_str(), stream)
+            .map_err(|e| Error::FailedConnection(e.to_string()))?;
+        let mut client = ExternalClient {
+            socket,
+            capabilities: Vec::new(),
+        };
+
+        client.send(hello)?;
+        match client.recv()? {
+            ServerMessage::Welcome { capabilities, .. } => {
+                client.capabilities = Capability::parse(&capabilities);
+                Ok(client)
+            }
+            ServerMessage::Rejected { reason } => {
+                Err(Error::FailedConnection(reason))
+            }
+            message => Err(Error::FailedConnection(format!(
+                "expected a welcome, got {:?}",
+                message
+            ))),
+        }
+    }
+
+    /// The capabilities accepted by the server.
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// How long `recv` waits before failing, or forever with `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.socket.get_ref().set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Waits for the next message.
+    pub fn recv(&mut self) -> Result<ServerMessage> {
+        loop {
+            match self.socket.read_message()? {
+                Message::Text(text) => {
+                    return Ok(serde_json::from_str(&text)?)
+                }
+                Message::Close(_) => {
+                    return Err(Error::FailedConnection(String::from(
+                        "the server closed the connection",
+                    )))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn send(&mut self, message: &ClientMessage) -> Result<()> {
+        let text = serde_json::to_string(message)?;
+        self.socket.write_message(Message::Text(text))?;
+        Ok(())
+    }
+
+    /// Acknowledges a command that didn't fail.
+    pub fn ack(&mut self, id: u64) -> Result<()> {
+        self.send(&ClientMessage::Ack {
+            id,
+            error: None,
+            position: None,
+        })
+    }
+
+    pub fn close(mut self) -> Result<()> {
+        self.socket.close(None)?;
+        // The connection is closed once the server replies.
+        while self.socket.read_message().is_ok() {}
+        Ok(())
+    }
+}
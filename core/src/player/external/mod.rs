@@ -0,0 +1,1130 @@
+//! The player for the external apps, like a TV or a second screen, which
+//! connect with the protocol in `protocol`. Any number of them can be
+//! connected at once: the commands are sent to all of them, and the
+//! position and the events are taken from the first one. The clients have
+//! the same time to acknowledge each command, so a slow one doesn't delay
+//! the rest.
+
+pub mod client;
+pub mod protocol;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::player::{check_speed, Event, PlayerBase, VideoSource, MAX_VOLUME};
+use crate::video::fallback::Visual;
+use protocol::{Capability, ClientMessage, ServerMessage, VERSION};
+
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use tungstenite::{Message, WebSocket};
+
+/// How long the clients have to acknowledge the commands, after which
+/// they're disconnected.
+const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long the clients have to send their `hello`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the connections check if there are commands to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Ack {
+    id: u64,
+    error: Option<String>,
+    position: Option<f64>,
+}
+
+/// A client after its handshake. Its connection is handled in a separate
+/// thread, which is stopped when this is dropped.
+struct Client {
+    id: usize,
+    name: String,
+    capabilities: Vec<Capability>,
+    commands: mpsc::Sender<ServerMessage>,
+    acks: mpsc::Receiver<Ack>,
+}
+
+impl Client {
+    fn supports(&self, command: &ServerMessage) -> bool {
+        command
+            .capability()
+            .is_none_or(|needed| self.capabilities.contains(&needed))
+    }
+
+    /// Sends a command without waiting for its acknowledgement. Returns
+    /// whether it was sent, since nothing is sent if the client doesn't
+    /// support it.
+    fn send(&self, command: &ServerMessage) -> Result<bool> {
+        if !self.supports(command) {
+            return Ok(false);
+        }
+
+        self.commands
+            .send(command.clone())
+            .map_err(|_| self.disconnected())?;
+        Ok(true)
+    }
+
+    fn disconnected(&self) -> Error {
+        Error::FailedConnection(format!("{} disconnected", self.name))
+    }
+}
+
+/// The `position` command sent to a client, whose acknowledgement is
+/// received without blocking.
+#[derive(Clone, Copy)]
+struct PositionRequest {
+    client: usize,
+    id: u64,
+    sent: Instant,
+}
+
+pub struct External {
+    address: SocketAddr,
+    closed: Arc<AtomicBool>,
+    new_clients: mpsc::Receiver<Client>,
+    clients: Vec<Client>,
+    events: mpsc::Receiver<(usize, Event)>,
+    next_id: Cell<u64>,
+    /// The state sent to the new clients. The position is estimated with
+    /// the time since it was last known.
+    source: Option<VideoSource>,
+    /// The visual shown instead of a video, if any.
+    fallback: Option<Visual>,
+    position: Cell<Duration>,
+    updated: Cell<Instant>,
+    /// The position being asked to the first client, if any.
+    requested: Cell<Option<PositionRequest>>,
+    /// The client that didn't reply to it, which is disconnected the next
+    /// time the clients can be modified.
+    unresponsive: Cell<Option<usize>>,
+    paused: bool,
+    volume: u32,
+    muted: bool,
+    speed: f64,
+}
+
+impl External {
+    /// Starts listening for the clients in the address, like
+    /// `127.0.0.1:8786`, or `0.0.0.0:8786` for the ones in other devices.
+    pub fn bind(address: &str) -> Result<External> {
+        External::listen(address, None)
+    }
+
+    /// Also serves a page in the same address to the HTTP requests that
+    /// aren't for a WebSocket, so that it can be opened in a browser.
+    pub fn with_page(address: &str, page: &'static str) -> Result<External> {
+        External::listen(address, Some(page))
+    }
+
+    fn listen(address: &str, page: Option<&'static str>) -> Result<External> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        info!("Waiting for the external players in {}", address);
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let (client_sender, new_clients) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+        let stop = Arc::clone(&closed);
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().enumerate() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Couldn't accept an external player: {}", e);
+                        continue;
+                    }
+                };
+
+                let clients = client_sender.clone();
+                let events = event_sender.clone();
+                thread::spawn(move || {
+                    let result = match page {
+                        Some(page) if !is_websocket(&stream) => {
+                            serve_page(stream, page)
+                        }
+                        _ => serve(stream, id, &clients, &events),
+                    };
+                    if let Err(e) = result {
+                        warn!("External player disconnected: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(External {
+            address,
+            closed,
+            new_clients,
+            clients: Vec::new(),
+            events,
+            next_id: Cell::new(1),
+            source: None,
+            fallback: None,
+            position: Cell::new(Duration::default()),
+            updated: Cell::new(Instant::now()),
+            requested: Cell::new(None),
+            unresponsive: Cell::new(None),
+            paused: false,
+            volume: MAX_VOLUME,
+            muted: false,
+            speed: 1.0,
+        })
+    }
+
+    /// Where it's listening, with the actual port if it was 0.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn next_id(&self) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    fn estimate(&self) -> Duration {
+        if self.source.is_none() {
+            Duration::default()
+        } else if self.paused {
+            self.position.get()
+        } else {
+            let elapsed = self.updated.get().elapsed().mul_f64(self.speed);
+            self.position.get() + elapsed
+        }
+    }
+
+    /// The reply to the position asked before is outdated after this.
+    fn set_position(&self, position: Duration) {
+        self.position.set(position);
+        self.updated.set(Instant::now());
+        self.requested.set(None);
+    }
+
+    /// Waits for the acknowledgement of a command until the deadline. The
+    /// errors are only for the connection, and the ones in the command are
+    /// in the `Ack`. The other acknowledgements are discarded, except for
+    /// the position asked before.
+    fn wait_ack(
+        &self,
+        client: &Client,
+        id: u64,
+        deadline: Instant,
+    ) -> Result<Ack> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match client.acks.recv_timeout(timeout) {
+                Ok(ack) if ack.id == id => return Ok(ack),
+                Ok(ack) => self.receive_position(client, &ack),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(Error::FailedConnection(format!(
+                        "{} didn't acknowledge the command",
+                        client.name
+                    )))
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(client.disconnected())
+                }
+            }
+        }
+    }
+
+    /// Saves the position if the acknowledgement is for the one asked.
+    fn receive_position(&self, client: &Client, ack: &Ack) {
+        match self.requested.get() {
+            Some(req) if req.client == client.id && req.id == ack.id => {}
+            _ => return,
+        }
+        self.requested.set(None);
+        if let Some(secs) = ack.position {
+            self.set_position(Duration::from_secs_f64(secs.max(0.0)));
+        } else if let Some(e) = &ack.error {
+            warn!("{} couldn't obtain the position: {}", client.name, e);
+        }
+    }
+
+    /// Sends the commands to the clients, and then waits for all their
+    /// acknowledgements until the same deadline. Returns the errors in the
+    /// commands, and the clients that have to be disconnected.
+    fn request_all(
+        &self,
+        clients: &[Client],
+        commands: &[ServerMessage],
+    ) -> (Vec<String>, Vec<usize>) {
+        let deadline = Instant::now() + ACK_TIMEOUT;
+        let mut sent = Vec::new();
+        let mut disconnected = Vec::new();
+        for client in clients {
+            let mut ids = Vec::new();
+            for command in commands {
+                match client.send(command) {
+                    Ok(true) => ids.extend(command.id()),
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!("Disconnecting the external player: {}", e);
+                        disconnected.push(client.id);
+                        break;
+                    }
+                }
+            }
+            sent.push(ids);
+        }
+
+        let mut errors = Vec::new();
+        for (client, ids) in clients.iter().zip(sent) {
+            if disconnected.contains(&client.id) {
+                continue;
+            }
+            for id in ids {
+                match self.wait_ack(client, id, deadline) {
+                    Ok(Ack { error: Some(e), .. }) => {
+                        errors.push(format!("{}: {}", client.name, e))
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Disconnecting the external player: {}", e);
+                        disconnected.push(client.id);
+                        break;
+                    }
+                }
+            }
+        }
+
+        (errors, disconnected)
+    }
+
+    /// Disconnects the client that didn't reply with its position in time.
+    fn drop_unresponsive(&mut self) {
+        if let Some(id) = self.unresponsive.take() {
+            self.clients.retain(|client| client.id != id);
+        }
+    }
+
+    /// The new clients receive the current state before the next commands,
+    /// so that they're in sync with the rest.
+    fn accept_clients(&mut self) {
+        self.drop_unresponsive();
+        let new_clients: Vec<Client> = self.new_clients.try_iter().collect();
+        if new_clients.is_empty() {
+            return;
+        }
+
+        let mut commands = vec![
+            ServerMessage::Volume {
+                id: self.next_id(),
+                volume: self.volume,
+                muted: self.muted,
+            },
+            ServerMessage::Speed {
+                id: self.next_id(),
+                speed: self.speed,
+            },
+        ];
+        if let Some(visual) = &self.fallback {
+            commands.push(fallback_command(self.next_id(), visual));
+        } else if let Some(source) = &self.source {
+            commands.push(load_command(
+                self.next_id(),
+                &source.clone().with_start(self.estimate()),
+            ));
+        }
+        let id = self.next_id();
+        commands.push(if self.paused {
+            ServerMessage::Pause { id }
+        } else {
+            ServerMessage::Play { id }
+        });
+
+        let (errors, failed) = self.request_all(&new_clients, &commands);
+        for e in errors {
+            warn!("Couldn't sync the external player {}", e);
+        }
+        for client in new_clients {
+            if !failed.contains(&client.id) {
+                info!("Connected to the external player {}", client.name);
+                self.clients.push(client);
+            }
+        }
+    }
+
+    /// Sends a command to all the clients, disconnecting the ones that
+    /// don't acknowledge it. The first error in the command is returned.
+    fn broadcast(&mut self, command: ServerMessage) -> Result<()> {
+        self.accept_clients();
+
+        let (errors, disconnected) =
+            self.request_all(&self.clients, &[command]);
+        self.clients
+            .retain(|client| !disconnected.contains(&client.id));
+
+        match errors.into_iter().next() {
+            Some(e) => Err(Error::Player(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for External {
+    /// The thread waiting for the clients is stopped with a last
+    /// connection.
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        let _ = TcpStream::connect(address);
+    }
+}
+
+fn load_command(id: u64, source: &VideoSource) -> ServerMessage {
+    ServerMessage::Load {
+        id,
+        url: source.url.clone(),
+        start: source.start.map(|start| start.as_secs_f64()),
+        headers: source.headers.clone(),
+    }
+}
+
+fn fallback_command(id: u64, visual: &Visual) -> ServerMessage {
+    match visual.clone() {
+        Visual::Artwork { url, artist, title } => ServerMessage::Artwork {
+            id,
+            url,
+            artist,
+            title,
+        },
+        Visual::Loop(url) => ServerMessage::Loop { id, url },
+    }
+}
+
+/// Checks the headers of the request without consuming them, so that the
+/// WebSocket handshake can still read them.
+fn is_websocket(stream: &TcpStream) -> bool {
+    let mut buf = [0; 4096];
+    let start = Instant::now();
+    while start.elapsed() < HANDSHAKE_TIMEOUT {
+        let len = match stream.peek(&mut buf) {
+            Ok(len) => len,
+            Err(_) => return false,
+        };
+        let request = String::from_utf8_lossy(&buf[..len]).to_lowercase();
+        if len == 0 || len == buf.len() || request.contains("\r\n\r\n") {
+            return request.contains("upgrade: websocket");
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    false
+}
+
+/// Replies to a request for the page, which is only in the root.
+fn serve_page(mut stream: TcpStream, page: &str) -> Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut request = String::new();
+    let mut reader = BufReader::new(&stream);
+    reader.read_line(&mut request)?;
+    // The rest of the headers are ignored.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let is_root = path.split('?').next() == Some("/");
+    let (status, content_type, body) =
+        if request.starts_with("GET ") && is_root {
+            ("200 OK", "text/html; charset=utf-8", page)
+        } else {
+            ("404 Not Found", "text/plain", "Not found")
+        };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+/// Handles a connection until it's closed, or until its `Client` is
+/// dropped.
+fn serve(
+    stream: TcpStream,
+    id: usize,
+    clients: &mpsc::Sender<Client>,
+    events: &mpsc::Sender<(usize, Event)>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut socket = tungstenite::accept(stream)
+        .map_err(|e| Error::FailedConnection(e.to_string()))?;
+
+    let (name, capabilities) = match handshake(&mut socket) {
+        Ok(hello) => hello,
+        Err(reason) => {
+            send(
+                &mut socket,
+                &ServerMessage::Rejected {
+                    reason: reason.clone(),
+                },
+            )?;
+            socket.close(None)?;
+            return Err(Error::FailedConnection(reason));
+        }
+    };
+    send(
+        &mut socket,
+        &ServerMessage::Welcome {
+            version: VERSION,
+            capabilities: Capability::names(&capabilities),
+        },
+    )?;
+
+    let (command_sender, commands) = mpsc::channel();
+    let (ack_sender, acks) = mpsc::channel();
+    let client = Client {
+        id,
+        name: name.clone(),
+        capabilities,
+        commands: command_sender,
+        acks,
+    };
+    if clients.send(client).is_err() {
+        return Ok(());
+    }
+
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(command) => send(&mut socket, &command)?,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    socket.close(None)?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let text = match socket.read_message() {
+            Ok(Message::Text(text)) => text,
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(e))
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let event = match serde_json::from_str(&text) {
+            Ok(ClientMessage::Ack {
+                id,
+                error,
+                position,
+            }) => {
+                let _ = ack_sender.send(Ack {
+                    id,
+                    error,
+                    position,
+                });
+                continue;
+            }
+            Ok(ClientMessage::Hello { .. }) => {
+                warn!("{} sent its hello twice", name);
+                continue;
+            }
+            Ok(ClientMessage::Loaded) => Event::Loaded,
+            Ok(ClientMessage::Ended) => Event::Ended,
+            Ok(ClientMessage::Buffering { buffering }) => {
+                Event::Buffering(buffering)
+            }
+            Ok(ClientMessage::Error { message }) => Event::Error(message),
+            Err(e) => {
+                warn!("Invalid message from {}: {}", name, e);
+                continue;
+            }
+        };
+        let _ = events.send((id, event));
+    }
+}
+
+/// Reads the `hello`, returning the reason if the client is rejected.
+fn handshake(
+    socket: &mut WebSocket<TcpStream>,
+) -> std::result::Result<(String, Vec<Capability>), String> {
+    let text = match socket.read_message() {
+        Ok(Message::Text(text)) => text,
+        Ok(_) => return Err(String::from("expected a text message")),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match serde_json::from_str(&text) {
+        Ok(ClientMessage::Hello {
+            version,
+            name,
+            capabilities,
+        }) => {
+            if version != VERSION {
+                return Err(format!(
+                    "unsupported version {}, expected {}",
+                    version, VERSION
+                ));
+            }
+            let capabilities = Capability::parse(&capabilities);
+            if !capabilities.contains(&Capability::Load) {
+                return Err(String::from("the load capability is required"));
+            }
+            Ok((name, capabilities))
+        }
+        Ok(_) => Err(String::from("expected a hello")),
+        Err(e) => Err(format!("invalid hello: {}", e)),
+    }
+}
+
+fn send(
+    socket: &mut WebSocket<TcpStream>,
+    message: &ServerMessage,
+) -> Result<()> {
+    let text = serde_json::to_string(message)?;
+    socket.write_message(Message::Text(text))?;
+    Ok(())
+}
+
+impl PlayerBase for External {
+    fn new(config: &Config) -> Result<Self> {
+        External::bind(&config.external_address)
+    }
+
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        self.source = Some(source.clone());
+        self.fallback = None;
+        self.set_position(source.start.unwrap_or_default());
+        self.broadcast(load_command(self.next_id(), source))
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.set_position(self.estimate());
+        self.paused = false;
+        self.broadcast(ServerMessage::Play { id: self.next_id() })
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.set_position(self.estimate());
+        self.paused = true;
+        self.broadcast(ServerMessage::Pause { id: self.next_id() })
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Asked to the first client that can, or estimated otherwise. It
+    /// doesn't wait for the reply, which is used in the next calls, and
+    /// it's asked again once received. The client is disconnected if it
+    /// doesn't reply in time.
+    fn position(&self) -> Duration {
+        if self.source.is_none() {
+            return Duration::default();
+        }
+
+        let client = self.clients.iter().find(|client| {
+            client.capabilities.contains(&Capability::Position)
+                && Some(client.id) != self.unresponsive.get()
+        });
+        if let Some(client) = client {
+            while let Ok(ack) = client.acks.try_recv() {
+                self.receive_position(client, &ack);
+            }
+
+            match self.requested.get() {
+                Some(req) if req.client == client.id => {
+                    if req.sent.elapsed() > ACK_TIMEOUT {
+                        warn!("{} didn't send its position", client.name);
+                        self.requested.set(None);
+                        self.unresponsive.set(Some(client.id));
+                    }
+                }
+                _ => {
+                    let id = self.next_id();
+                    match client.send(&ServerMessage::Position { id }) {
+                        Ok(_) => self.requested.set(Some(PositionRequest {
+                            client: client.id,
+                            id,
+                            sent: Instant::now(),
+                        })),
+                        Err(_) => self.unresponsive.set(Some(client.id)),
+                    }
+                }
+            }
+        }
+
+        self.estimate()
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        if self.source.is_none() {
+            return Ok(());
+        }
+
+        self.set_position(position);
+        self.broadcast(ServerMessage::Seek {
+            id: self.next_id(),
+            position: position.as_secs_f64(),
+        })
+    }
+
+    fn volume(&self) -> u32 {
+        self.volume
+    }
+
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        self.volume = volume.min(MAX_VOLUME);
+        self.broadcast(ServerMessage::Volume {
+            id: self.next_id(),
+            volume: self.volume,
+            muted: self.muted,
+        })
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.muted = muted;
+        self.broadcast(ServerMessage::Volume {
+            id: self.next_id(),
+            volume: self.volume,
+            muted: self.muted,
+        })
+    }
+
+    fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        check_speed(speed)?;
+        self.set_position(self.estimate());
+        self.speed = speed;
+        self.broadcast(ServerMessage::Speed {
+            id: self.next_id(),
+            speed,
+        })
+    }
+
+    /// Only the events of the first client are returned, since they should
+    /// be the same in the rest.
+    fn poll_event(&mut self) -> Option<Event> {
+        self.accept_clients();
+
+        let first = self.clients.first().map(|client| client.id);
+        while let Ok((id, event)) = self.events.try_recv() {
+            if Some(id) == first {
+                return Some(event);
+            }
+        }
+
+        None
+    }
+
+    /// The clients without the `fallback` capability keep the previous
+    /// video, so it's unsupported when none of them have it.
+    fn show_fallback(&mut self, visual: &Visual) -> Result<()> {
+        self.accept_clients();
+        let supported = self.clients.is_empty()
+            || self.clients.iter().any(|client| {
+                client.capabilities.contains(&Capability::Fallback)
+            });
+        if !supported {
+            return Err(Error::Unsupported);
+        }
+
+        self.source = None;
+        self.fallback = Some(visual.clone());
+        self.set_position(Duration::default());
+        self.broadcast(fallback_command(self.next_id(), visual))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::player::external::client::ExternalClient;
+    use crate::testing::check_player;
+
+    use std::sync::Mutex;
+
+    /// An app that answers the commands like a real one would, playing the
+    /// videos instantly. The commands it receives are saved.
+    fn screen(
+        player: &External,
+        name: &str,
+        capabilities: &[Capability],
+    ) -> Arc<Mutex<Vec<ServerMessage>>> {
+        let address = player.address().to_string();
+        let mut client =
+            ExternalClient::connect(&address, name, capabilities).unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let saved = Arc::clone(&received);
+        thread::spawn(move || {
+            let mut position = 0.0;
+            let mut since: Option<Instant> = None;
+            let mut speed = 1.0;
+            let now = |position: f64, since: Option<Instant>, speed: f64| {
+                position
+                    + since.map_or(0.0, |t| t.elapsed().as_secs_f64() * speed)
+            };
+
+            while let Ok(command) = client.recv() {
+                saved.lock().unwrap().push(command.clone());
+                let id = command.id().unwrap();
+                let mut ack = ClientMessage::Ack {
+                    id,
+                    error: None,
+                    position: None,
+                };
+                let mut event = None;
+                match command {
+                    ServerMessage::Load { url, start, .. } => {
+                        position = start.unwrap_or(0.0);
+                        since = since.map(|_| Instant::now());
+                        event = Some(if url.contains("broken") {
+                            ClientMessage::Error {
+                                message: String::from("Video unavailable"),
+                            }
+                        } else {
+                            ClientMessage::Loaded
+                        });
+                    }
+                    ServerMessage::Play { .. } => {
+                        position = now(position, since, speed);
+                        since = Some(Instant::now());
+                    }
+                    ServerMessage::Pause { .. } => {
+                        position = now(position, since, speed);
+                        since = None;
+                    }
+                    ServerMessage::Seek { position: pos, .. } => {
+                        position = pos;
+                        since = since.map(|_| Instant::now());
+                    }
+                    ServerMessage::Position { .. } => {
+                        ack = ClientMessage::Ack {
+                            id,
+                            error: None,
+                            position: Some(now(position, since, speed)),
+                        };
+                    }
+                    ServerMessage::Speed { speed: new, .. } => {
+                        position = now(position, since, speed);
+                        since = since.map(|_| Instant::now());
+                        speed = new;
+                    }
+                    _ => {}
+                }
+
+                client.send(&ack).unwrap();
+                if let Some(event) = event {
+                    client.send(&event).unwrap();
+                }
+            }
+        });
+
+        received
+    }
+
+    /// An app that acknowledges every command except the ones that need
+    /// the ignored capability, like if it had frozen.
+    fn frozen(
+        player: &External,
+        name: &str,
+        capabilities: &[Capability],
+        ignored: Capability,
+    ) {
+        let address = player.address().to_string();
+        let mut client =
+            ExternalClient::connect(&address, name, capabilities).unwrap();
+        thread::spawn(move || {
+            while let Ok(command) = client.recv() {
+                if command.capability() != Some(ignored) {
+                    let _ = client.ack(command.id().unwrap());
+                }
+            }
+        });
+    }
+
+    /// The clients are only added when the player is used.
+    fn wait_clients(player: &mut External, count: usize) {
+        let start = Instant::now();
+        while player.clients.len() < count {
+            player.poll_event();
+            assert!(start.elapsed() < HANDSHAKE_TIMEOUT);
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn bind() -> External {
+        External::bind("127.0.0.1:0").unwrap()
+    }
+
+    #[test]
+    fn semantics() {
+        let mut player = bind();
+        let tv = screen(
+            &player,
+            "TV",
+            &[
+                Capability::Load,
+                Capability::Seek,
+                Capability::Position,
+                Capability::Volume,
+                Capability::Speed,
+            ],
+        );
+        let phone = screen(&player, "Phone", &[Capability::Load]);
+        wait_clients(&mut player, 2);
+
+        let source = VideoSource::new("https://videos/song.mp4")
+            .with_start(Duration::from_secs(1))
+            .with_header("Referer", "https://example.com");
+        check_player(&mut player, &source);
+
+        for received in &[&tv, &phone] {
+            let received = received.lock().unwrap();
+            let loads = received
+                .iter()
+                .filter(|command| {
+                    matches!(command, ServerMessage::Load { .. })
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                loads,
+                [&load_command(loads[0].id().unwrap(), &source)]
+            );
+        }
+        // The phone only receives what it supports.
+        assert!(phone.lock().unwrap().iter().all(|command| {
+            command.capability() == Some(Capability::Load)
+        }));
+
+        // The events of the second client are ignored.
+        player
+            .load(&VideoSource::new("https://videos/broken.mp4"))
+            .unwrap();
+        let start = Instant::now();
+        let event = loop {
+            if let Some(event) = player.poll_event() {
+                break event;
+            }
+            assert!(start.elapsed() < ACK_TIMEOUT);
+            thread::sleep(POLL_INTERVAL);
+        };
+        assert_eq!(event, Event::Error(String::from("Video unavailable")));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.poll_event(), None);
+    }
+
+    #[test]
+    fn handshake() {
+        let player = bind();
+        let address = player.address().to_string();
+        let hello = |version, capabilities: &[&str]| ClientMessage::Hello {
+            version,
+            name: String::from("TV"),
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        };
+
+        let error = ExternalClient::handshake(&address, &hello(2, &["load"]))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("unsupported version 2"));
+        let error = ExternalClient::handshake(&address, &hello(1, &["seek"]))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("load capability"));
+
+        let client = ExternalClient::handshake(
+            &address,
+            &hello(1, &["load", "subtitles", "volume"]),
+        )
+        .unwrap();
+        assert_eq!(
+            client.capabilities(),
+            [Capability::Load, Capability::Volume]
+        );
+    }
+
+    #[test]
+    fn late_clients() {
+        let mut player = bind();
+        player.set_volume(30).unwrap();
+        player.pause().unwrap();
+        let source = VideoSource::new("https://videos/song.mp4");
+        player.load(&source).unwrap();
+        player.seek(Duration::from_secs(5)).unwrap();
+
+        let received =
+            screen(&player, "TV", &[Capability::Load, Capability::Volume]);
+        wait_clients(&mut player, 1);
+        let received = received.lock().unwrap();
+        let ids = received.iter().map(|c| c.id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            *received,
+            [
+                ServerMessage::Volume {
+                    id: ids[0],
+                    volume: 30,
+                    muted: false,
+                },
+                load_command(
+                    ids[1],
+                    &source.with_start(Duration::from_secs(5))
+                ),
+                ServerMessage::Pause { id: ids[2] },
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback() {
+        let mut player = bind();
+        let tv = screen(&player, "TV", &[Capability::Load]);
+        wait_clients(&mut player, 1);
+        player
+            .load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+        let artwork = Visual::Artwork {
+            url: String::from("https://art/cover.jpg"),
+            artist: Some(String::from("Queen")),
+            title: None,
+        };
+        assert!(matches!(
+            player.show_fallback(&artwork),
+            Err(Error::Unsupported)
+        ));
+
+        let display = screen(
+            &player,
+            "Screen",
+            &[Capability::Load, Capability::Fallback],
+        );
+        wait_clients(&mut player, 2);
+        player.show_fallback(&artwork).unwrap();
+        assert_eq!(player.position(), Duration::default());
+        let expected = |id| ServerMessage::Artwork {
+            id,
+            url: String::from("https://art/cover.jpg"),
+            artist: Some(String::from("Queen")),
+            title: None,
+        };
+        let last = display.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(last, expected(last.id().unwrap()));
+        assert!(!tv.lock().unwrap().iter().any(|command| {
+            matches!(command, ServerMessage::Artwork { .. })
+        }));
+
+        // The new clients receive it instead of the last video.
+        let late =
+            screen(&player, "Late", &[Capability::Load, Capability::Fallback]);
+        wait_clients(&mut player, 3);
+        let late = late.lock().unwrap();
+        assert!(late
+            .iter()
+            .any(|command| *command == expected(command.id().unwrap())));
+        assert!(!late
+            .iter()
+            .any(|command| matches!(command, ServerMessage::Load { .. })));
+    }
+
+    #[test]
+    fn disconnections() {
+        let mut player = bind();
+        let address = player.address().to_string();
+        screen(&player, "TV", &[Capability::Load]);
+        // It only acknowledges the state sent when it connects.
+        let phone = thread::spawn(move || {
+            let mut client = ExternalClient::connect(
+                &address,
+                "Phone",
+                &[Capability::Load],
+            )
+            .unwrap();
+            let command = client.recv().unwrap();
+            client.ack(command.id().unwrap()).unwrap();
+            client
+        });
+        wait_clients(&mut player, 2);
+
+        phone.join().unwrap().close().unwrap();
+        player
+            .load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+        assert_eq!(player.clients.len(), 1);
+        assert_eq!(player.clients[0].name, "TV");
+    }
+
+    #[test]
+    fn unresponsive() {
+        let mut player = bind();
+        let capabilities = &[Capability::Load, Capability::Seek];
+        screen(&player, "TV", capabilities);
+        frozen(&player, "Phone", capabilities, Capability::Seek);
+        frozen(&player, "Tablet", capabilities, Capability::Seek);
+        wait_clients(&mut player, 3);
+        player
+            .load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+
+        // Both are waited for at the same time.
+        let start = Instant::now();
+        player.seek(Duration::from_secs(5)).unwrap();
+        assert!(start.elapsed() < ACK_TIMEOUT * 2);
+        assert_eq!(player.clients.len(), 1);
+        assert_eq!(player.clients[0].name, "TV");
+    }
+
+    #[test]
+    fn position_timeout() {
+        let mut player = bind();
+        frozen(
+            &player,
+            "TV",
+            &[Capability::Load, Capability::Position],
+            Capability::Position,
+        );
+        wait_clients(&mut player, 1);
+        player
+            .load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+
+        // The position is estimated without waiting for the reply.
+        let start = Instant::now();
+        while start.elapsed() <= ACK_TIMEOUT {
+            let before = Instant::now();
+            player.position();
+            assert!(before.elapsed() < POLL_INTERVAL * 10);
+            thread::sleep(POLL_INTERVAL * 10);
+        }
+        player.position();
+        player.poll_event();
+        assert!(player.clients.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! The protocol used by the external apps that play the videos, like a TV
+//! or a second screen. They connect to vidify with a WebSocket, and every
+//! message is a JSON object in a text frame, with its kind in `type`.
+//!
+//! # Handshake
+//!
+//! The client starts with a `hello`, including the version of the protocol
+//! it implements and the capabilities it supports:
+//!
+//! ```json
+//! {"type": "hello", "version": 1, "name": "Living room TV",
+//!  "capabilities": ["load", "seek", "position", "volume"]}
+//! ```
+//!
+//! The server replies with a `welcome` with the capabilities that will be
+//! used, which are the ones it knows of. Otherwise, it replies with a
+//! `rejected` with the reason and it closes the connection, like when the
+//! versions are different or `load` isn't supported:
+//!
+//! ```json
+//! {"type": "welcome", "version": 1, "capabilities": ["load", "seek"]}
+//! {"type": "rejected", "reason": "unsupported version 2, expected 1"}
+//! ```
+//!
+//! The client then receives the current state, in the same way as any
+//! other change.
+//!
+//! # Commands
+//!
+//! The server sends the commands only if the client has their capability.
+//! Each of them has an `id`, and it must be acknowledged with an `ack` with
+//! the same `id`, and an `error` if it failed. The positions are in
+//! seconds.
+//!
+//! | Command | Fields | Capability |
+//! |---------|--------|------------|
+//! | `load` | `url`, `start` (optional), `headers` | `load` |
+//! | `play`, `pause` | | `load` |
+//! | `seek` | `position` | `seek` |
+//! | `position` | | `position` |
+//! | `volume` | `volume` (0 to 100), `muted` | `volume` |
+//! | `speed` | `speed` | `speed` |
+//! | `artwork` | `url`, `artist` and `title` (optional) | `fallback` |
+//! | `loop` | `url` | `fallback` |
+//!
+//! ```json
+//! {"type": "seek", "id": 7, "position": 31.5}
+//! {"type": "ack", "id": 7}
+//! {"type": "position", "id": 8}
+//! {"type": "ack", "id": 8, "position": 31.52}
+//! {"type": "ack", "id": 9, "error": "the video is unavailable"}
+//! ```
+//!
+//! A loaded video keeps the paused state, and the headers are a list of
+//! name and value pairs needed to access it.
+//!
+//! The `fallback` commands replace the video when the track doesn't have
+//! one: `artwork` shows the album artwork over a blurred copy of itself,
+//! with the artist and title, and `loop` plays a video muted and in a
+//! loop. They're acknowledged once they're shown.
+//!
+//! # Events
+//!
+//! The client reports what happens to the video without an `id`: `loaded`
+//! once it can be played, `ended`, `buffering` with a `buffering` boolean,
+//! and `error` with a `message` if the video can't be played.
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+/// The version of the protocol, which must be the same in the clients.
+pub const VERSION: u32 = 1;
+
+/// What a client can do. The names are in lowercase in the messages.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Capability {
+    Load,
+    Seek,
+    Position,
+    Volume,
+    Speed,
+    Fallback,
+}
+
+impl Capability {
+    /// The known capabilities in the list, ignoring the rest.
+    pub fn parse(names: &[String]) -> Vec<Capability> {
+        names.iter().filter_map(|name| name.parse().ok()).collect()
+    }
+
+    pub fn names(capabilities: &[Capability]) -> Vec<String> {
+        capabilities.iter().map(ToString::to_string).collect()
+    }
+}
+
+/// The messages sent by the clients.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+        name: String,
+        capabilities: Vec<String>,
+    },
+    Ack {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        position: Option<f64>,
+    },
+    Loaded,
+    Ended,
+    Buffering {
+        buffering: bool,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// The messages sent by the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerMessage {
+    Welcome {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+    Rejected {
+        reason: String,
+    },
+    Load {
+        id: u64,
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        start: Option<f64>,
+        #[serde(default)]
+        headers: Vec<(String, String)>,
+    },
+    Play {
+        id: u64,
+    },
+    Pause {
+        id: u64,
+    },
+    Seek {
+        id: u64,
+        position: f64,
+    },
+    Position {
+        id: u64,
+    },
+    Volume {
+        id: u64,
+        volume: u32,
+        muted: bool,
+    },
+    Speed {
+        id: u64,
+        speed: f64,
+    },
+    Artwork {
+        id: u64,
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        artist: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    Loop {
+        id: u64,
+        url: String,
+    },
+}
+
+impl ServerMessage {
+    /// The ID of the command, which must be acknowledged.
+    pub fn id(&self) -> Option<u64> {
+        use ServerMessage::*;
+        match self {
+            Welcome { .. } | Rejected { .. } => None,
+            Load { id, .. }
+            | Play { id }
+            | Pause { id }
+            | Seek { id, .. }
+            | Position { id }
+            | Volume { id, .. }
+            | Speed { id, .. }
+            | Artwork { id, .. }
+            | Loop { id, .. } => Some(*id),
+        }
+    }
+
+    /// The capability needed to receive the command.
+    pub fn capability(&self) -> Option<Capability> {
+        use ServerMessage::*;
+        match self {
+            Welcome { .. } | Rejected { .. } => None,
+            Load { .. } | Play { .. } | Pause { .. } => Some(Capability::Load),
+            Seek { .. } => Some(Capability::Seek),
+            Position { .. } => Some(Capability::Position),
+            Volume { .. } => Some(Capability::Volume),
+            Speed { .. } => Some(Capability::Speed),
+            Artwork { .. } | Loop { .. } => Some(Capability::Fallback),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn messages() {
+        let hello: ClientMessage = serde_json::from_value(json!({
+            "type": "hello",
+            "version": 1,
+            "name": "TV",
+            "capabilities": ["load", "subtitles"]
+        }))
+        .unwrap();
+        match hello {
+            ClientMessage::Hello { capabilities, .. } => {
+                assert_eq!(
+                    Capability::parse(&capabilities),
+                    [Capability::Load]
+                )
+            }
+            _ => panic!("unexpected message {:?}", hello),
+        }
+
+        let ack = ClientMessage::Ack {
+            id: 3,
+            error: None,
+            position: Some(1.5),
+        };
+        assert_eq!(
+            serde_json::to_value(&ack).unwrap(),
+            json!({"type": "ack", "id": 3, "position": 1.5})
+        );
+
+        let load = ServerMessage::Load {
+            id: 4,
+            url: String::from("https://videos/song.mp4"),
+            start: None,
+            headers: vec![(String::from("Referer"), String::from("vidify"))],
+        };
+        assert_eq!(
+            serde_json::to_value(&load).unwrap(),
+            json!({
+                "type": "load",
+                "id": 4,
+                "url": "https://videos/song.mp4",
+                "headers": [["Referer", "vidify"]]
+            })
+        );
+        assert_eq!(load.id(), Some(4));
+        assert_eq!(load.capability(), Some(Capability::Load));
+    }
+}
@@ -0,0 +1,633 @@
+//! A player that sends the videos to Kodi, with its JSON-RPC API over HTTP.
+//! Its web server has to be enabled in the settings, under Services >
+//! Control. Kodi doesn't notify the changes over HTTP, so the events are
+//! obtained by checking its active player once in a while. A video is only
+//! considered loaded once the player has its file, since the previous one
+//! may still be playing right after it's sent.
+//!
+//! The API is documented in <https://kodi.wiki/view/JSON-RPC_API>.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::player::{check_speed, Event, PlayerBase, VideoSource, MAX_VOLUME};
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How often Kodi is asked for its state in `poll_event`.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long Kodi has to start playing a video before it's considered
+/// broken.
+const OPEN_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct ActivePlayer {
+    playerid: u64,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// The video with the URL was sent at the given time, but Kodi isn't
+    /// playing it yet.
+    Opening(String, Instant),
+    /// Kodi is playing the video in the player with the ID.
+    Open(u64),
+}
+
+pub struct Kodi {
+    url: String,
+    client: Client,
+    credentials: Option<(String, Option<String>)>,
+    next_id: Cell<u64>,
+    state: State,
+    last_poll: Option<Instant>,
+    paused: bool,
+    volume: u32,
+    muted: bool,
+}
+
+impl Kodi {
+    /// Connects to Kodi in the URL of its web server, like
+    /// `http://192.168.1.10:8080`.
+    pub fn connect(
+        url: &str,
+        username: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<Kodi> {
+        let kodi = Kodi {
+            url: format!("{}/jsonrpc", url.trim_end_matches('/')),
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            credentials: username.map(|username| {
+                (username.to_string(), password.map(String::from))
+            }),
+            next_id: Cell::new(1),
+            state: State::Idle,
+            last_poll: None,
+            paused: false,
+            volume: MAX_VOLUME,
+            muted: false,
+        };
+
+        // The API is checked before it's used, with the volume as well.
+        let props = kodi.call(
+            "Application.GetProperties",
+            json!({"properties": ["volume", "muted"]}),
+        )?;
+        info!("Connected to Kodi in {}", url);
+
+        Ok(Kodi {
+            volume: props["volume"].as_u64().unwrap_or(100) as u32,
+            muted: props["muted"].as_bool().unwrap_or(false),
+            ..kodi
+        })
+    }
+
+    /// Calls a method, returning its result.
+    fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let body = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": id,
+        });
+
+        let mut req = self.client.post(&self.url).json(&body);
+        if let Some((username, password)) = &self.credentials {
+            req = req.basic_auth(username, password.as_ref());
+        }
+        let res = req.send()?;
+        match res.status() {
+            StatusCode::OK => {}
+            StatusCode::UNAUTHORIZED => {
+                return Err(Error::FailedConnection(String::from(
+                    "Kodi rejected the username or password",
+                )))
+            }
+            status => {
+                return Err(Error::FailedRequest(format!(
+                    "Kodi returned {} for {}",
+                    status, method
+                )))
+            }
+        }
+
+        let res: Response = res.json()?;
+        match res.error {
+            Some(error) => Err(Error::Player(format!(
+                "Kodi failed {} ({}): {}",
+                method, error.code, error.message
+            ))),
+            None => Ok(res.result),
+        }
+    }
+
+    /// The ID of the player with a video, if any.
+    fn active_player(&self) -> Result<Option<u64>> {
+        let players = self.call("Player.GetActivePlayers", json!({}))?;
+        let players: Vec<ActivePlayer> = serde_json::from_value(players)?;
+        Ok(players
+            .into_iter()
+            .find(|player| player.kind == "video")
+            .map(|player| player.playerid))
+    }
+
+    /// Whether the player has the file with the URL, ignoring its headers.
+    fn is_playing(&self, id: u64, url: &str) -> bool {
+        let params = json!({"playerid": id, "properties": ["file"]});
+        match self.call("Player.GetItem", params) {
+            Ok(item) => {
+                let file = item["item"]["file"].as_str().unwrap_or_default();
+                file.split('|').next() == Some(url)
+            }
+            Err(e) => {
+                warn!("Couldn't get the video in Kodi: {}", e);
+                false
+            }
+        }
+    }
+
+    fn play_pause(&self, id: u64, play: bool) -> Result<()> {
+        self.call("Player.PlayPause", json!({"playerid": id, "play": play}))?;
+        Ok(())
+    }
+}
+
+/// Kodi takes the HTTP headers after the URL, separated by `|`, like
+/// `https://example.com/video.mp4|User-Agent=vidify&Referer=...`.
+fn kodi_url(source: &VideoSource) -> String {
+    if source.headers.is_empty() {
+        return source.url.clone();
+    }
+
+    let headers = source
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, encode(value)))
+        .collect::<Vec<_>>();
+    format!("{}|{}", source.url, headers.join("&"))
+}
+
+/// Percent-encodes everything but the unreserved characters.
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The times in the API are split into their units.
+fn to_time(duration: Duration) -> Value {
+    let secs = duration.as_secs();
+    json!({
+        "hours": secs / 3600,
+        "minutes": secs / 60 % 60,
+        "seconds": secs % 60,
+        "milliseconds": duration.subsec_millis(),
+    })
+}
+
+fn from_time(time: &Value) -> Option<Duration> {
+    let unit = |name: &str| time[name].as_u64();
+    let secs =
+        unit("hours")? * 3600 + unit("minutes")? * 60 + unit("seconds")?;
+    Some(
+        Duration::from_secs(secs)
+            + Duration::from_millis(unit("milliseconds")?),
+    )
+}
+
+impl PlayerBase for Kodi {
+    fn new(config: &Config) -> Result<Kodi> {
+        Kodi::connect(
+            &config.kodi_url,
+            config.kodi_username.as_deref(),
+            config.kodi_password.as_deref(),
+        )
+    }
+
+    /// The paused state is applied once Kodi starts playing it.
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        info!("Opening {} in Kodi", source.url);
+        let mut params = json!({"item": {"file": kodi_url(source)}});
+        if let Some(start) = source.start {
+            params["options"] = json!({"resume": to_time(start)});
+        }
+        self.call("Player.Open", params)?;
+        self.state = State::Opening(source.url.clone(), Instant::now());
+        self.last_poll = None;
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        if let State::Open(id) = self.state {
+            self.play_pause(id, true)?;
+        }
+        self.paused = false;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        if let State::Open(id) = self.state {
+            self.play_pause(id, false)?;
+        }
+        self.paused = true;
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn position(&self) -> Duration {
+        let id = match self.state {
+            State::Open(id) => id,
+            _ => return Duration::default(),
+        };
+
+        let params = json!({"playerid": id, "properties": ["time"]});
+        match self.call("Player.GetProperties", params) {
+            Ok(props) => from_time(&props["time"]).unwrap_or_default(),
+            Err(e) => {
+                warn!("Couldn't get the position in Kodi: {}", e);
+                Duration::default()
+            }
+        }
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        if let State::Open(id) = self.state {
+            let value = json!({"time": to_time(position)});
+            self.call("Player.Seek", json!({"playerid": id, "value": value}))?;
+        }
+        Ok(())
+    }
+
+    fn volume(&self) -> u32 {
+        self.volume
+    }
+
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        let volume = volume.min(MAX_VOLUME);
+        self.call("Application.SetVolume", json!({ "volume": volume }))?;
+        self.volume = volume;
+        Ok(())
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.call("Application.SetMute", json!({ "mute": muted }))?;
+        self.muted = muted;
+        Ok(())
+    }
+
+    fn speed(&self) -> f64 {
+        1.0
+    }
+
+    /// Kodi only has the speeds used to fast-forward, so the video can only
+    /// be played at the normal one.
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        check_speed(speed)?;
+        if (speed - 1.0).abs() > f64::EPSILON {
+            return Err(Error::Unsupported);
+        }
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        if self.state == State::Idle {
+            return None;
+        }
+        if let Some(last) = self.last_poll {
+            if last.elapsed() < POLL_INTERVAL {
+                return None;
+            }
+        }
+        self.last_poll = Some(Instant::now());
+
+        let active = match self.active_player() {
+            Ok(active) => active,
+            Err(e) => {
+                warn!("Couldn't get the state of Kodi: {}", e);
+                return None;
+            }
+        };
+        // The previous video may still be playing while it's opening.
+        let active = match (&self.state, active) {
+            (State::Opening(url, _), Some(id))
+                if !self.is_playing(id, url) =>
+            {
+                None
+            }
+            (_, active) => active,
+        };
+        match (&self.state, active) {
+            (State::Opening(..), Some(id)) => {
+                self.state = State::Open(id);
+                if self.paused {
+                    if let Err(e) = self.play_pause(id, false) {
+                        warn!("Couldn't pause Kodi: {}", e);
+                    }
+                }
+                Some(Event::Loaded)
+            }
+            (State::Opening(_, sent), None)
+                if sent.elapsed() > OPEN_TIMEOUT =>
+            {
+                self.state = State::Idle;
+                Some(Event::Error(String::from(
+                    "Kodi didn't start playing the video",
+                )))
+            }
+            (State::Open(_), None) => {
+                self.state = State::Idle;
+                Some(Event::Ended)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{check_player, MockServer, Request};
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// What the mock plays. A video replaces the previous one after the
+    /// second check of the active players, like if it took a while to
+    /// open.
+    #[derive(Default)]
+    struct Playing {
+        file: Option<String>,
+        opening: Option<String>,
+        checks: usize,
+        time: Value,
+    }
+
+    /// Answers the methods like Kodi, with the video being played after
+    /// `Player.Open` until `stopped` is set.
+    fn mock_kodi(stopped: Arc<Mutex<bool>>) -> MockServer {
+        let playing = Mutex::new(Playing::default());
+        MockServer::new(move |req: &Request| {
+            let body: Value = serde_json::from_str(&req.body).unwrap();
+            let params = &body["params"];
+            let mut playing = playing.lock().unwrap();
+            let result = match body["method"].as_str().unwrap() {
+                "Application.GetProperties" => {
+                    json!({"volume": 80, "muted": false})
+                }
+                "Player.Open" => {
+                    let file = params["item"]["file"].as_str().unwrap();
+                    playing.opening = Some(file.to_string());
+                    playing.checks = 0;
+                    playing.time = match params["options"].get("resume") {
+                        Some(time) => time.clone(),
+                        None => to_time(Duration::default()),
+                    };
+                    json!("OK")
+                }
+                "Player.GetActivePlayers" => {
+                    playing.checks += 1;
+                    if playing.checks == 2 && playing.opening.is_some() {
+                        playing.file = playing.opening.take();
+                    }
+                    if playing.file.is_some() && !*stopped.lock().unwrap() {
+                        json!([{"playerid": 1, "type": "video"}])
+                    } else {
+                        json!([])
+                    }
+                }
+                "Player.GetItem" => json!({"item": {
+                    "file": playing.file, "label": "", "type": "unknown"
+                }}),
+                "Player.GetProperties" => json!({"time": playing.time}),
+                "Player.Seek" => {
+                    playing.time = params["value"]["time"].clone();
+                    json!("OK")
+                }
+                _ => json!("OK"),
+            };
+            let res =
+                json!({"jsonrpc": "2.0", "id": body["id"], "result": result});
+            (200, "application/json", res.to_string())
+        })
+    }
+
+    /// The methods and their parameters, in order.
+    fn calls(server: &MockServer) -> Vec<(String, Value)> {
+        server
+            .requests()
+            .iter()
+            .map(|req| {
+                let body: Value = serde_json::from_str(&req.body).unwrap();
+                (
+                    body["method"].as_str().unwrap().to_string(),
+                    body["params"].clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn wait_event(kodi: &mut Kodi) -> Event {
+        let start = Instant::now();
+        loop {
+            if let Some(event) = kodi.poll_event() {
+                return event;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn playback() {
+        let stopped = Arc::new(Mutex::new(false));
+        let server = mock_kodi(Arc::clone(&stopped));
+        let mut kodi = Kodi::connect(&server.url, None, None).unwrap();
+        assert_eq!(kodi.volume(), 80);
+        assert_eq!(kodi.position(), Duration::default());
+
+        kodi.pause().unwrap();
+        let source = VideoSource::new("https://videos/song.mp4")
+            .with_start(Duration::from_millis(62_500))
+            .with_header("User-Agent", "vidify/3.0 (Linux)");
+        kodi.load(&source).unwrap();
+        assert_eq!(wait_event(&mut kodi), Event::Loaded);
+        assert!(kodi.is_paused());
+        assert_eq!(kodi.position(), Duration::from_millis(62_500));
+        kodi.seek(Duration::from_secs(3723)).unwrap();
+        assert_eq!(kodi.position(), Duration::from_secs(3723));
+        kodi.play().unwrap();
+        kodi.set_volume(MAX_VOLUME + 50).unwrap();
+        kodi.set_muted(true).unwrap();
+        kodi.set_speed(1.0).unwrap();
+        assert!(matches!(kodi.set_speed(1.5), Err(Error::Unsupported)));
+
+        let calls = calls(&server)
+            .into_iter()
+            .filter(|(method, _)| !method.starts_with("Player.Get"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [
+                (
+                    String::from("Application.GetProperties"),
+                    json!({"properties": ["volume", "muted"]})
+                ),
+                (
+                    String::from("Player.Open"),
+                    json!({
+                        "item": {
+                            "file": "https://videos/song.mp4|\
+                                     User-Agent=vidify%2F3.0%20%28Linux%29"
+                        },
+                        "options": {"resume": {
+                            "hours": 0, "minutes": 1, "seconds": 2,
+                            "milliseconds": 500
+                        }}
+                    })
+                ),
+                (
+                    String::from("Player.PlayPause"),
+                    json!({"playerid": 1, "play": false})
+                ),
+                (
+                    String::from("Player.Seek"),
+                    json!({"playerid": 1, "value": {"time": {
+                        "hours": 1, "minutes": 2, "seconds": 3,
+                        "milliseconds": 0
+                    }}})
+                ),
+                (
+                    String::from("Player.PlayPause"),
+                    json!({"playerid": 1, "play": true})
+                ),
+                (
+                    String::from("Application.SetVolume"),
+                    json!({"volume": 100})
+                ),
+                (String::from("Application.SetMute"), json!({"mute": true})),
+            ]
+        );
+
+        *stopped.lock().unwrap() = true;
+        assert_eq!(wait_event(&mut kodi), Event::Ended);
+        assert_eq!(kodi.position(), Duration::default());
+    }
+
+    #[test]
+    fn semantics() {
+        let server = mock_kodi(Arc::new(Mutex::new(false)));
+        let mut kodi = Kodi::connect(&server.url, None, None).unwrap();
+        let source = VideoSource::new("https://videos/song.mp4")
+            .with_start(Duration::from_secs(1))
+            .with_header("Referer", "https://example.com");
+        check_player(&mut kodi, &source);
+    }
+
+    #[test]
+    fn replaced() {
+        let server = mock_kodi(Arc::new(Mutex::new(false)));
+        let mut kodi = Kodi::connect(&server.url, None, None).unwrap();
+        kodi.load(&VideoSource::new("https://videos/first.mp4"))
+            .unwrap();
+        assert_eq!(wait_event(&mut kodi), Event::Loaded);
+
+        // The first video is still playing when it's checked right after.
+        let source = VideoSource::new("https://videos/second.mp4")
+            .with_header("User-Agent", "vidify");
+        kodi.load(&source).unwrap();
+        assert_eq!(kodi.poll_event(), None);
+        assert_eq!(wait_event(&mut kodi), Event::Loaded);
+        let items = calls(&server)
+            .into_iter()
+            .filter(|(method, _)| method == "Player.GetItem")
+            .count();
+        assert_eq!(items, 3);
+    }
+
+    #[test]
+    fn credentials() {
+        let server = MockServer::new(|req: &Request| {
+            // "kodi:secret" in base64.
+            match req.header("Authorization") {
+                Some("Basic a29kaTpzZWNyZXQ=") => (
+                    200,
+                    "application/json",
+                    String::from(
+                        r#"{"id": 1, "jsonrpc": "2.0", "result": {}}"#,
+                    ),
+                ),
+                _ => (401, "text/plain", String::new()),
+            }
+        });
+
+        assert!(
+            Kodi::connect(&server.url, Some("kodi"), Some("secret")).is_ok()
+        );
+        let error = Kodi::connect(&server.url, Some("kodi"), Some("wrong"))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("username or password"));
+        assert!(Kodi::connect(&server.url, None, None).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let server = MockServer::new(|_: &Request| {
+            let error = json!({
+                "code": -32100,
+                "message": "Failed to execute method."
+            });
+            let res = json!({"id": 1, "jsonrpc": "2.0", "error": error});
+            (200, "application/json", res.to_string())
+        });
+
+        let error = Kodi::connect(&server.url, None, None).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Player error: Kodi failed Application.GetProperties (-32100): \
+             Failed to execute method."
+        );
+    }
+}
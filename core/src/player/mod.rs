@@ -1,25 +1,133 @@
+pub mod browser;
+pub mod dlna;
 pub mod external;
+pub mod kodi;
+#[cfg(feature = "libmpv")]
 pub mod mpv;
+#[cfg(any(feature = "libmpv", unix))]
+pub mod mpv_fallback;
+pub mod mpv_flags;
+#[cfg(unix)]
+pub mod mpv_ipc;
 
 use crate::config::Config;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::video::fallback::Visual;
+
+use std::time::Duration;
 
 use strum_macros::{Display, EnumString};
 
-#[derive(Clone, Debug, Display, EnumString)]
+/// `Mpv` requires the `libmpv` feature, so the default is `MpvIpc`, which
+/// only needs the mpv binary.
+#[derive(Clone, Debug, Default, Display, EnumString)]
 pub enum Player {
     Mpv,
+    #[default]
+    MpvIpc,
     External,
+    Browser,
+    Kodi,
+    Dlna,
+}
+
+/// The maximum volume, in percent. Higher values are clamped.
+pub const MAX_VOLUME: u32 = 100;
+
+/// What the player should open.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VideoSource {
+    pub url: String,
+    /// Where to start playing, instead of the beginning.
+    pub start: Option<Duration>,
+    /// The HTTP headers needed to access the stream, like its user agent.
+    pub headers: Vec<(String, String)>,
+}
+
+impl VideoSource {
+    pub fn new(url: &str) -> VideoSource {
+        VideoSource {
+            url: url.to_string(),
+            ..VideoSource::default()
+        }
+    }
+
+    pub fn with_start(mut self, start: Duration) -> VideoSource {
+        self.start = Some(start);
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> VideoSource {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// What happened in the player, returned in the same order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The video was opened and its position is available.
+    Loaded,
+    /// The video played until its end.
+    Ended,
+    /// The player started waiting for more data, or it stopped waiting.
+    Buffering(bool),
+    /// The video couldn't be loaded, or it stopped because of an error.
+    Error(String),
 }
 
+/// The players are controlled with the same semantics:
+///
+/// * Loading a video replaces the current one and keeps the paused state,
+///   so that it can be loaded paused and started once it's in sync.
+/// * The positions are relative to the start of the video, and they're
+///   zero while nothing is loaded.
+/// * The volume is a percentage, up to `MAX_VOLUME`, and it's independent
+///   from the muted state. The speed is a positive factor, 1 being the
+///   normal speed.
+/// * The events are returned only once, in the order they happened.
 pub trait PlayerBase {
     fn new(config: &Config) -> Result<Self>
     where
         Self: Sized;
 
-    fn pause(&mut self);
+    fn load(&mut self, source: &VideoSource) -> Result<()>;
+    fn play(&mut self) -> Result<()>;
+    fn pause(&mut self) -> Result<()>;
     fn is_paused(&self) -> bool;
-    fn position(&self) -> u32;
-    fn seek(&mut self);
-    fn start_video(&mut self);
+    fn position(&self) -> Duration;
+    fn seek(&mut self, position: Duration) -> Result<()>;
+    fn volume(&self) -> u32;
+    fn set_volume(&mut self, volume: u32) -> Result<()>;
+    fn is_muted(&self) -> bool;
+    fn set_muted(&mut self, muted: bool) -> Result<()>;
+    fn speed(&self) -> f64;
+    /// Returns an error if the speed isn't positive.
+    fn set_speed(&mut self, speed: f64) -> Result<()>;
+    /// The next event, if any.
+    fn poll_event(&mut self) -> Option<Event>;
+
+    /// Loads the video of the next track in advance, so that switching to
+    /// it is close to instant. It's optional, so it returns
+    /// `Error::Unsupported` by default.
+    fn preload(&mut self, _source: &VideoSource) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// Shows something else while there's no music video for the track.
+    /// It's optional as well, so it returns `Error::Unsupported` by
+    /// default.
+    fn show_fallback(&mut self, _visual: &Visual) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Checks the speed before it's set, so that all the players reject the
+/// same values.
+pub fn check_speed(speed: f64) -> Result<()> {
+    if speed.is_finite() && speed > 0.0 {
+        Ok(())
+    } else {
+        Err(Error::Player(format!("invalid speed {}", speed)))
+    }
 }
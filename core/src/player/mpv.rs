@@ -1,27 +1,445 @@
+//! The embedded player, which uses libmpv. The handle is created with the
+//! `mpv_flags` in the config, and its events are received in a separate
+//! thread, since libmpv only lets the client block while waiting for them.
+
 use crate::config::Config;
-use crate::error::Result;
-use crate::player::PlayerBase;
+use crate::error::{Error, Result};
+use crate::player::{check_speed, Event, PlayerBase, VideoSource, MAX_VOLUME};
+use crate::player::{mpv_fallback, mpv_flags};
+use crate::video::fallback::Visual;
+
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use libmpv::events::{Event as MpvEvent, PropertyData};
+use libmpv::{mpv_error, Format};
+use log::{info, warn};
+
+impl From<libmpv::Error> for Error {
+    fn from(err: libmpv::Error) -> Self {
+        Error::Player(describe(&err))
+    }
+}
+
+pub struct Mpv {
+    handle: Arc<libmpv::Mpv>,
+    events: mpsc::Receiver<Event>,
+    /// The one mpv uses by default, restored when a source doesn't have
+    /// one.
+    user_agent: String,
+    /// The URL appended to the playlist by `preload`.
+    preloaded: Option<String>,
+    /// Whether a fallback visual is shown, whose options are reset when
+    /// loading a video.
+    fallback: bool,
+}
+
+impl Mpv {
+    /// Creates the handle with the given flags, in the same format as the
+    /// ones in the config, like `--vo=null --no-border`. The invalid ones
+    /// are reported as a config error.
+    pub fn with_flags(flags: &str) -> Result<Mpv> {
+        let options = mpv_flags::parse(flags)?;
+        let mut failed = None;
+        let handle = libmpv::Mpv::with_initializer(|init| {
+            // The handle is kept alive when nothing is playing.
+            init.set_property("idle", "yes")?;
+            for option in &options {
+                let value = option.value.as_str();
+                if let Err(e) = init.set_property(&option.name, value) {
+                    failed = Some(option);
+                    return Err(e);
+                }
+            }
+            Ok(())
+        })
+        .map_err(|e| match failed {
+            Some(option) => {
+                mpv_flags::invalid(&option.to_string(), &describe(&e))
+            }
+            None => Error::from(e),
+        })?;
+        let handle = Arc::new(handle);
+        let user_agent = handle.get_property("user-agent")?;
+
+        let (sender, events) = mpsc::channel();
+        let (ready_sender, ready) = mpsc::channel();
+        let listened = Arc::clone(&handle);
+        thread::spawn(move || listen(&listened, sender, ready_sender));
+        // The events aren't observed until the thread is ready.
+        ready.recv().map_err(|_| {
+            Error::Player(String::from("the event thread stopped"))
+        })??;
 
-use libmpv;
+        Ok(Mpv {
+            handle,
+            events,
+            user_agent,
+            preloaded: None,
+            fallback: false,
+        })
+    }
+
+    fn is_idle(&self) -> bool {
+        self.handle.get_property("idle-active").unwrap_or(true)
+    }
 
-pub struct Mpv {}
+    fn set_options(&self, options: &[mpv_flags::MpvOption]) -> Result<()> {
+        for option in options {
+            self.handle
+                .set_property(&option.name, option.value.as_str())?;
+        }
+        Ok(())
+    }
+}
 
 impl PlayerBase for Mpv {
     fn new(config: &Config) -> Result<Mpv> {
-        Ok(Mpv {})
+        Mpv::with_flags(&config.mpv_flags)
+    }
+
+    /// The start and the headers are set as options before loading the
+    /// video, since they only apply to the next one. A preloaded video is
+    /// already in the playlist, so it's only switched to.
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        info!("Loading {} in mpv", source.url);
+        if self.fallback {
+            self.set_options(&mpv_fallback::reset())?;
+            self.fallback = false;
+        }
+        let start = match source.start {
+            Some(start) => start.as_secs_f64().to_string(),
+            None => String::from("none"),
+        };
+        self.handle.set_property("start", start.as_str())?;
+
+        let mut user_agent = self.user_agent.as_str();
+        let mut fields = Vec::new();
+        for (name, value) in &source.headers {
+            // mpv sends its own user agent, which would be duplicated.
+            if name.eq_ignore_ascii_case("user-agent") {
+                user_agent = value;
+            } else {
+                fields.push(escape_list(&format!("{}: {}", name, value)));
+            }
+        }
+        self.handle.set_property("user-agent", user_agent)?;
+        self.handle
+            .set_property("http-header-fields", fields.join(",").as_str())?;
+
+        if self.preloaded.take().as_ref() == Some(&source.url) {
+            self.handle.command("playlist-next", &["weak"])?;
+        } else {
+            self.handle
+                .command("loadfile", &[&quote(&source.url), "replace"])?;
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        Ok(self.handle.unpause()?)
     }
 
-    fn pause(&mut self) {}
+    fn pause(&mut self) -> Result<()> {
+        Ok(self.handle.pause()?)
+    }
 
     fn is_paused(&self) -> bool {
-        true
+        self.handle.get_property("pause").unwrap_or(true)
+    }
+
+    fn position(&self) -> Duration {
+        self.handle
+            .get_property::<f64>("time-pos")
+            .map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+            .unwrap_or_default()
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        if self.is_idle() {
+            return Ok(());
+        }
+
+        let secs = position.as_secs_f64().to_string();
+        self.handle.command("seek", &[&secs, "absolute"])?;
+        Ok(())
+    }
+
+    fn volume(&self) -> u32 {
+        self.handle
+            .get_property::<f64>("volume")
+            .map(|volume| volume.round() as u32)
+            .unwrap_or(MAX_VOLUME)
+    }
+
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        let volume = f64::from(volume.min(MAX_VOLUME));
+        Ok(self.handle.set_property("volume", volume)?)
+    }
+
+    fn is_muted(&self) -> bool {
+        self.handle.get_property("mute").unwrap_or(false)
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        Ok(self.handle.set_property("mute", muted)?)
+    }
+
+    fn speed(&self) -> f64 {
+        self.handle.get_property("speed").unwrap_or(1.0)
+    }
+
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        check_speed(speed)?;
+        Ok(self.handle.set_property("speed", speed)?)
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        self.events.try_recv().ok()
+    }
+
+    /// The video is appended to the playlist after the current one, and
+    /// mpv starts downloading it before it's loaded.
+    fn preload(&mut self, source: &VideoSource) -> Result<()> {
+        info!("Preloading {} in mpv", source.url);
+        self.handle.set_property("prefetch-playlist", true)?;
+        self.handle.command("playlist-clear", &[])?;
+        self.handle
+            .command("loadfile", &[&quote(&source.url), "append"])?;
+        self.preloaded = Some(source.url.clone());
+        Ok(())
+    }
+
+    fn show_fallback(&mut self, visual: &Visual) -> Result<()> {
+        let (url, options) = mpv_fallback::options(visual);
+        info!("Showing {} in mpv", url);
+        self.set_options(&options)?;
+        self.fallback = true;
+        self.preloaded = None;
+        self.handle.command("loadfile", &[&quote(url), "replace"])?;
+        Ok(())
+    }
+}
+
+impl Drop for Mpv {
+    /// The event thread holds the handle as well, so it has to be stopped
+    /// for the handle to be destroyed.
+    fn drop(&mut self) {
+        if let Err(e) = self.handle.command("quit", &[]) {
+            warn!("Couldn't quit mpv: {}", Error::from(e));
+        }
+    }
+}
+
+/// Sends the events of the handle until it shuts down. The end of a file
+/// isn't reported by libmpv when it finishes normally, so it's detected
+/// when the player becomes idle after starting it instead.
+fn listen(
+    handle: &libmpv::Mpv,
+    sender: mpsc::Sender<Event>,
+    ready: mpsc::Sender<Result<()>>,
+) {
+    let mut ctx = handle.create_event_context();
+    let observed = ctx
+        .observe_property("idle-active", Format::Flag, 0)
+        .and_then(|_| {
+            ctx.observe_property("paused-for-cache", Format::Flag, 1)
+        })
+        .map_err(Error::from);
+    let failed = observed.is_err();
+    let _ = ready.send(observed);
+    if failed {
+        return;
+    }
+
+    let mut playing = false;
+    let mut buffering = false;
+    loop {
+        let event = match ctx.wait_event(-1.0) {
+            Some(Ok(MpvEvent::Shutdown)) => break,
+            Some(Ok(MpvEvent::StartFile)) => {
+                playing = true;
+                continue;
+            }
+            Some(Ok(MpvEvent::FileLoaded)) => Event::Loaded,
+            Some(Ok(MpvEvent::PropertyChange {
+                name: "idle-active",
+                change: PropertyData::Flag(true),
+                ..
+            })) if playing => {
+                playing = false;
+                Event::Ended
+            }
+            Some(Ok(MpvEvent::PropertyChange {
+                name: "paused-for-cache",
+                change: PropertyData::Flag(paused),
+                ..
+            })) if paused != buffering => {
+                buffering = paused;
+                Event::Buffering(paused)
+            }
+            Some(Err(e)) => {
+                playing = false;
+                Event::Error(describe(&e))
+            }
+            _ => continue,
+        };
+
+        if sender.send(event).is_err() {
+            break;
+        }
+    }
+}
+
+/// Escapes the separator of the items in a list option.
+fn escape_list(item: &str) -> String {
+    item.replace('\\', "\\\\").replace(',', "\\,")
+}
+
+/// Quotes an argument of a command, which are separated by spaces.
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The errors of libmpv only have a code, which is translated to the same
+/// message mpv would show.
+fn describe(err: &libmpv::Error) -> String {
+    let code = match err {
+        libmpv::Error::Raw(code) => *code,
+        libmpv::Error::Loadfiles { error, .. } => return describe(error),
+        other => return format!("{:?}", other),
+    };
+
+    let message = match code {
+        mpv_error::LoadingFailed => "loading failed",
+        mpv_error::UnknownFormat => "unrecognized file format",
+        mpv_error::NothingToPlay => "no audio or video data played",
+        mpv_error::AoInitFailed => "audio output initialization failed",
+        mpv_error::VoInitFailed => "video output initialization failed",
+        mpv_error::Unsupported => "unsupported",
+        mpv_error::OptionNotFound => "option not found",
+        mpv_error::OptionFormat => "unsupported format for accessing option",
+        mpv_error::OptionError => "error setting option",
+        mpv_error::PropertyNotFound => "property not found",
+        mpv_error::PropertyUnavailable => "property unavailable",
+        mpv_error::Command => "error running command",
+        _ => return format!("mpv error {}", code),
+    };
+
+    message.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{check_player, temp_dir};
+
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Instant;
+
+    const HEADLESS: &str = "--vo=null --ao=null";
+
+    /// Writes an uncompressed video with the given number of frames, at ten
+    /// per second, which mpv can play without any codecs.
+    fn fixture(dir: &Path, frames: usize) -> PathBuf {
+        let (width, height) = (16, 16);
+        let mut data = format!(
+            "YUV4MPEG2 W{} H{} F10:1 Ip A1:1 C420jpeg\n",
+            width, height
+        )
+        .into_bytes();
+        for frame in 0..frames {
+            data.extend(b"FRAME\n");
+            // The luma changes between frames, and the chroma is neutral.
+            data.extend(vec![(frame * 10 % 256) as u8; width * height]);
+            data.extend(vec![128; width * height / 2]);
+        }
+
+        let path = dir.join(format!("{}-frames.y4m", frames));
+        fs::write(&path, data).unwrap();
+        path
     }
 
-    fn position(&self) -> u32 {
-        123
+    /// Waits for the next event, failing after a few seconds.
+    fn next_event(player: &mut Mpv) -> Event {
+        let start = Instant::now();
+        loop {
+            if let Some(event) = player.poll_event() {
+                return event;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
     }
 
-    fn seek(&mut self) {}
+    #[test]
+    fn escaped() {
+        assert_eq!(quote(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(escape_list("Cookie: a=1, b=2"), r"Cookie: a=1\, b=2");
+    }
+
+    #[test]
+    fn playback() {
+        let dir = temp_dir("mpv-playback");
+        let video = fixture(&dir, 30);
+        let mut player = Mpv::with_flags(HEADLESS).unwrap();
+        assert_eq!(player.position(), Duration::default());
+
+        let source = VideoSource::new(video.to_str().unwrap())
+            .with_start(Duration::from_secs(1))
+            .with_header("User-Agent", "vidify")
+            .with_header("Referer", "https://example.com");
+        check_player(&mut player, &source);
+        let agent: String = player.handle.get_property("user-agent").unwrap();
+        assert_eq!(agent, "vidify");
+
+        // The rest of the video is played until the end.
+        assert_eq!(next_event(&mut player), Event::Ended);
+        assert_eq!(player.position(), Duration::default());
+        player.seek(Duration::from_secs(1)).unwrap();
+    }
 
-    fn start_video(&mut self) {}
+    #[test]
+    fn errors() {
+        let dir = temp_dir("mpv-errors");
+        let broken = dir.join("broken.mp4");
+        fs::write(&broken, "This isn't a video").unwrap();
+        let video = fixture(&dir, 5);
+        let mut player = Mpv::with_flags(HEADLESS).unwrap();
+
+        player
+            .load(&VideoSource::new(broken.to_str().unwrap()))
+            .unwrap();
+        match next_event(&mut player) {
+            Event::Error(e) => assert!(!e.is_empty()),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // A video can be played after an error.
+        player
+            .load(&VideoSource::new(video.to_str().unwrap()))
+            .unwrap();
+        assert_eq!(next_event(&mut player), Event::Loaded);
+        assert_eq!(next_event(&mut player), Event::Ended);
+        assert_eq!(player.poll_event(), None);
+    }
+
+    #[test]
+    fn invalid_flags() {
+        let cases = &[
+            ("--vo=null --unknown-option=1", "'--unknown-option=1'"),
+            ("--vo=null --volume=loud", "'--volume=loud'"),
+            ("--vo=null --no-osc=maybe", "'--no-osc=maybe'"),
+            ("--vo=null --title=\"Unclosed", "'--title=Unclosed'"),
+        ];
+        for (flags, named) in cases {
+            let error = Mpv::with_flags(flags).err().unwrap();
+            assert!(matches!(error, Error::InvalidConfig(_)));
+            assert!(error.to_string().contains(named), "{}", error);
+        }
+    }
 }
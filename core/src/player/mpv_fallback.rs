@@ -0,0 +1,64 @@
+//! The options that show the fallback visuals in mpv, shared by both of its
+//! players. They're set before loading the visual's URL, and they're reset
+//! once a video is loaded again.
+//!
+//! The artwork is scaled over a blurred copy of itself with a filter, and
+//! the artist and title are shown in the OSD.
+
+use crate::player::mpv_flags::MpvOption;
+use crate::video::fallback::Visual;
+
+/// The filter for the artwork, which fills a 1080p canvas with the blurred
+/// copy and fits the original one in its center.
+const ARTWORK_FILTER: &str = "lavfi=[split[a][b];\
+    [a]scale=1920:1080:force_original_aspect_ratio=increase,\
+    crop=1920:1080,boxblur=20[bg];\
+    [b]scale=1920:1080:force_original_aspect_ratio=decrease[fg];\
+    [bg][fg]overlay=(W-w)/2:(H-h)/2]";
+
+/// The URL to load for the visual, and the options to set before.
+pub fn options(visual: &Visual) -> (&str, Vec<MpvOption>) {
+    match visual {
+        Visual::Artwork { url, artist, title } => {
+            let text = artist
+                .iter()
+                .chain(title)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" - ");
+            let options = vec![
+                MpvOption::new("start", "none"),
+                MpvOption::new("loop-file", "no"),
+                MpvOption::new("aid", "no"),
+                MpvOption::new("image-display-duration", "inf"),
+                MpvOption::new("vf", ARTWORK_FILTER),
+                // The OSD expands the properties that start with `$`.
+                MpvOption::new("osd-msg1", &text.replace('$', "$$")),
+            ];
+            (url, options)
+        }
+        // The audio isn't the track's, so it's disabled.
+        Visual::Loop(url) => {
+            let options = vec![
+                MpvOption::new("start", "none"),
+                MpvOption::new("loop-file", "inf"),
+                MpvOption::new("aid", "no"),
+                MpvOption::new("image-display-duration", "1"),
+                MpvOption::new("vf", ""),
+                MpvOption::new("osd-msg1", ""),
+            ];
+            (url, options)
+        }
+    }
+}
+
+/// The options that restore the normal playback after a visual.
+pub fn reset() -> Vec<MpvOption> {
+    vec![
+        MpvOption::new("loop-file", "no"),
+        MpvOption::new("aid", "auto"),
+        MpvOption::new("image-display-duration", "1"),
+        MpvOption::new("vf", ""),
+        MpvOption::new("osd-msg1", ""),
+    ]
+}
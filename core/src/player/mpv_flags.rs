@@ -0,0 +1,174 @@
+//! Parses the flags for mpv in the config, which use the same syntax as its
+//! command line: `--key=value`, `--flag` and `--no-flag`, separated by
+//! spaces. The values may be quoted to include spaces, and the quotes and
+//! backslashes may be escaped with a backslash, except inside single
+//! quotes. Any other backslash is kept, like the ones in Windows paths.
+
+use crate::error::{Error, Result};
+
+use std::fmt;
+
+/// An option for mpv, with its value as a string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MpvOption {
+    pub name: String,
+    pub value: String,
+}
+
+impl MpvOption {
+    pub fn new(name: &str, value: &str) -> MpvOption {
+        MpvOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Formatted as a flag, to be shown in the errors.
+impl fmt::Display for MpvOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "--{}={}", self.name, self.value)
+    }
+}
+
+/// Parses the flags in order, so that the later ones override the earlier
+/// ones once applied. The flags without a value are enabled, or disabled
+/// if they start with `no-`.
+pub fn parse(flags: &str) -> Result<Vec<MpvOption>> {
+    split(flags)?
+        .into_iter()
+        .map(|flag| {
+            let name = match flag.strip_prefix("--") {
+                Some(name) if !name.is_empty() && !name.starts_with('=') => {
+                    name
+                }
+                _ => {
+                    return Err(invalid(
+                        &flag,
+                        "expected --name or --name=value",
+                    ))
+                }
+            };
+
+            let option = match name.find('=') {
+                Some(pos) => MpvOption::new(&name[..pos], &name[pos + 1..]),
+                None => match name.strip_prefix("no-") {
+                    Some(name) => MpvOption::new(name, "no"),
+                    None => MpvOption::new(name, "yes"),
+                },
+            };
+            if option.name.chars().any(char::is_whitespace) {
+                return Err(invalid(&flag, "the name can't contain spaces"));
+            }
+
+            Ok(option)
+        })
+        .collect()
+}
+
+/// The error for a flag that can't be used.
+pub fn invalid(flag: &str, reason: &str) -> Error {
+    Error::InvalidConfig(format!("mpv flag '{}': {}", flag, reason))
+}
+
+/// Splits the flags by spaces, except inside quotes, which are removed.
+fn split(flags: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = None;
+    let mut quote = None;
+    let mut chars = flags.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', None) | ('\\', Some('"')) => {
+                let escaped =
+                    chars.next_if(|next| matches!(next, '"' | '\'' | '\\'));
+                word.get_or_insert_with(String::new)
+                    .push(escaped.unwrap_or(c));
+            }
+            ('"', None) | ('\'', None) => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (c, Some(open)) if c == open => quote = None,
+            (c, None) if c.is_whitespace() => words.extend(word.take()),
+            (c, _) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        let last = word.unwrap_or_default();
+        return Err(invalid(&last, "the quote isn't closed"));
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        assert_eq!(
+            parse(" --vo=null --no-border  --fs ").unwrap(),
+            [
+                MpvOption::new("vo", "null"),
+                MpvOption::new("border", "no"),
+                MpvOption::new("fs", "yes"),
+            ]
+        );
+        assert_eq!(
+            parse("--title=\"My videos\" --sub-font='Noto Sans'").unwrap(),
+            [
+                MpvOption::new("title", "My videos"),
+                MpvOption::new("sub-font", "Noto Sans"),
+            ]
+        );
+        assert_eq!(
+            parse(r#"--title="Say \"hi\"" --path=C:\\videos"#).unwrap(),
+            [
+                MpvOption::new("title", "Say \"hi\""),
+                MpvOption::new("path", "C:\\videos"),
+            ]
+        );
+        assert_eq!(
+            parse(
+                r#"--screenshot-directory=C:\videos --title="C:\My videos" "#
+            )
+            .unwrap(),
+            [
+                MpvOption::new("screenshot-directory", "C:\\videos"),
+                MpvOption::new("title", "C:\\My videos"),
+            ]
+        );
+        assert_eq!(
+            parse(r#"--title='\"hi\"' --path=C:\"#).unwrap(),
+            [
+                MpvOption::new("title", "\\\"hi\\\""),
+                MpvOption::new("path", "C:\\"),
+            ]
+        );
+        assert_eq!(
+            parse("--osd-msg1=").unwrap(),
+            [MpvOption::new("osd-msg1", "")]
+        );
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_flags() {
+        let cases = &[
+            ("fs", "'fs'"),
+            ("--vo=gpu -v", "'-v'"),
+            ("--", "'--'"),
+            ("--=yes", "'--=yes'"),
+            ("--title=\"Unclosed", "'--title=Unclosed'"),
+            ("--\"no border\"", "'--no border'"),
+        ];
+        for (flags, named) in cases {
+            let error = parse(flags).unwrap_err().to_string();
+            assert!(error.contains(named), "{}: {}", flags, error);
+        }
+    }
+}
@@ -0,0 +1,1060 @@
+//! A player that controls a standalone mpv process through its JSON IPC, so
+//! that libmpv doesn't have to be linked. mpv is started with the flags in
+//! the config and a socket to receive the commands, and it's started again
+//! if it dies, restoring the state it had. The properties read by the
+//! player are observed, so that they don't have to be requested each time.
+//!
+//! The protocol is documented in <https://mpv.io/manual/master/#json-ipc>.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::player::{check_speed, Event, PlayerBase, VideoSource, MAX_VOLUME};
+use crate::player::{mpv_fallback, mpv_flags};
+use crate::video::fallback::Visual;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// How long to wait for the replies, and for the socket after starting
+/// mpv.
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The minimum time between restarts, so that a broken mpv isn't started
+/// over and over.
+const RESTART_INTERVAL: Duration = Duration::from_secs(1);
+/// The properties observed, whose IDs are their positions starting at 1.
+const OBSERVED: &[&str] = &[
+    "paused-for-cache",
+    "pause",
+    "volume",
+    "mute",
+    "speed",
+    "time-pos",
+    "idle-active",
+];
+
+/// Used to have a different socket for each player.
+static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+/// Both the replies and the events are received as a JSON object in a line.
+#[derive(Deserialize)]
+struct Message {
+    request_id: Option<u64>,
+    error: Option<String>,
+    #[serde(default)]
+    data: Value,
+    event: Option<String>,
+    reason: Option<String>,
+    file_error: Option<String>,
+    name: Option<String>,
+}
+
+/// The last values of the observed properties. They're also the state
+/// restored after restarting mpv.
+#[derive(Clone, Debug)]
+struct Properties {
+    paused: bool,
+    volume: u32,
+    muted: bool,
+    speed: f64,
+    /// `None` if there's no video.
+    position: Option<Duration>,
+    idle: bool,
+}
+
+impl Properties {
+    fn update(&mut self, name: &str, value: &Value) {
+        match name {
+            "pause" => self.paused = value.as_bool().unwrap_or(self.paused),
+            "volume" => {
+                if let Some(volume) = value.as_f64() {
+                    self.volume = volume.round() as u32;
+                }
+            }
+            "mute" => self.muted = value.as_bool().unwrap_or(self.muted),
+            "speed" => self.speed = value.as_f64().unwrap_or(self.speed),
+            "time-pos" => {
+                self.position = value
+                    .as_f64()
+                    .map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+            }
+            "idle-active" => self.idle = value.as_bool().unwrap_or(self.idle),
+            _ => {}
+        }
+    }
+}
+
+struct Reply {
+    id: u64,
+    error: String,
+    data: Value,
+}
+
+/// A connection to the socket, whose messages are read in a separate
+/// thread.
+struct Connection {
+    stream: UnixStream,
+    replies: mpsc::Receiver<Reply>,
+    next_id: AtomicU64,
+    closed: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// The events are sent to the given channel, and the changes of the
+    /// properties are saved in the given ones. Both are shared between
+    /// connections.
+    fn open(
+        socket: &Path,
+        events: mpsc::Sender<Event>,
+        properties: Arc<Mutex<Properties>>,
+    ) -> Result<Connection> {
+        let stream = UnixStream::connect(socket)?;
+        let reader = stream.try_clone()?;
+        let (sender, replies) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let flag = Arc::clone(&closed);
+        thread::spawn(move || {
+            read_messages(reader, &sender, &events, &properties);
+            flag.store(true, Ordering::SeqCst);
+        });
+
+        Ok(Connection {
+            stream,
+            replies,
+            next_id: AtomicU64::new(1),
+            closed,
+        })
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Runs a command, returning the data in its reply.
+    fn request(&self, command: &Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let line = json!({ "command": command, "request_id": id });
+        if let Err(e) = writeln!(&self.stream, "{}", line) {
+            self.closed.store(true, Ordering::SeqCst);
+            return Err(e.into());
+        }
+
+        // The replies to the requests that timed out may still arrive.
+        loop {
+            let reply = match self.replies.recv_timeout(TIMEOUT) {
+                Ok(reply) => reply,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(Error::Player(format!(
+                        "mpv didn't reply to {}",
+                        command
+                    )))
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.closed.store(true, Ordering::SeqCst);
+                    return Err(Error::Player(String::from(
+                        "the connection with mpv was closed",
+                    )));
+                }
+            };
+            if reply.id != id {
+                continue;
+            }
+
+            return match reply.error.as_str() {
+                "success" => Ok(reply.data),
+                error => Err(Error::Player(format!(
+                    "{} failed: {}",
+                    command, error
+                ))),
+            };
+        }
+    }
+}
+
+impl Drop for Connection {
+    /// Stops the thread reading the messages.
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Reads the messages until the connection is closed, sending the replies
+/// and the events to their channels, and saving the observed properties.
+fn read_messages(
+    stream: UnixStream,
+    replies: &mpsc::Sender<Reply>,
+    events: &mpsc::Sender<Event>,
+    properties: &Mutex<Properties>,
+) {
+    let mut buffering = false;
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let message: Message = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Ignoring invalid message from mpv: {}", e);
+                continue;
+            }
+        };
+
+        if let Some(id) = message.request_id {
+            let _ = replies.send(Reply {
+                id,
+                error: message.error.unwrap_or_default(),
+                data: message.data,
+            });
+            continue;
+        }
+
+        let event = match message.event.as_deref() {
+            Some("file-loaded") => Event::Loaded,
+            Some("end-file") => match message.reason.as_deref() {
+                Some("eof") => Event::Ended,
+                Some("error") => Event::Error(
+                    message
+                        .file_error
+                        .unwrap_or_else(|| String::from("unknown error")),
+                ),
+                _ => continue,
+            },
+            Some("property-change")
+                if message.name.as_deref() == Some("paused-for-cache") =>
+            {
+                let paused = message.data.as_bool().unwrap_or(false);
+                if paused == buffering {
+                    continue;
+                }
+                buffering = paused;
+                Event::Buffering(paused)
+            }
+            // The data is missing while the property is unavailable.
+            Some("property-change") => {
+                if let Some(name) = &message.name {
+                    properties.lock().unwrap().update(name, &message.data);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let _ = events.send(event);
+    }
+}
+
+/// The mpv process, when it's owned by the player.
+struct Process {
+    binary: String,
+    args: Vec<String>,
+    child: Child,
+}
+
+impl Process {
+    fn spawn(binary: &str, args: &[String], socket: &Path) -> Result<Process> {
+        let _ = fs::remove_file(socket);
+        info!("Starting {} {}", binary, args.join(" "));
+        let mut child = Command::new(binary)
+            .arg("--idle=yes")
+            .arg(format!("--input-ipc-server={}", socket.display()))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => {
+                    Error::MissingBinary(binary.to_string())
+                }
+                _ => Error::IO(e),
+            })?;
+
+        // The output is logged, and the last line is kept in case it fails
+        // to start.
+        let last = Arc::new(Mutex::new(String::new()));
+        if let Some(stderr) = child.stderr.take() {
+            let last = Arc::clone(&last);
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    debug!("mpv: {}", line);
+                    *last.lock().unwrap() = line;
+                }
+            });
+        }
+
+        let start = Instant::now();
+        while !socket.exists() {
+            if let Some(status) = child.try_wait()? {
+                // The output may still be being read.
+                thread::sleep(Duration::from_millis(50));
+                return Err(Error::FailedCommand(format!(
+                    "{} ({}): {}",
+                    binary,
+                    status,
+                    last.lock().unwrap().trim()
+                )));
+            }
+            if start.elapsed() > TIMEOUT {
+                let _ = child.kill();
+                return Err(Error::Player(String::from(
+                    "mpv didn't create its socket",
+                )));
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        Ok(Process {
+            binary: binary.to_string(),
+            args: args.to_vec(),
+            child,
+        })
+    }
+
+    fn restart(&mut self, socket: &Path) -> Result<()> {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        *self = Process::spawn(&self.binary, &self.args, socket)?;
+        Ok(())
+    }
+}
+
+pub struct MpvIpc {
+    /// `None` if mpv was started by someone else, in which case it's only
+    /// connected to again if the connection is lost.
+    process: Option<Process>,
+    socket: PathBuf,
+    conn: Connection,
+    event_sender: mpsc::Sender<Event>,
+    events: mpsc::Receiver<Event>,
+    /// The one mpv uses by default, restored when a source doesn't have
+    /// one.
+    user_agent: String,
+    last_restart: Option<Instant>,
+    /// The video restored after restarting mpv, along with the properties.
+    source: Option<VideoSource>,
+    /// The visual shown instead of a video, if any.
+    fallback: Option<Visual>,
+    /// The URL appended to the playlist by `preload`.
+    preloaded: Option<String>,
+    properties: Arc<Mutex<Properties>>,
+}
+
+impl MpvIpc {
+    /// Starts mpv with the given flags, in the same format as the ones in
+    /// the config.
+    pub fn spawn(binary: &str, flags: &str) -> Result<MpvIpc> {
+        let args = mpv_flags::parse(flags)?
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let socket = env::temp_dir().join(format!(
+            "vidify-mpv-{}-{}.sock",
+            process::id(),
+            SOCKETS.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let process = Process::spawn(binary, &args, &socket)?;
+        MpvIpc::init(Some(process), socket)
+    }
+
+    /// Uses an mpv that is already listening in the socket.
+    pub fn connect(socket: &Path) -> Result<MpvIpc> {
+        MpvIpc::init(None, socket.to_path_buf())
+    }
+
+    /// The initial values of the properties are requested, since their
+    /// first changes are received in the background.
+    fn init(process: Option<Process>, socket: PathBuf) -> Result<MpvIpc> {
+        let (event_sender, events) = mpsc::channel();
+        let properties = Arc::new(Mutex::new(Properties {
+            paused: false,
+            volume: 100,
+            muted: false,
+            speed: 1.0,
+            position: None,
+            idle: true,
+        }));
+        let conn = Connection::open(
+            &socket,
+            event_sender.clone(),
+            Arc::clone(&properties),
+        )?;
+        let get = |name| conn.request(&json!(["get_property", name]));
+        let user_agent = get("user-agent")?.as_str().unwrap_or("").to_string();
+        for name in &["pause", "volume", "mute", "speed", "idle-active"] {
+            properties.lock().unwrap().update(name, &get(name)?);
+        }
+        observe(&conn)?;
+
+        Ok(MpvIpc {
+            process,
+            socket,
+            conn,
+            event_sender,
+            events,
+            user_agent,
+            last_restart: None,
+            source: None,
+            fallback: None,
+            preloaded: None,
+            properties,
+        })
+    }
+
+    /// Runs a command, restarting mpv and trying again if it died.
+    fn command(&mut self, command: Value) -> Result<Value> {
+        match self.conn.request(&command) {
+            Err(e) if self.conn.is_closed() => {
+                warn!("Lost the connection with mpv ({}), restarting it", e);
+                self.restart()?;
+                self.conn.request(&command)
+            }
+            result => result,
+        }
+    }
+
+    fn properties(&self) -> MutexGuard<'_, Properties> {
+        self.properties.lock().unwrap()
+    }
+
+    /// Starts mpv again if it's owned, connects to it and restores the
+    /// state it had, including the video and its position. The properties
+    /// are observed again once restored, so that the new process' defaults
+    /// don't replace them.
+    fn restart(&mut self) -> Result<()> {
+        if let Some(last) = self.last_restart {
+            if last.elapsed() < RESTART_INTERVAL {
+                return Err(Error::Player(String::from(
+                    "mpv was restarted too recently",
+                )));
+            }
+        }
+        self.last_restart = Some(Instant::now());
+
+        if let Some(process) = &mut self.process {
+            process.restart(&self.socket)?;
+        }
+        self.conn = Connection::open(
+            &self.socket,
+            self.event_sender.clone(),
+            Arc::clone(&self.properties),
+        )?;
+        // The playlist is lost with the old process.
+        self.preloaded = None;
+
+        let state = self.properties().clone();
+        let mut commands = vec![
+            json!(["set_property", "volume", state.volume]),
+            json!(["set_property", "mute", state.muted]),
+            json!(["set_property", "speed", state.speed]),
+            json!(["set_property", "pause", state.paused]),
+        ];
+        if let Some(visual) = &self.fallback {
+            commands.extend(fallback_commands(visual));
+        } else if let Some(source) = &self.source {
+            let start = state.position.unwrap_or_default();
+            commands
+                .extend(self.load_commands(&source.clone().with_start(start)));
+        }
+        for command in commands {
+            self.conn.request(&command)?;
+        }
+
+        observe(&self.conn)
+    }
+
+    /// The start and the headers are set as options before loading the
+    /// video, since they only apply to the next one. A preloaded video is
+    /// already in the playlist, so it's only switched to.
+    fn load_commands(&self, source: &VideoSource) -> Vec<Value> {
+        let start = match source.start {
+            Some(start) => start.as_secs_f64().to_string(),
+            None => String::from("none"),
+        };
+        let mut user_agent = self.user_agent.as_str();
+        let mut fields = Vec::new();
+        for (name, value) in &source.headers {
+            // mpv sends its own user agent, which would be duplicated.
+            if name.eq_ignore_ascii_case("user-agent") {
+                user_agent = value;
+            } else {
+                fields.push(format!("{}: {}", name, value));
+            }
+        }
+
+        vec![
+            json!(["set_property", "start", start]),
+            json!(["set_property", "user-agent", user_agent]),
+            json!(["set_property", "http-header-fields", fields]),
+            if self.preloaded.as_deref() == Some(source.url.as_str()) {
+                json!(["playlist-next", "weak"])
+            } else {
+                json!(["loadfile", source.url, "replace"])
+            },
+        ]
+    }
+}
+
+/// Sets the options of the visual before loading it.
+fn fallback_commands(visual: &Visual) -> Vec<Value> {
+    let (url, options) = mpv_fallback::options(visual);
+    options
+        .iter()
+        .map(|option| json!(["set_property", option.name, option.value]))
+        .chain(Some(json!(["loadfile", url, "replace"])))
+        .collect()
+}
+
+/// Observes the properties used for the events and the getters.
+fn observe(conn: &Connection) -> Result<()> {
+    for (i, name) in OBSERVED.iter().enumerate() {
+        conn.request(&json!(["observe_property", i + 1, name]))?;
+    }
+    Ok(())
+}
+
+impl PlayerBase for MpvIpc {
+    fn new(config: &Config) -> Result<MpvIpc> {
+        MpvIpc::spawn(&config.mpv_path, &config.mpv_flags)
+    }
+
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        info!("Loading {} in mpv", source.url);
+        if self.fallback.is_some() {
+            for option in mpv_fallback::reset() {
+                let value = option.value;
+                self.command(json!(["set_property", option.name, value]))?;
+            }
+            self.fallback = None;
+        }
+        for command in self.load_commands(source) {
+            self.command(command)?;
+        }
+        self.preloaded = None;
+        self.source = Some(source.clone());
+        self.properties().position = Some(source.start.unwrap_or_default());
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.command(json!(["set_property", "pause", false]))?;
+        self.properties().paused = false;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.command(json!(["set_property", "pause", true]))?;
+        self.properties().paused = true;
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.properties().paused
+    }
+
+    fn position(&self) -> Duration {
+        self.properties().position.unwrap_or_default()
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        if self.properties().idle {
+            return Ok(());
+        }
+
+        let secs = position.as_secs_f64();
+        self.command(json!(["seek", secs, "absolute"]))?;
+        self.properties().position = Some(position);
+        Ok(())
+    }
+
+    fn volume(&self) -> u32 {
+        self.properties().volume
+    }
+
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        let volume = volume.min(MAX_VOLUME);
+        self.command(json!(["set_property", "volume", volume]))?;
+        self.properties().volume = volume;
+        Ok(())
+    }
+
+    fn is_muted(&self) -> bool {
+        self.properties().muted
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.command(json!(["set_property", "mute", muted]))?;
+        self.properties().muted = muted;
+        Ok(())
+    }
+
+    fn speed(&self) -> f64 {
+        self.properties().speed
+    }
+
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        check_speed(speed)?;
+        self.command(json!(["set_property", "speed", speed]))?;
+        self.properties().speed = speed;
+        Ok(())
+    }
+
+    /// mpv is restarted if it died in the meantime.
+    fn poll_event(&mut self) -> Option<Event> {
+        if self.conn.is_closed() {
+            warn!("mpv stopped, restarting it");
+            if let Err(e) = self.restart() {
+                warn!("Couldn't restart mpv: {}", e);
+            }
+        }
+
+        self.events.try_recv().ok()
+    }
+
+    /// The video is appended to the playlist after the current one, and
+    /// mpv starts downloading it before it's loaded.
+    fn preload(&mut self, source: &VideoSource) -> Result<()> {
+        info!("Preloading {} in mpv", source.url);
+        self.command(json!(["set_property", "prefetch-playlist", true]))?;
+        self.command(json!(["playlist-clear"]))?;
+        self.command(json!(["loadfile", source.url, "append"]))?;
+        self.preloaded = Some(source.url.clone());
+        Ok(())
+    }
+
+    fn show_fallback(&mut self, visual: &Visual) -> Result<()> {
+        info!("Showing {:?} in mpv", visual);
+        for command in fallback_commands(visual) {
+            self.command(command)?;
+        }
+        self.fallback = Some(visual.clone());
+        self.source = None;
+        self.preloaded = None;
+        self.properties().position = None;
+        Ok(())
+    }
+}
+
+impl Drop for MpvIpc {
+    fn drop(&mut self) {
+        if let Some(process) = &mut self.process {
+            let _ = self.conn.request(&json!(["quit"]));
+            let _ = process.child.kill();
+            let _ = process.child.wait();
+            let _ = fs::remove_file(&self.socket);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{check_player, temp_dir};
+
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    /// Answers the commands like mpv would, saving them by connection and
+    /// notifying the changes of the observed properties. The connection is
+    /// closed when `crash` is set, like if mpv had died.
+    struct FakeMpv {
+        socket: PathBuf,
+        commands: Arc<Mutex<Vec<Vec<Value>>>>,
+        crash: Arc<AtomicBool>,
+    }
+
+    impl FakeMpv {
+        fn new(name: &str) -> FakeMpv {
+            let socket = temp_dir(&format!("mpv-ipc-{}", name)).join("socket");
+            let listener = UnixListener::bind(&socket).unwrap();
+            let commands = Arc::new(Mutex::new(Vec::new()));
+            let crash = Arc::new(AtomicBool::new(false));
+
+            let saved = Arc::clone(&commands);
+            let crashed = Arc::clone(&crash);
+            thread::spawn(move || {
+                let mut properties: HashMap<String, Value> = [
+                    ("pause", json!(false)),
+                    ("volume", json!(100.0)),
+                    ("mute", json!(false)),
+                    ("speed", json!(1.0)),
+                    ("user-agent", json!("libmpv")),
+                    ("idle-active", json!(true)),
+                ]
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect();
+
+                for stream in listener.incoming() {
+                    let stream = stream.unwrap();
+                    saved.lock().unwrap().push(Vec::new());
+                    crashed.store(false, Ordering::SeqCst);
+                    serve(stream, &mut properties, &saved, &crashed);
+                }
+            });
+
+            FakeMpv {
+                socket,
+                commands,
+                crash,
+            }
+        }
+
+        /// The commands received in each connection.
+        fn commands(&self) -> Vec<Vec<Value>> {
+            self.commands.lock().unwrap().clone()
+        }
+    }
+
+    fn serve(
+        stream: UnixStream,
+        properties: &mut HashMap<String, Value>,
+        saved: &Mutex<Vec<Vec<Value>>>,
+        crash: &AtomicBool,
+    ) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut playlist = Vec::new();
+        let mut observed = Vec::new();
+        for line in BufReader::new(stream).lines() {
+            if crash.load(Ordering::SeqCst) {
+                return;
+            }
+            let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            let command = request["command"].clone();
+            saved
+                .lock()
+                .unwrap()
+                .last_mut()
+                .unwrap()
+                .push(command.clone());
+
+            let mut data = Value::Null;
+            let mut error = "success";
+            let mut events = Vec::new();
+            let before = properties.clone();
+            let arg = |i: usize| command[i].clone();
+            match command[0].as_str().unwrap() {
+                "observe_property" => {
+                    let name = arg(2).as_str().unwrap().to_string();
+                    events.push(change(&name, properties.get(&name)));
+                    observed.push(name);
+                }
+                "get_property" => {
+                    match properties.get(arg(1).as_str().unwrap()) {
+                        Some(value) => data = value.clone(),
+                        None => error = "property unavailable",
+                    }
+                }
+                "set_property" => {
+                    let name = arg(1).as_str().unwrap().to_string();
+                    properties.insert(name, arg(2));
+                }
+                "loadfile" if arg(2) == "append" => {
+                    playlist.push(arg(1).as_str().unwrap().to_string());
+                }
+                "loadfile" => {
+                    playlist.clear();
+                    open(arg(1).as_str().unwrap(), properties, &mut events);
+                }
+                "playlist-clear" => playlist.clear(),
+                "playlist-next" if !playlist.is_empty() => {
+                    let url = playlist.remove(0);
+                    open(&url, properties, &mut events);
+                }
+                "seek" => {
+                    properties.insert("time-pos".into(), arg(1));
+                }
+                _ => {}
+            }
+
+            // The changes are notified before the events of the command.
+            let changes = observed.iter().filter_map(|name| {
+                let value = properties.get(name);
+                if value == before.get(name) {
+                    None
+                } else {
+                    Some(change(name, value))
+                }
+            });
+            let events: Vec<_> = changes.chain(events).collect();
+
+            let reply = json!({
+                "request_id": request["request_id"],
+                "error": error,
+                "data": data
+            });
+            writeln!(writer, "{}", reply).unwrap();
+            for event in events {
+                writeln!(writer, "{}", event).unwrap();
+            }
+        }
+    }
+
+    /// The data is left out if the property is unavailable.
+    fn change(name: &str, value: Option<&Value>) -> Value {
+        let mut event = json!({"event": "property-change", "name": name});
+        if let Some(value) = value {
+            event["data"] = value.clone();
+        }
+        event
+    }
+
+    /// Opens a file, which fails when its URL contains "broken".
+    fn open(
+        url: &str,
+        properties: &mut HashMap<String, Value>,
+        events: &mut Vec<Value>,
+    ) {
+        if url.contains("broken") {
+            properties.remove("time-pos");
+            events.push(json!({
+                "event": "end-file",
+                "reason": "error",
+                "file_error": "loading failed"
+            }));
+        } else {
+            let start = properties["start"].as_str().unwrap();
+            let start = start.parse::<f64>().unwrap_or(0.0);
+            properties.insert("time-pos".into(), json!(start));
+            properties.insert("idle-active".into(), json!(false));
+            events.push(json!({"event": "file-loaded"}));
+        }
+    }
+
+    /// The events are received in a separate thread, after the replies.
+    fn next_event(player: &mut MpvIpc) -> Event {
+        let start = Instant::now();
+        loop {
+            if let Some(event) = player.poll_event() {
+                return event;
+            }
+            assert!(start.elapsed() < TIMEOUT);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn semantics() {
+        let fake = FakeMpv::new("semantics");
+        let mut player = MpvIpc::connect(&fake.socket).unwrap();
+        assert_eq!(player.position(), Duration::default());
+        player.seek(Duration::from_secs(1)).unwrap();
+
+        let source = VideoSource::new("https://videos/song.mp4")
+            .with_start(Duration::from_secs(1))
+            .with_header("User-Agent", "vidify")
+            .with_header("Referer", "https://example.com");
+        check_player(&mut player, &source);
+
+        // The properties are only requested when connecting, and they're
+        // observed afterwards.
+        let commands = fake.commands();
+        let requested = commands[0]
+            .iter()
+            .filter(|command| command[0] == "get_property")
+            .count();
+        assert_eq!(requested, 6);
+        assert!(commands[0].contains(&json!([
+            "observe_property",
+            6,
+            "time-pos"
+        ])));
+        assert!(commands[0].contains(&json!(["set_property", "start", "1"])));
+        assert!(commands[0].contains(&json!([
+            "set_property",
+            "user-agent",
+            "vidify"
+        ])));
+        assert!(commands[0].contains(&json!([
+            "set_property",
+            "http-header-fields",
+            ["Referer: https://example.com"]
+        ])));
+        assert!(commands[0].contains(&json!([
+            "loadfile",
+            "https://videos/song.mp4",
+            "replace"
+        ])));
+
+        player
+            .load(&VideoSource::new("https://videos/broken.mp4"))
+            .unwrap();
+        assert_eq!(
+            next_event(&mut player),
+            Event::Error(String::from("loading failed"))
+        );
+        assert_eq!(player.poll_event(), None);
+    }
+
+    #[test]
+    fn preload() {
+        let fake = FakeMpv::new("preload");
+        let mut player = MpvIpc::connect(&fake.socket).unwrap();
+        player
+            .load(&VideoSource::new("https://videos/song.mp4"))
+            .unwrap();
+        assert_eq!(next_event(&mut player), Event::Loaded);
+
+        let next = VideoSource::new("https://videos/next.mp4");
+        player.preload(&next).unwrap();
+        let next = next.with_start(Duration::from_secs(3));
+        player.load(&next).unwrap();
+        assert_eq!(next_event(&mut player), Event::Loaded);
+        assert_eq!(player.position(), Duration::from_secs(3));
+
+        // Only the preloaded video is switched to.
+        player
+            .load(&VideoSource::new("https://videos/next.mp4"))
+            .unwrap();
+        let commands = fake.commands();
+        let commands = &commands[0];
+        let count = |command: Value| {
+            commands.iter().filter(|sent| **sent == command).count()
+        };
+        assert_eq!(count(json!(["playlist-next", "weak"])), 1);
+        assert_eq!(
+            count(json!(["loadfile", "https://videos/next.mp4", "append"])),
+            1
+        );
+        assert_eq!(
+            count(json!(["loadfile", "https://videos/next.mp4", "replace"])),
+            1
+        );
+    }
+
+    #[test]
+    fn fallback() {
+        let fake = FakeMpv::new("fallback");
+        let mut player = MpvIpc::connect(&fake.socket).unwrap();
+        let artwork = Visual::Artwork {
+            url: String::from("https://art/cover.jpg"),
+            artist: Some(String::from("Queen")),
+            title: Some(String::from("$ellout")),
+        };
+        player.show_fallback(&artwork).unwrap();
+        assert_eq!(next_event(&mut player), Event::Loaded);
+        let source = VideoSource::new("https://videos/song.mp4");
+        player.load(&source).unwrap();
+
+        let commands = fake.commands();
+        let commands = &commands[0];
+        let sent = |command: Value| {
+            commands.iter().position(|sent| *sent == command).unwrap()
+        };
+        let text =
+            sent(json!(["set_property", "osd-msg1", "Queen - $$ellout"]));
+        let filter = commands
+            .iter()
+            .position(|sent| {
+                sent[1] == "vf" && sent[2].as_str().unwrap().contains("blur")
+            })
+            .unwrap();
+        let image =
+            sent(json!(["loadfile", "https://art/cover.jpg", "replace"]));
+        assert!(text < image && filter < image);
+        // The options are reset once a video is loaded.
+        let reset = sent(json!(["set_property", "vf", ""]));
+        let video =
+            sent(json!(["loadfile", "https://videos/song.mp4", "replace"]));
+        assert!(image < reset && reset < video);
+    }
+
+    #[test]
+    fn reconnect() {
+        let fake = FakeMpv::new("reconnect");
+        let mut player = MpvIpc::connect(&fake.socket).unwrap();
+        let source = VideoSource::new("https://videos/song.mp4");
+        player.set_volume(30).unwrap();
+        player.pause().unwrap();
+        player.load(&source).unwrap();
+        player.seek(Duration::from_secs(42)).unwrap();
+        assert_eq!(player.position(), Duration::from_secs(42));
+
+        // The command is sent again once the state is restored.
+        fake.crash.store(true, Ordering::SeqCst);
+        player.set_muted(true).unwrap();
+        let commands = fake.commands();
+        assert_eq!(commands.len(), 2);
+        let mut restored = vec![
+            json!(["set_property", "volume", 30]),
+            json!(["set_property", "mute", false]),
+            json!(["set_property", "speed", 1.0]),
+            json!(["set_property", "pause", true]),
+            json!(["set_property", "start", "42"]),
+            json!(["set_property", "user-agent", "libmpv"]),
+            json!(["set_property", "http-header-fields", []]),
+            json!(["loadfile", "https://videos/song.mp4", "replace"]),
+        ];
+        for (i, name) in OBSERVED.iter().enumerate() {
+            restored.push(json!(["observe_property", i + 1, name]));
+        }
+        restored.push(json!(["set_property", "mute", true]));
+        assert_eq!(commands[1], restored);
+        assert!(player.is_muted());
+        assert!(player.is_paused());
+
+        // It's also detected while waiting for the events.
+        thread::sleep(RESTART_INTERVAL);
+        fake.crash.store(true, Ordering::SeqCst);
+        assert!(player
+            .conn
+            .request(&json!(["get_property", "pause"]))
+            .is_err());
+        let start = Instant::now();
+        while fake.commands().len() < 3 {
+            player.poll_event();
+            assert!(start.elapsed() < TIMEOUT);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(player.volume(), 30);
+    }
+
+    #[test]
+    fn failed_start() {
+        let dir = temp_dir("mpv-ipc-start");
+        let script = dir.join("mpv");
+        fs::write(
+            &script,
+            "#!/bin/sh\n\
+             echo 'Error parsing option unknown (option not found)' >&2\n\
+             exit 1\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let error = MpvIpc::spawn(script.to_str().unwrap(), "--unknown=1")
+            .err()
+            .unwrap();
+        assert!(matches!(error, Error::FailedCommand(_)));
+        assert!(error.to_string().contains("option not found"));
+        let missing = dir.join("missing").to_string_lossy().into_owned();
+        assert!(matches!(
+            MpvIpc::spawn(&missing, "").err().unwrap(),
+            Error::MissingBinary(_)
+        ));
+        assert!(matches!(
+            MpvIpc::spawn(&missing, "--title=\"Unclosed").err().unwrap(),
+            Error::InvalidConfig(_)
+        ));
+    }
+}
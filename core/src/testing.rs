@@ -0,0 +1,378 @@
+//! Utilities shared by the tests, like a minimal HTTP server to mock the web
+//! APIs used in this crate.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::player::{check_speed, Event, PlayerBase, VideoSource, MAX_VOLUME};
+use crate::video::{Candidate, VideoProvider};
+
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+
+/// A request received by the mock server.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    /// The path, including the query.
+    pub path: String,
+    /// With lowercase names.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    /// The path without the query.
+    pub fn route(&self) -> &str {
+        self.path.split('?').next().unwrap()
+    }
+
+    /// The decoded value of a parameter in the query.
+    pub fn param(&self, key: &str) -> Option<String> {
+        let url =
+            Url::parse(&format!("http://localhost{}", self.path)).ok()?;
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// The status code, the content type and the body of a response.
+pub type Response = (u16, &'static str, String);
+
+/// A web server that runs in a separate thread during the test, answering
+/// each request with the given handler. The received requests are saved so
+/// that they can be checked afterwards.
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub fn new<F>(handler: F) -> MockServer
+    where
+        F: Fn(&Request) -> Response + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let saved = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let req = match read_request(&mut stream) {
+                    Some(req) => req,
+                    None => continue,
+                };
+                let (status, content_type, body) = handler(&req);
+                saved.lock().unwrap().push(req);
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(pos) = line.find(':') {
+            headers.insert(
+                line[..pos].trim().to_lowercase(),
+                line[pos + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    let len = headers
+        .get("content-length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+/// Creates an empty directory for the files used in a test. The process ID
+/// is included so that concurrent runs don't share it.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let name = format!("vidify-test-{}-{}", process::id(), name);
+    let dir = env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A player that only keeps its state. The sources whose URL is in `broken`
+/// fail to load.
+pub struct FakePlayer {
+    pub loaded: Option<VideoSource>,
+    pub preloaded: Vec<VideoSource>,
+    pub broken: Vec<String>,
+    events: VecDeque<Event>,
+    paused: bool,
+    position: Duration,
+    volume: u32,
+    muted: bool,
+    speed: f64,
+}
+
+impl FakePlayer {
+    pub fn new(broken: &[&str]) -> FakePlayer {
+        FakePlayer {
+            loaded: None,
+            preloaded: Vec::new(),
+            broken: broken.iter().map(|url| url.to_string()).collect(),
+            events: VecDeque::new(),
+            paused: false,
+            position: Duration::default(),
+            volume: MAX_VOLUME,
+            muted: false,
+            speed: 1.0,
+        }
+    }
+}
+
+impl PlayerBase for FakePlayer {
+    fn new(_config: &Config) -> Result<Self> {
+        Err(Error::Unsupported)
+    }
+
+    fn load(&mut self, source: &VideoSource) -> Result<()> {
+        if self.broken.contains(&source.url) {
+            self.loaded = None;
+            self.position = Duration::default();
+            self.events
+                .push_back(Event::Error(String::from("Video unavailable")));
+        } else {
+            self.loaded = Some(source.clone());
+            self.position = source.start.unwrap_or_default();
+            self.events.push_back(Event::Loaded);
+        }
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<()> {
+        self.paused = false;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.paused = true;
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn position(&self) -> Duration {
+        self.position
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        if self.loaded.is_some() {
+            self.position = position;
+        }
+        Ok(())
+    }
+
+    fn volume(&self) -> u32 {
+        self.volume
+    }
+
+    fn set_volume(&mut self, volume: u32) -> Result<()> {
+        self.volume = volume.min(MAX_VOLUME);
+        Ok(())
+    }
+
+    fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.muted = muted;
+        Ok(())
+    }
+
+    fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    fn set_speed(&mut self, speed: f64) -> Result<()> {
+        check_speed(speed)?;
+        self.speed = speed;
+        Ok(())
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn preload(&mut self, source: &VideoSource) -> Result<()> {
+        self.preloaded.push(source.clone());
+        Ok(())
+    }
+}
+
+/// A provider that finds the configured candidates for any track, or a
+/// video named after each track if there are none, like `video-Song`. The
+/// searches are counted, and its state is shared by its clones.
+#[derive(Clone, Default)]
+pub struct FakeProvider {
+    pub candidates: Option<Vec<Candidate>>,
+    /// The titles of the tracks without any video, which may change while
+    /// it's used.
+    pub missing: Arc<Mutex<Vec<String>>>,
+    /// The candidates whose stream can't be obtained.
+    pub broken: Vec<String>,
+    /// Prepended to the candidate's URL to obtain its stream.
+    pub stream_prefix: String,
+    pub searches: Arc<AtomicUsize>,
+}
+
+impl FakeProvider {
+    pub fn new(candidates: Vec<Candidate>) -> FakeProvider {
+        FakeProvider {
+            candidates: Some(candidates),
+            ..FakeProvider::default()
+        }
+    }
+
+    pub fn searches(&self) -> usize {
+        self.searches.load(Ordering::SeqCst)
+    }
+}
+
+impl VideoProvider for FakeProvider {
+    fn new(_config: &Config) -> Result<Self> {
+        Err(Error::Unsupported)
+    }
+
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Candidate>> {
+        self.searches.fetch_add(1, Ordering::SeqCst);
+        let title = track.title.clone().unwrap_or_default();
+        if self.missing.lock().unwrap().contains(&title) {
+            return Ok(Vec::new());
+        }
+
+        match &self.candidates {
+            Some(candidates) => Ok(candidates.clone()),
+            None => Ok(vec![Candidate {
+                url: format!("video-{}", title),
+                title,
+                duration: track.duration,
+                ..Candidate::default()
+            }]),
+        }
+    }
+
+    fn stream_url(&self, candidate: &Candidate) -> Result<String> {
+        if self.broken.contains(&candidate.url) {
+            return Err(Error::FailedRequest(candidate.url.clone()));
+        }
+        Ok(format!("{}{}", self.stream_prefix, candidate.url))
+    }
+}
+
+/// Checks that a player follows the semantics of `PlayerBase`, with a
+/// source that starts at least a second before its end. The players that
+/// can't change the speed must stay at the normal one.
+pub fn check_player(player: &mut dyn PlayerBase, source: &VideoSource) {
+    player.set_volume(MAX_VOLUME + 50).unwrap();
+    assert_eq!(player.volume(), MAX_VOLUME);
+    player.set_volume(40).unwrap();
+    player.set_muted(true).unwrap();
+    assert!(player.is_muted());
+    assert_eq!(player.volume(), 40);
+    player.set_muted(false).unwrap();
+    assert!(!player.is_muted());
+
+    assert!(player.set_speed(0.0).is_err());
+    assert!(player.set_speed(f64::NAN).is_err());
+    match player.set_speed(1.5) {
+        Ok(()) => assert!((player.speed() - 1.5).abs() < 1e-9),
+        Err(Error::Unsupported) => {
+            assert!((player.speed() - 1.0).abs() < 1e-9)
+        }
+        Err(e) => panic!("couldn't set the speed: {}", e),
+    }
+    player.set_speed(1.0).unwrap();
+
+    player.pause().unwrap();
+    player.load(source).unwrap();
+    let start = Instant::now();
+    loop {
+        match player.poll_event() {
+            Some(Event::Loaded) => break,
+            Some(Event::Error(e)) => panic!("couldn't load the source: {}", e),
+            _ => {}
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(player.is_paused());
+    let offset = source.start.unwrap_or_default();
+    assert!(player.position() + Duration::from_millis(100) >= offset);
+
+    player.seek(offset + Duration::from_millis(500)).unwrap();
+    let start = Instant::now();
+    while player.position() < offset + Duration::from_millis(400) {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(10));
+    }
+    player.play().unwrap();
+    assert!(!player.is_paused());
+}
@@ -0,0 +1,556 @@
+//! A cache of the videos chosen for each track, saved in the data directory
+//! so that the songs played often don't have to be searched again. The
+//! stream URLs are saved as well, but they expire much sooner than the
+//! videos themselves, so they're obtained again more often. When the cache
+//! is full, the least recently used entries are removed.
+//!
+//! The videos that failed to play are remembered as well in the entry of
+//! their track, so that they're not chosen again for any track until they
+//! expire. That happens much sooner, since they're usually temporary
+//! errors.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::data::{Res, ResKind};
+use crate::error::Result;
+use crate::video::overrides::same_video;
+use crate::video::query::{normalize, track_keys};
+use crate::video::Candidate;
+
+use std::fs;
+use std::time;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// The name of the file in the data directory.
+pub const DATA_FILE: &str = "cache.json";
+
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// For how long the chosen video is kept.
+    pub ttl: time::Duration,
+    /// For how long the stream URL is kept.
+    pub stream_ttl: time::Duration,
+    /// For how long the failed videos are avoided.
+    pub failure_ttl: time::Duration,
+    /// The maximum number of tracks saved, including the ones with only
+    /// failures.
+    pub size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            ttl: time::Duration::from_secs(30 * 24 * 60 * 60),
+            stream_ttl: time::Duration::from_secs(5 * 60 * 60),
+            failure_ttl: time::Duration::from_secs(6 * 60 * 60),
+            size: 1000,
+        }
+    }
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> Limits {
+        Limits {
+            ttl: time::Duration::from_secs(
+                config.cache_ttl.saturating_mul(60 * 60),
+            ),
+            stream_ttl: time::Duration::from_secs(
+                config.cache_stream_ttl.saturating_mul(60),
+            ),
+            failure_ttl: time::Duration::from_secs(
+                config.cache_failure_ttl.saturating_mul(60 * 60),
+            ),
+            size: config.cache_size,
+        }
+    }
+}
+
+/// The times are saved in seconds since the UNIX epoch.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// All the keys of the track, so that it's found from any source.
+    keys: Vec<String>,
+    /// The chosen video, which is `None` when only its failures are known.
+    candidate: Option<Candidate>,
+    created: u64,
+    stream: Option<String>,
+    stream_created: u64,
+    used: u64,
+    #[serde(default)]
+    failures: Vec<Failure>,
+}
+
+impl Entry {
+    fn new(keys: Vec<String>) -> Entry {
+        let now = now();
+        Entry {
+            keys,
+            candidate: None,
+            created: now,
+            stream: None,
+            stream_created: 0,
+            used: now,
+            failures: Vec::new(),
+        }
+    }
+
+    /// The cached video with its stream, if it hasn't expired.
+    fn cached(&self, stream_ttl: time::Duration) -> Option<Cached> {
+        let stream = self.stream.clone().filter(|_| {
+            now().saturating_sub(self.stream_created) < stream_ttl.as_secs()
+        });
+        Some(Cached {
+            candidate: self.candidate.clone()?,
+            stream,
+        })
+    }
+}
+
+/// A video that couldn't be played, like if it was removed or it's
+/// blocked in the user's country.
+#[derive(Debug, Serialize, Deserialize)]
+struct Failure {
+    video: String,
+    created: u64,
+}
+
+/// A cached video for a track. The stream is only available if it hasn't
+/// expired.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cached {
+    pub candidate: Candidate,
+    pub stream: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Cache {
+    entries: Vec<Entry>,
+    #[serde(skip)]
+    limits: Limits,
+}
+
+impl Cache {
+    pub fn new(limits: Limits) -> Cache {
+        Cache {
+            entries: Vec::new(),
+            limits,
+        }
+    }
+
+    /// Loads the cache from a file, which may be empty.
+    pub fn load(path: &str, limits: Limits) -> Result<Cache> {
+        let data = fs::read_to_string(path)?;
+        let mut cache: Cache = if data.trim().is_empty() {
+            Cache::default()
+        } else {
+            serde_json::from_str(&data)?
+        };
+        cache.limits = limits;
+        cache.expire();
+
+        Ok(cache)
+    }
+
+    /// The cache isn't essential, so it starts empty if the file can't be
+    /// loaded, like if it's corrupt.
+    pub fn load_or_new(path: &str, limits: Limits) -> Cache {
+        Cache::load(path, limits.clone()).unwrap_or_else(|e| {
+            warn!(
+                "Couldn't load the cache, starting with an empty one: {}",
+                e
+            );
+            Cache::new(limits)
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The number of cached videos, without the tracks that only have
+    /// failures.
+    pub fn len(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.candidate.is_some())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The video cached for the track, if it hasn't expired.
+    pub fn get(&mut self, track: &TrackInfo) -> Option<Cached> {
+        self.expire();
+        let index = self.find(&track_keys(track))?;
+        let entry = &mut self.entries[index];
+        entry.used = now();
+        entry.cached(self.limits.stream_ttl)
+    }
+
+    /// The most recently used video of another track by the same artist.
+    pub fn by_artist(&mut self, track: &TrackInfo) -> Option<Cached> {
+        self.expire();
+        let artist = normalize(track)?.artist?;
+        let prefix = format!("track:{} - ", artist.to_lowercase());
+        let keys = track_keys(track);
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.candidate.is_some()
+                    && entry.keys.iter().any(|key| key.starts_with(&prefix))
+                    && !entry.keys.iter().any(|key| keys.contains(key))
+            })
+            .max_by_key(|entry| entry.used)?
+            .cached(self.limits.stream_ttl)
+    }
+
+    /// Saves the video chosen for a track, replacing the previous one. Its
+    /// failures are kept.
+    pub fn insert(&mut self, track: &TrackInfo, candidate: &Candidate) {
+        let now = now();
+        if let Some(entry) = self.entry(track) {
+            entry.candidate = Some(candidate.clone());
+            entry.created = now;
+            entry.stream = None;
+            entry.used = now;
+        }
+        self.expire();
+    }
+
+    /// Saves the stream URL of the video cached for a track.
+    pub fn set_stream(&mut self, track: &TrackInfo, stream: &str) {
+        if let Some(index) = self.find(&track_keys(track)) {
+            let entry = &mut self.entries[index];
+            entry.stream = Some(stream.to_string());
+            entry.stream_created = now();
+        }
+    }
+
+    /// Forgets the video chosen for the track, but not its failures.
+    pub fn remove(&mut self, track: &TrackInfo) {
+        let keys = track_keys(track);
+        for entry in &mut self.entries {
+            if entry.keys.iter().any(|key| keys.contains(key)) {
+                entry.candidate = None;
+                entry.stream = None;
+            }
+        }
+        self.expire();
+    }
+
+    /// Remembers that the video couldn't be played for the track, removing
+    /// it from the tracks that used it.
+    pub fn set_failed(&mut self, track: &TrackInfo, video: &str) {
+        for entry in &mut self.entries {
+            let used = entry
+                .candidate
+                .as_ref()
+                .is_some_and(|candidate| same_video(&candidate.url, video));
+            if used {
+                entry.candidate = None;
+                entry.stream = None;
+            }
+        }
+
+        let failed = self.is_failed(video);
+        if let Some(entry) = self.entry(track) {
+            entry.used = now();
+            if !failed {
+                entry.failures.push(Failure {
+                    video: video.to_string(),
+                    created: now(),
+                });
+            }
+        }
+        self.expire();
+    }
+
+    /// Whether the video failed for any track.
+    pub fn is_failed(&self, video: &str) -> bool {
+        let ttl = self.limits.failure_ttl.as_secs();
+        self.entries
+            .iter()
+            .flat_map(|entry| &entry.failures)
+            .any(|failure| {
+                now().saturating_sub(failure.created) < ttl
+                    && same_video(&failure.video, video)
+            })
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn find(&self, keys: &[String]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.keys.iter().any(|key| keys.contains(key)))
+    }
+
+    /// The entry of the track, which is created if it doesn't exist. The
+    /// entries found with different keys are merged, keeping the first one
+    /// and the failures of the rest. It's `None` if the track can't be
+    /// identified or the cache is disabled.
+    fn entry(&mut self, track: &TrackInfo) -> Option<&mut Entry> {
+        let keys = track_keys(track);
+        if keys.is_empty() || self.limits.size == 0 {
+            return None;
+        }
+
+        let mut found: Option<Entry> = None;
+        while let Some(index) = self.find(&keys) {
+            let old = self.entries.remove(index);
+            match &mut found {
+                Some(entry) => entry.failures.extend(old.failures),
+                None => found = Some(old),
+            }
+        }
+        let mut entry = found.unwrap_or_else(|| Entry::new(keys.clone()));
+        entry.keys = keys;
+        self.entries.push(entry);
+        self.entries.last_mut()
+    }
+
+    /// Removes the expired videos and failures, and the least recently
+    /// used entries if there are too many.
+    fn expire(&mut self) {
+        let now = now();
+        let ttl = self.limits.ttl.as_secs();
+        let failure_ttl = self.limits.failure_ttl.as_secs();
+        for entry in &mut self.entries {
+            if now.saturating_sub(entry.created) >= ttl {
+                entry.candidate = None;
+                entry.stream = None;
+            }
+            entry.failures.retain(|failure| {
+                now.saturating_sub(failure.created) < failure_ttl
+            });
+        }
+        self.entries.retain(|entry| {
+            entry.candidate.is_some() || !entry.failures.is_empty()
+        });
+
+        if self.entries.len() > self.limits.size {
+            self.entries
+                .sort_by_key(|entry| std::cmp::Reverse(entry.used));
+            self.entries.truncate(self.limits.size);
+        }
+    }
+}
+
+/// Removes all the cached videos.
+pub fn clear_data() -> Result<()> {
+    let path = Res::new(ResKind::Data(String::from(DATA_FILE)))?;
+    Cache::default().save(&path)
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::temp_dir;
+
+    fn track(title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Artist")),
+            title: Some(String::from(title)),
+            ..TrackInfo::default()
+        }
+    }
+
+    fn candidate(url: &str) -> Candidate {
+        Candidate {
+            url: url.to_string(),
+            title: url.to_string(),
+            duration: Some(time::Duration::from_secs(200)),
+            ..Candidate::default()
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let mut cache = Cache::new(Limits::default());
+        let mut song = track("Song");
+        assert_eq!(cache.get(&song), None);
+
+        cache.insert(&song, &candidate("video"));
+        let cached = cache.get(&song).unwrap();
+        assert_eq!(cached.candidate, candidate("video"));
+        assert_eq!(cached.stream, None);
+        cache.set_stream(&song, "stream");
+        assert_eq!(
+            cache.get(&song).unwrap().stream.as_deref(),
+            Some("stream")
+        );
+
+        // The same track, found by the normalized title.
+        song.title = Some(String::from("Song - Remastered 2011"));
+        song.isrc = Some(String::from("USABC1234567"));
+        assert!(cache.get(&song).is_some());
+        cache.insert(&song, &candidate("other"));
+        assert_eq!(cache.len(), 1);
+        let other = TrackInfo {
+            isrc: song.isrc.clone(),
+            ..TrackInfo::default()
+        };
+        assert_eq!(cache.get(&other).unwrap().candidate.url, "other");
+
+        cache.remove(&song);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&track("Unknown")), None);
+    }
+
+    #[test]
+    fn same_artist() {
+        let mut cache = Cache::new(Limits::default());
+        assert_eq!(cache.by_artist(&track("Song")), None);
+        cache.insert(&track("Song"), &candidate("song"));
+        assert_eq!(cache.by_artist(&track("Song")), None);
+
+        cache.insert(&track("Other"), &candidate("other"));
+        cache.set_stream(&track("Other"), "stream");
+        let cached = cache.by_artist(&track("Song")).unwrap();
+        assert_eq!(cached.candidate.url, "other");
+        assert_eq!(cached.stream.as_deref(), Some("stream"));
+
+        let mut stranger = track("Other");
+        stranger.artist = Some(String::from("Artist Two"));
+        assert_eq!(cache.by_artist(&stranger), None);
+        assert_eq!(cache.by_artist(&TrackInfo::default()), None);
+    }
+
+    #[test]
+    fn expiration() {
+        // The streams expire immediately, but not the videos.
+        let mut cache = Cache::new(Limits {
+            stream_ttl: time::Duration::from_secs(0),
+            ..Limits::default()
+        });
+        cache.insert(&track("Song"), &candidate("video"));
+        cache.set_stream(&track("Song"), "stream");
+        let cached = cache.get(&track("Song")).unwrap();
+        assert_eq!(cached.candidate.url, "video");
+        assert_eq!(cached.stream, None);
+
+        let mut cache = Cache::new(Limits {
+            ttl: time::Duration::from_secs(0),
+            ..Limits::default()
+        });
+        cache.insert(&track("Song"), &candidate("video"));
+        assert_eq!(cache.get(&track("Song")), None);
+
+        let mut cache = Cache::new(Limits {
+            size: 0,
+            ..Limits::default()
+        });
+        cache.insert(&track("Song"), &candidate("video"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn failures() {
+        let rick = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        let mut cache = Cache::new(Limits::default());
+        cache.insert(&track("Song"), &candidate(rick));
+        cache.insert(&track("Other"), &candidate("other"));
+        assert!(!cache.is_failed(rick));
+
+        cache.set_failed(&track("Song"), "https://youtu.be/dQw4w9WgXcQ");
+        cache.set_failed(&track("Third"), rick);
+        assert!(cache.is_failed(rick));
+        assert!(!cache.is_failed("other"));
+        assert_eq!(cache.get(&track("Song")), None);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.entries[1].failures.len(), 1);
+
+        // Choosing another video for the track keeps its failures.
+        cache.insert(&track("Song"), &candidate("another"));
+        assert!(cache.is_failed(rick));
+        cache.remove(&track("Song"));
+        assert!(cache.is_failed(rick));
+
+        // They expire sooner than the videos.
+        cache.limits.failure_ttl = time::Duration::from_secs(0);
+        assert!(!cache.is_failed(rick));
+        cache.expire();
+        assert_eq!(cache.entries.len(), 1);
+        assert!(cache.get(&track("Other")).is_some());
+    }
+
+    #[test]
+    fn least_recently_used() {
+        let mut cache = Cache::new(Limits {
+            size: 2,
+            ..Limits::default()
+        });
+        cache.insert(&track("First"), &candidate("first"));
+        cache.insert(&track("Second"), &candidate("second"));
+        // The times are in seconds, so they're modified to be different.
+        cache.entries[0].used -= 10;
+        cache.entries[1].used -= 20;
+        cache.insert(&track("Third"), &candidate("third"));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&track("First")).is_some());
+        assert!(cache.get(&track("Second")).is_none());
+        assert!(cache.get(&track("Third")).is_some());
+
+        // The tracks with only failures count as well.
+        cache.entries[0].used -= 30;
+        cache.set_failed(&track("Fourth"), "dead");
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.is_failed("dead"));
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = temp_dir("cache");
+        let path = dir.join("cache.json").to_string_lossy().into_owned();
+        fs::write(&path, "").unwrap();
+        let mut cache = Cache::load(&path, Limits::default()).unwrap();
+        assert!(cache.is_empty());
+
+        cache.insert(&track("Song"), &candidate("video"));
+        cache.set_stream(&track("Song"), "stream");
+        cache.set_failed(&track("Other"), "dead");
+        cache.save(&path).unwrap();
+        let mut loaded = Cache::load(&path, Limits::default()).unwrap();
+        assert_eq!(
+            loaded.get(&track("Song")),
+            Some(Cached {
+                candidate: candidate("video"),
+                stream: Some(String::from("stream")),
+            })
+        );
+        assert!(loaded.is_failed("dead"));
+
+        // Loading with a lower limit removes the exceeding entries.
+        let zero = Limits {
+            size: 0,
+            ..Limits::default()
+        };
+        assert!(Cache::load(&path, zero).unwrap().is_empty());
+
+        loaded.clear();
+        assert!(loaded.is_empty());
+
+        // A corrupt file is ignored.
+        fs::write(&path, "{\"entries\": [").unwrap();
+        assert!(Cache::load(&path, Limits::default()).is_err());
+        assert!(Cache::load_or_new(&path, Limits::default()).is_empty());
+    }
+}
@@ -0,0 +1,501 @@
+//! Downloads the videos played into the data directory, so that they're
+//! available offline and don't have to be streamed again. The total size
+//! is limited, removing the least recently played videos when it's
+//! exceeded. The favourite ones can be pinned so that they're never
+//! removed.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::data::{Res, ResKind};
+use crate::error::{Error, Result};
+use crate::video::query::track_keys;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time;
+
+use log::{info, warn};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// The name of the index in the data directory.
+pub const DATA_FILE: &str = "downloads.json";
+/// The name of the directory with the videos, next to the index.
+pub const DIR_NAME: &str = "downloads";
+/// How often the index is saved when only the play times changed, since
+/// they're updated every time a video is played.
+const SAVE_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// The extensions of the known containers, by their content type.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/x-matroska", "mkv"),
+];
+
+/// The times are saved in seconds since the UNIX epoch.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// All the keys of the track, so that it's found from any source.
+    keys: Vec<String>,
+    /// The URL of the video that was downloaded.
+    video: String,
+    /// The name of the file inside the directory.
+    file: String,
+    /// In bytes.
+    size: u64,
+    used: u64,
+    /// The pinned videos are never removed.
+    pinned: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Downloads {
+    entries: Vec<Entry>,
+    #[serde(skip)]
+    dir: PathBuf,
+    /// The maximum total size in bytes.
+    #[serde(skip)]
+    max_size: u64,
+    /// The videos being downloaded at the moment.
+    #[serde(skip)]
+    pending: HashSet<String>,
+    /// When the index was last saved, and whether the play times changed
+    /// since then.
+    #[serde(skip)]
+    saved: Option<time::Instant>,
+    #[serde(skip)]
+    unsaved: bool,
+}
+
+impl Downloads {
+    pub fn new(dir: PathBuf, max_size: u64) -> Downloads {
+        Downloads {
+            dir,
+            max_size,
+            ..Downloads::default()
+        }
+    }
+
+    /// Loads the index from a file, which may be empty. The entries whose
+    /// file was removed are ignored.
+    pub fn load(path: &str, dir: PathBuf, max_size: u64) -> Result<Downloads> {
+        let data = fs::read_to_string(path)?;
+        let mut downloads: Downloads = if data.trim().is_empty() {
+            Downloads::default()
+        } else {
+            serde_json::from_str(&data)?
+        };
+        downloads.dir = dir;
+        downloads.max_size = max_size;
+        let dir = &downloads.dir;
+        downloads
+            .entries
+            .retain(|entry| dir.join(&entry.file).is_file());
+        downloads.evict();
+
+        Ok(downloads)
+    }
+
+    /// Uses the index and the directory in the data directory, with the
+    /// limit in the config.
+    pub fn from_config(config: &Config) -> Result<(Downloads, String)> {
+        let path = Res::new(ResKind::Data(String::from(DATA_FILE)))?;
+        let dir = Path::new(&path.path).with_file_name(DIR_NAME);
+        fs::create_dir_all(&dir)?;
+        let max_size = config.download_size * 1024 * 1024;
+        let downloads = Downloads::load(&path, dir, max_size)?;
+
+        Ok((downloads, path.path))
+    }
+
+    pub fn save(&mut self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        self.saved = Some(time::Instant::now());
+        self.unsaved = false;
+        Ok(())
+    }
+
+    /// Whether the play times changed and the index wasn't saved recently,
+    /// so that it's not written every time a video is played.
+    pub fn should_save(&self) -> bool {
+        self.unsaved
+            && self
+                .saved
+                .is_none_or(|saved| saved.elapsed() >= SAVE_INTERVAL)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The total size of the downloaded videos, in bytes.
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// The URL of the video downloaded for the track and the path to its
+    /// file, which is marked as played.
+    pub fn get(&mut self, track: &TrackInfo) -> Option<(String, PathBuf)> {
+        let index = self.find(&track_keys(track))?;
+        let path = self.dir.join(&self.entries[index].file);
+        if !path.is_file() {
+            self.entries.remove(index);
+            return None;
+        }
+        let entry = &mut self.entries[index];
+        entry.used = now();
+        self.unsaved = true;
+
+        Some((entry.video.clone(), path))
+    }
+
+    /// The URL of the video downloaded for the track, without marking it as
+    /// played.
+    pub fn video(&self, track: &TrackInfo) -> Option<&str> {
+        let index = self.find(&track_keys(track))?;
+        Some(&self.entries[index].video)
+    }
+
+    /// Whether the video has to be downloaded for the track, in which case
+    /// it's marked as pending. Returns the path where it should be saved,
+    /// without its extension, which depends on the stream.
+    pub fn start(
+        &mut self,
+        track: &TrackInfo,
+        video: &str,
+    ) -> Option<PathBuf> {
+        let keys = track_keys(track);
+        let downloaded = self
+            .find(&keys)
+            .is_some_and(|index| self.entries[index].video == video);
+        if keys.is_empty() || downloaded || self.pending.contains(video) {
+            return None;
+        }
+
+        self.pending.insert(video.to_string());
+        Some(self.dir.join(file_stem(video)))
+    }
+
+    /// Saves a finished download, replacing the previous video of the
+    /// track. Its pin is kept.
+    pub fn insert(&mut self, track: &TrackInfo, video: &str, path: &Path) {
+        self.pending.remove(video);
+        let size = match fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return,
+        };
+        let file = match path.file_name() {
+            Some(file) => file.to_string_lossy().into_owned(),
+            None => return,
+        };
+
+        let keys = track_keys(track);
+        let mut pinned = false;
+        while let Some(index) = self.find(&keys) {
+            let old = self.entries.remove(index);
+            pinned |= old.pinned;
+            if old.file != file {
+                self.remove_file(&old.file);
+            }
+        }
+        self.entries.push(Entry {
+            keys,
+            video: video.to_string(),
+            file,
+            size,
+            used: now(),
+            pinned,
+        });
+        self.evict();
+    }
+
+    /// Forgets a failed download.
+    pub fn cancel(&mut self, video: &str) {
+        self.pending.remove(video);
+    }
+
+    /// Pins or unpins the video downloaded for the track.
+    pub fn pin(&mut self, track: &TrackInfo, pinned: bool) -> Result<()> {
+        let index =
+            self.find(&track_keys(track)).ok_or(Error::NotDownloaded)?;
+        self.entries[index].pinned = pinned;
+        if !pinned {
+            self.evict();
+        }
+
+        Ok(())
+    }
+
+    pub fn is_pinned(&self, track: &TrackInfo) -> bool {
+        self.find(&track_keys(track))
+            .is_some_and(|index| self.entries[index].pinned)
+    }
+
+    /// Removes the video downloaded for the track, and its file.
+    pub fn remove(&mut self, track: &TrackInfo) {
+        let keys = track_keys(track);
+        while let Some(index) = self.find(&keys) {
+            let entry = self.entries.remove(index);
+            self.remove_file(&entry.file);
+        }
+    }
+
+    fn find(&self, keys: &[String]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.keys.iter().any(|key| keys.contains(key)))
+    }
+
+    /// Removes the least recently played videos until the total size is
+    /// below the limit. The pinned ones are kept even if it's exceeded.
+    fn evict(&mut self) {
+        self.entries
+            .sort_by_key(|entry| std::cmp::Reverse(entry.used));
+        while self.size() > self.max_size {
+            let last = self.entries.iter().rposition(|entry| !entry.pinned);
+            match last {
+                Some(index) => {
+                    let entry = self.entries.remove(index);
+                    info!("Removing the downloaded video {}", entry.video);
+                    self.remove_file(&entry.file);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove_file(&self, file: &str) {
+        if let Err(e) = fs::remove_file(self.dir.join(file)) {
+            warn!("Couldn't remove the downloaded video {}: {}", file, e);
+        }
+    }
+}
+
+/// Downloads the stream into a file, returning its path. The extension is
+/// added to the given path from the content type of the stream. It's
+/// written to a temporary file first so that it's never left incomplete.
+pub fn fetch(url: &str, path: &Path) -> Result<PathBuf> {
+    info!("Downloading {} into {}", url, path.display());
+    let partial = path.with_extension("part");
+    // The default timeout is too short for a full video.
+    let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+    let result = client
+        .get(url)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .map_err(Error::from)
+        .and_then(|mut resp| {
+            let kind = resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|kind| kind.to_str().ok())
+                .map(String::from);
+            let mut file = fs::File::create(&partial)?;
+            resp.copy_to(&mut file)?;
+            Ok(kind)
+        });
+
+    match result {
+        Ok(kind) => {
+            let ext = extension(kind.as_deref(), url);
+            let path = path.with_extension(ext);
+            fs::rename(&partial, &path)?;
+            Ok(path)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// A name that's unique for each video.
+fn file_stem(video: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    video.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// The extension of a stream from its content type. Some servers only
+/// send a generic one, so the extension of the URL is tried as well.
+fn extension(kind: Option<&str>, url: &str) -> &'static str {
+    let by_kind = kind.and_then(|kind| {
+        let mime = kind.split(';').next()?.trim().to_lowercase();
+        EXTENSIONS.iter().find(|(known, _)| *known == mime)
+    });
+    let by_url = || {
+        let url = Url::parse(url).ok()?;
+        let ext = Path::new(url.path()).extension()?.to_string_lossy();
+        let ext = ext.to_lowercase();
+        EXTENSIONS.iter().find(|(_, known)| *known == ext)
+    };
+
+    by_kind.or_else(by_url).map_or("mp4", |(_, ext)| ext)
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{temp_dir, MockServer};
+
+    fn track(title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Artist")),
+            title: Some(String::from(title)),
+            ..TrackInfo::default()
+        }
+    }
+
+    /// Downloads a video with the given number of bytes.
+    fn download(
+        downloads: &mut Downloads,
+        server: &MockServer,
+        title: &str,
+        size: usize,
+    ) {
+        let video = format!("{}/{}/{}", server.url, size, title);
+        let path = downloads.start(&track(title), &video).unwrap();
+        let path = fetch(&video, &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), size as u64);
+        downloads.insert(&track(title), &video, &path);
+    }
+
+    fn server() -> MockServer {
+        MockServer::new(|req| {
+            let size = req.path.split('/').nth(1).unwrap();
+            match size.parse() {
+                Ok(size) => (200, "video/webm", "x".repeat(size)),
+                Err(_) => (404, "text/plain", String::new()),
+            }
+        })
+    }
+
+    #[test]
+    fn downloads() {
+        let dir = temp_dir("downloads");
+        let server = server();
+        let mut downloads = Downloads::new(dir.clone(), 1000);
+        assert_eq!(downloads.get(&track("Song")), None);
+
+        let video = format!("{}/100/Song", server.url);
+        let path = downloads.start(&track("Song"), &video).unwrap();
+        // It's not downloaded twice at the same time.
+        assert_eq!(downloads.start(&track("Song"), &video), None);
+        let path = fetch(&video, &path).unwrap();
+        assert!(path.to_string_lossy().ends_with(".webm"));
+        downloads.insert(&track("Song"), &video, &path);
+        assert_eq!(downloads.start(&track("Song"), &video), None);
+        assert_eq!(downloads.get(&track("Song")), Some((video, path.clone())));
+        assert_eq!(fs::read_to_string(&path).unwrap().len(), 100);
+
+        // A new video for the same track replaces the previous one.
+        download(&mut downloads, &server, "Song", 200);
+        assert_eq!(downloads.len(), 1);
+        assert_eq!(downloads.size(), 200);
+        assert!(!path.exists());
+
+        // The failed downloads don't leave any files behind.
+        let missing = format!("{}/missing", server.url);
+        let path = downloads.start(&track("Other"), &missing).unwrap();
+        assert!(fetch(&missing, &path).is_err());
+        downloads.cancel(&missing);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(downloads.start(&track("Other"), &missing).is_some());
+
+        downloads.remove(&track("Song"));
+        assert!(downloads.is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn eviction() {
+        let dir = temp_dir("downloads-eviction");
+        let server = server();
+        let mut downloads = Downloads::new(dir, 1000);
+        download(&mut downloads, &server, "First", 400);
+        download(&mut downloads, &server, "Second", 400);
+        // The times are in seconds, so they're modified to be different.
+        downloads.entries[0].used -= 20;
+        downloads.entries[1].used -= 10;
+        downloads.pin(&track("First"), true).unwrap();
+        assert!(downloads.is_pinned(&track("First")));
+
+        // The least recently played video that isn't pinned is removed.
+        download(&mut downloads, &server, "Third", 400);
+        assert_eq!(downloads.size(), 800);
+        assert!(downloads.get(&track("First")).is_some());
+        assert!(downloads.get(&track("Second")).is_none());
+        assert!(downloads.get(&track("Third")).is_some());
+
+        // Even if the pinned ones exceed the limit.
+        downloads.pin(&track("Third"), true).unwrap();
+        download(&mut downloads, &server, "Fourth", 400);
+        assert_eq!(downloads.len(), 2);
+        assert!(downloads.get(&track("Fourth")).is_none());
+        assert!(matches!(
+            downloads.pin(&track("Unknown"), true),
+            Err(Error::NotDownloaded)
+        ));
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(extension(Some("video/webm"), "https://a/v.mp4"), "webm");
+        assert_eq!(
+            extension(Some("video/x-matroska; codecs=vp9"), "https://a/v"),
+            "mkv"
+        );
+        let generic = Some("application/octet-stream");
+        assert_eq!(extension(generic, "https://a/v.WEBM?x=1"), "webm");
+        assert_eq!(extension(generic, "https://a/videoplayback"), "mp4");
+        assert_eq!(extension(None, "not a url"), "mp4");
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = temp_dir("downloads-persistence");
+        let videos = dir.join(DIR_NAME);
+        fs::create_dir(&videos).unwrap();
+        let path = dir.join(DATA_FILE).to_string_lossy().into_owned();
+        fs::write(&path, "").unwrap();
+        let server = server();
+
+        let mut downloads =
+            Downloads::load(&path, videos.clone(), 1000).unwrap();
+        assert!(downloads.is_empty());
+        download(&mut downloads, &server, "First", 100);
+        download(&mut downloads, &server, "Second", 100);
+        downloads.pin(&track("Second"), true).unwrap();
+        downloads.save(&path).unwrap();
+
+        // The videos removed by hand are forgotten.
+        let (_, first) = downloads.get(&track("First")).unwrap();
+        fs::remove_file(first).unwrap();
+        let mut loaded = Downloads::load(&path, videos, 1000).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded.get(&track("Second")).is_some());
+        assert!(loaded.is_pinned(&track("Second")));
+
+        // The play times are saved, but not every time.
+        assert!(loaded.should_save());
+        loaded.save(&path).unwrap();
+        assert!(loaded.get(&track("Second")).is_some());
+        assert!(!loaded.should_save());
+    }
+}
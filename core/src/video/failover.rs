@@ -0,0 +1,190 @@
+//! Some videos can only be known to be unavailable once the player tries to
+//! load them, like when they're geo-blocked, removed or age-restricted.
+//! When that happens, the failure is reported to the resolver and the next
+//! candidate is tried, up to a maximum number of attempts per track.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::player::Event;
+use crate::video::query::track_keys;
+use crate::video::resolver::{Resolved, Resolver};
+
+use log::{info, warn};
+
+pub struct Failover {
+    max_attempts: u32,
+    /// The keys of the track whose attempts are being counted.
+    track: Vec<String>,
+    attempts: u32,
+}
+
+impl Failover {
+    pub fn new(max_attempts: u32) -> Failover {
+        Failover {
+            max_attempts,
+            track: Vec::new(),
+            attempts: 0,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Failover {
+        Failover::new(config.video_attempts)
+    }
+
+    /// Reports that the video of the track couldn't be played, returning
+    /// the next one to try. It's `None` once the attempts are exhausted or
+    /// there are no more candidates, in which case a fallback should be
+    /// shown instead.
+    pub fn failed(
+        &mut self,
+        resolver: &Resolver,
+        track: &TrackInfo,
+        resolved: &Resolved,
+    ) -> Option<Resolved> {
+        let keys = track_keys(track);
+        if keys != self.track {
+            self.track = keys;
+            self.attempts = 1;
+        }
+        resolver.report_failure(track, resolved);
+
+        if self.attempts >= self.max_attempts {
+            info!("Giving up after {} failed videos", self.attempts);
+            return None;
+        }
+        self.attempts += 1;
+
+        match resolver.resolve(track) {
+            Ok(next) => next,
+            Err(e) => {
+                warn!("Couldn't look for another video: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Checks if an event of the player is an error with the current
+    /// video, returning the next one to try. Once it's `None`, the player
+    /// should be left as is if nothing failed, and a fallback should be
+    /// shown otherwise. The attempts are counted again once a video is
+    /// loaded.
+    pub fn check(
+        &mut self,
+        event: &Event,
+        resolver: &Resolver,
+        track: &TrackInfo,
+        resolved: &Resolved,
+    ) -> Option<Resolved> {
+        let error = match event {
+            Event::Error(error) => error,
+            Event::Loaded => {
+                self.track.clear();
+                self.attempts = 0;
+                return None;
+            }
+            _ => return None,
+        };
+        warn!("The player couldn't load {}: {}", resolved.url, error);
+        self.failed(resolver, track, resolved)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::player::{PlayerBase, VideoSource};
+    use crate::testing::{FakePlayer, FakeProvider};
+    use crate::video::score::Scorer;
+    use crate::video::Candidate;
+
+    use std::time;
+
+    /// Returns the same ranked candidates for any track.
+    fn resolver(urls: &[&str]) -> Resolver {
+        let candidates = urls
+            .iter()
+            .map(|url| Candidate {
+                url: url.to_string(),
+                title: url.to_string(),
+                duration: Some(time::Duration::from_secs(200)),
+                ..Candidate::default()
+            })
+            .collect();
+        let provider = FakeProvider::new(candidates);
+        Resolver::new(Box::new(provider), Scorer::default())
+    }
+
+    fn track(title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Artist")),
+            title: Some(title.to_string()),
+            duration: Some(time::Duration::from_secs(200)),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn next_candidate() {
+        let resolver = resolver(&["blocked", "removed", "good"]);
+        let mut player = FakePlayer::new(&["blocked", "removed"]);
+        let mut failover = Failover::new(3);
+        let song = track("Song");
+
+        let mut current = resolver.resolve(&song).unwrap().unwrap();
+        player.load(&VideoSource::new(&current.url)).unwrap();
+        while let Some(event) = player.poll_event() {
+            if let Some(next) =
+                failover.check(&event, &resolver, &song, &current)
+            {
+                current = next;
+                player.load(&VideoSource::new(&current.url)).unwrap();
+            }
+        }
+        assert_eq!(current.url, "good");
+        assert_eq!(player.loaded, Some(VideoSource::new("good")));
+
+        // The dead videos aren't chosen again, even for other tracks.
+        let other = resolver.resolve(&track("Other")).unwrap().unwrap();
+        assert_eq!(other.url, "good");
+        assert_eq!(resolver.resolve(&song).unwrap(), Some(current));
+    }
+
+    #[test]
+    fn max_attempts() {
+        let resolver = resolver(&["first", "second", "third"]);
+        let mut failover = Failover::new(2);
+        let song = track("Song");
+
+        let first = resolver.resolve(&song).unwrap().unwrap();
+        let second = failover.failed(&resolver, &song, &first).unwrap();
+        assert_eq!(second.url, "second");
+        assert_eq!(failover.failed(&resolver, &song, &second), None);
+
+        // The attempts are counted again for a new track.
+        let other = track("Other");
+        let third = resolver.resolve(&other).unwrap().unwrap();
+        assert_eq!(third.url, "third");
+        assert_eq!(failover.failed(&resolver, &other, &third), None);
+        assert_eq!(resolver.resolve(&other).unwrap(), None);
+    }
+
+    #[test]
+    fn loaded_resets() {
+        let resolver = resolver(&["first", "second", "third"]);
+        let mut failover = Failover::new(2);
+        let song = track("Song");
+
+        let first = resolver.resolve(&song).unwrap().unwrap();
+        let second = failover.failed(&resolver, &song, &first).unwrap();
+        assert_eq!(
+            failover.check(&Event::Loaded, &resolver, &song, &second),
+            None
+        );
+
+        // The video that played fails later on, which is a new attempt.
+        let error = Event::Error(String::from("connection lost"));
+        let third = failover.check(&error, &resolver, &song, &second);
+        assert_eq!(third.unwrap().url, "third");
+    }
+}
@@ -0,0 +1,309 @@
+//! What's shown when no music video is found for a track, or when none of
+//! them is good enough. The modes are tried in the configured order until
+//! one of them is available:
+//!
+//! * `Artwork`: the album artwork over a blurred copy of itself, with the
+//!   artist and title of the track.
+//! * `Artist`: a video already found for another song by the same artist,
+//!   played in a loop.
+//! * `Screensaver`: a clip from a local directory, played in a loop.
+//!
+//! The video is looked for again periodically while the fallback is shown,
+//! since it may become available later, like when the connection is back
+//! or when the user pins one.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::data::find_files;
+use crate::error::{Error, Result};
+use crate::video::library::EXTENSIONS;
+use crate::video::query::track_keys;
+use crate::video::resolver::{Resolved, Resolver};
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::str::FromStr;
+use std::time;
+
+use log::{info, warn};
+use reqwest::Url;
+use strum_macros::{Display, EnumString};
+
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+pub enum Mode {
+    Artwork,
+    Artist,
+    Screensaver,
+}
+
+/// What the player should show instead of the music video.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Visual {
+    /// The album artwork, to be shown over a blurred copy of itself with
+    /// the artist and title of the track.
+    Artwork {
+        url: String,
+        artist: Option<String>,
+        title: Option<String>,
+    },
+    /// A video to be played in a loop and muted, since it's not the
+    /// track's.
+    Loop(String),
+}
+
+pub struct Fallback {
+    modes: Vec<Mode>,
+    /// The URLs of the clips used as a screensaver.
+    clips: Vec<String>,
+}
+
+impl Fallback {
+    /// Looks for the clips inside the directory, if any.
+    pub fn new(
+        modes: Vec<Mode>,
+        clips_dir: Option<&Path>,
+    ) -> Result<Fallback> {
+        let mut clips = Vec::new();
+        if let Some(dir) = clips_dir {
+            let mut files = Vec::new();
+            find_files(dir, EXTENSIONS, &mut files)?;
+            clips = files
+                .into_iter()
+                .filter_map(|(path, _)| Url::from_file_path(path).ok())
+                .map(|url| url.to_string())
+                .collect();
+            // The order of the files in a directory is arbitrary.
+            clips.sort();
+            info!("Found {} clips for the screensaver", clips.len());
+        }
+
+        Ok(Fallback { modes, clips })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Fallback> {
+        Fallback::new(
+            parse_modes(&config.fallback)?,
+            config.fallback_clips.as_deref().map(Path::new),
+        )
+    }
+
+    /// The first visual available for the track, trying the modes in
+    /// order.
+    pub fn visual(
+        &self,
+        track: &TrackInfo,
+        resolver: &Resolver,
+    ) -> Option<Visual> {
+        self.modes.iter().find_map(|mode| match mode {
+            Mode::Artwork => {
+                track.art_url.clone().map(|url| Visual::Artwork {
+                    url,
+                    artist: track.artist.clone(),
+                    title: track.title.clone(),
+                })
+            }
+            Mode::Artist => resolver
+                .artist_video(track)
+                .map(|resolved| Visual::Loop(resolved.url)),
+            Mode::Screensaver => self.clip(track).map(Visual::Loop),
+        })
+    }
+
+    /// The same clip is always used for a track, but they're different
+    /// between tracks.
+    fn clip(&self, track: &TrackInfo) -> Option<String> {
+        if self.clips.is_empty() {
+            return None;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        track_keys(track).hash(&mut hasher);
+        let index = hasher.finish() as usize % self.clips.len();
+        Some(self.clips[index].clone())
+    }
+}
+
+/// Parses a list of modes separated by spaces. The unknown ones are
+/// reported as a config error.
+pub fn parse_modes(modes: &str) -> Result<Vec<Mode>> {
+    modes
+        .split_whitespace()
+        .map(|mode| {
+            Mode::from_str(mode).map_err(|_| {
+                Error::InvalidConfig(format!(
+                    "unknown fallback mode '{}'",
+                    mode
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Looks for the video of the track being shown with a fallback again once
+/// in a while.
+pub struct Retry {
+    track: Option<TrackInfo>,
+    last: time::Instant,
+    interval: time::Duration,
+}
+
+impl Retry {
+    pub fn new(interval: time::Duration) -> Retry {
+        Retry {
+            track: None,
+            last: time::Instant::now(),
+            interval,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Retry {
+        Retry::new(time::Duration::from_secs(config.fallback_retry))
+    }
+
+    /// Starts retrying the track, replacing the previous one.
+    pub fn start(&mut self, track: &TrackInfo) {
+        self.track = Some(track.clone());
+        self.last = time::Instant::now();
+    }
+
+    /// Stops retrying, like when the track changes.
+    pub fn stop(&mut self) {
+        self.track = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.track.is_some()
+    }
+
+    /// Looks for the video again if it's time to, returning it once it's
+    /// found. The player should then switch to it.
+    pub fn poll(&mut self, resolver: &Resolver) -> Option<Resolved> {
+        if self.last.elapsed() < self.interval {
+            return None;
+        }
+        self.last = time::Instant::now();
+
+        let track = self.track.as_ref()?;
+        match resolver.resolve(track) {
+            Ok(Some(resolved)) => {
+                info!("Found the video after the fallback: {}", resolved.url);
+                self.track = None;
+                Some(resolved)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Couldn't look for the video again: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{temp_dir, FakeProvider};
+    use crate::video::score::Scorer;
+
+    use std::fs;
+
+    fn track(title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Artist")),
+            title: Some(title.to_string()),
+            art_url: Some(String::from("https://art/cover.jpg")),
+            ..TrackInfo::default()
+        }
+    }
+
+    /// The video of `Song` isn't found until it's removed from the missing
+    /// ones.
+    fn resolver(provider: &FakeProvider) -> Resolver {
+        provider.missing.lock().unwrap().push(String::from("Song"));
+        Resolver::new(Box::new(provider.clone()), Scorer::default())
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(
+            parse_modes(" Artist  Artwork").unwrap(),
+            [Mode::Artist, Mode::Artwork]
+        );
+        assert!(parse_modes("").unwrap().is_empty());
+        assert!(matches!(
+            parse_modes("Artist Unknown"),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn visuals() {
+        let dir = temp_dir("fallback");
+        fs::write(dir.join("waves.mp4"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        let resolver = resolver(&FakeProvider::default());
+        let mut song = track("Song");
+
+        let fallback = Fallback::new(
+            parse_modes("Artist Artwork Screensaver").unwrap(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            fallback.visual(&song, &resolver),
+            Some(Visual::Artwork {
+                url: String::from("https://art/cover.jpg"),
+                artist: Some(String::from("Artist")),
+                title: Some(String::from("Song")),
+            })
+        );
+
+        // Once a video by the same artist is found, it's used instead.
+        assert!(resolver.resolve(&track("Found")).unwrap().is_some());
+        assert_eq!(
+            fallback.visual(&song, &resolver),
+            Some(Visual::Loop(String::from("video-Found")))
+        );
+
+        // The screensaver is only used without artwork.
+        let fallback = Fallback::new(
+            parse_modes("Artwork Screensaver").unwrap(),
+            Some(dir.as_path()),
+        )
+        .unwrap();
+        song.art_url = None;
+        let clip = Url::from_file_path(dir.join("waves.mp4")).unwrap();
+        assert_eq!(
+            fallback.visual(&song, &resolver),
+            Some(Visual::Loop(clip.to_string()))
+        );
+
+        let fallback = Fallback::new(vec![Mode::Artwork], None).unwrap();
+        assert_eq!(fallback.visual(&song, &resolver), None);
+    }
+
+    #[test]
+    fn retry() {
+        let provider = FakeProvider::default();
+        let resolver = resolver(&provider);
+        let mut retry = Retry::new(time::Duration::from_secs(0));
+        assert_eq!(retry.poll(&resolver), None);
+
+        retry.start(&track("Song"));
+        assert!(retry.is_active());
+        assert_eq!(retry.poll(&resolver), None);
+        provider.missing.lock().unwrap().clear();
+        let resolved = retry.poll(&resolver).unwrap();
+        assert_eq!(resolved.url, "video-Song");
+        assert!(!retry.is_active());
+
+        // It's not done more often than the interval.
+        let mut retry = Retry::new(time::Duration::from_secs(3600));
+        retry.start(&track("Other"));
+        assert_eq!(retry.poll(&resolver), None);
+        retry.stop();
+        assert!(!retry.is_active());
+    }
+}
@@ -0,0 +1,91 @@
+//! Shared functionality for the providers that use public instances of a
+//! web API, like Invidious or Piped. These instances are often overloaded or
+//! down, so the next one is tried whenever a request fails.
+
+use crate::error::{Error, Result};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time;
+
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+
+const TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+pub struct Instances {
+    urls: Vec<String>,
+    /// The instance that worked last time, which will be tried first.
+    current: AtomicUsize,
+    client: Client,
+}
+
+impl Instances {
+    /// Initializes the instances from a list of URLs separated by spaces.
+    pub fn new(urls: &str) -> Result<Instances> {
+        let urls: Vec<String> = urls
+            .split_whitespace()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .collect();
+        if urls.is_empty() {
+            return Err(Error::FailedConnection(String::from(
+                "no instances configured",
+            )));
+        }
+
+        Ok(Instances {
+            urls,
+            current: AtomicUsize::new(0),
+            client: Client::builder().timeout(TIMEOUT).build()?,
+        })
+    }
+
+    /// Performs a GET request to the given path, rotating to the next
+    /// instance if it fails. The URL of the instance that answered is also
+    /// returned.
+    pub fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<(T, &str)> {
+        let start = self.current.load(Ordering::Relaxed);
+        let mut error = None;
+        for i in 0..self.urls.len() {
+            let index = (start + i) % self.urls.len();
+            let base = &self.urls[index];
+            match self.request(base, path, query) {
+                Ok(data) => {
+                    self.current.store(index, Ordering::Relaxed);
+                    return Ok((data, base));
+                }
+                Err(e) => {
+                    warn!("Request to {} failed: {}", base, e);
+                    error = Some(e);
+                }
+            }
+        }
+
+        // There's always at least one instance, so the error will be set.
+        Err(error.unwrap())
+    }
+
+    fn request<T: DeserializeOwned>(
+        &self,
+        base: &str,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T> {
+        let url = format!("{}{}", base, path);
+        info!("Requesting {}", url);
+        let res = self.client.get(&url).query(query).send()?;
+        if !res.status().is_success() {
+            return Err(Error::FailedRequest(format!(
+                "{} returned {}",
+                url,
+                res.status()
+            )));
+        }
+
+        Ok(res.json()?)
+    }
+}
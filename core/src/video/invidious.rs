@@ -0,0 +1,276 @@
+//! A provider that uses the API of an Invidious instance, an alternative
+//! front-end to YouTube, so that no external binaries are required.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::video::instances::Instances;
+use crate::video::query::QueryTemplate;
+use crate::video::{youtube_id, youtube_url, Candidate, VideoProvider};
+
+use std::time;
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchResult {
+    #[serde(rename = "type")]
+    kind: String,
+    title: Option<String>,
+    video_id: Option<String>,
+    author: Option<String>,
+    length_seconds: Option<u64>,
+    #[serde(default)]
+    video_thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Deserialize)]
+struct Thumbnail {
+    quality: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Video {
+    #[serde(default)]
+    format_streams: Vec<FormatStream>,
+}
+
+/// The streams that include both video and audio.
+#[derive(Deserialize)]
+struct FormatStream {
+    url: String,
+    /// For example, `720p`.
+    resolution: Option<String>,
+}
+
+pub struct Invidious {
+    instances: Instances,
+    results: usize,
+    query: QueryTemplate,
+}
+
+impl VideoProvider for Invidious {
+    fn new(config: &Config) -> Result<Self> {
+        Ok(Invidious {
+            instances: Instances::new(&config.invidious_instances)?,
+            results: config.video_results as usize,
+            query: QueryTemplate::from_config(config),
+        })
+    }
+
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Candidate>> {
+        let query = self.query.render(track)?;
+        let (results, base): (Vec<SearchResult>, _) = self
+            .instances
+            .get("/api/v1/search", &[("q", &query), ("type", "video")])?;
+
+        let candidates = results
+            .into_iter()
+            .filter(|res| res.kind == "video")
+            .filter_map(|res| {
+                // The thumbnails may be relative to the instance.
+                let thumbnail = res
+                    .video_thumbnails
+                    .iter()
+                    .find(|thumb| thumb.quality == "high")
+                    .or_else(|| res.video_thumbnails.first())
+                    .map(|thumb| {
+                        if thumb.url.starts_with('/') {
+                            format!("{}{}", base, thumb.url)
+                        } else {
+                            thumb.url.clone()
+                        }
+                    });
+
+                Some(Candidate {
+                    url: youtube_url(&res.video_id?),
+                    title: res.title?,
+                    channel: res.author,
+                    duration: res
+                        .length_seconds
+                        .map(time::Duration::from_secs),
+                    thumbnail,
+                })
+            })
+            .take(self.results)
+            .collect();
+
+        Ok(candidates)
+    }
+
+    /// The stream with the highest resolution that includes audio is used.
+    fn stream_url(&self, candidate: &Candidate) -> Result<String> {
+        let id = youtube_id(&candidate.url).ok_or_else(|| {
+            Error::FailedRequest(format!(
+                "{} isn't a YouTube video",
+                candidate.url
+            ))
+        })?;
+        let (video, _): (Video, _) =
+            self.instances.get(&format!("/api/v1/videos/{}", id), &[])?;
+
+        video
+            .format_streams
+            .into_iter()
+            .max_by_key(|stream| resolution(stream.resolution.as_deref()))
+            .map(|stream| stream.url)
+            .ok_or_else(|| {
+                Error::FailedRequest(format!(
+                    "no streams available for {}",
+                    id
+                ))
+            })
+    }
+}
+
+/// Parses resolutions like `720p` into their height. The unknown ones are
+/// the least preferred.
+fn resolution(res: Option<&str>) -> u32 {
+    res.and_then(|res| res.trim_end_matches('p').parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::MockServer;
+
+    const SEARCH: &str = r#"[
+        {"type": "channel", "author": "Rick Astley"},
+        {
+            "type": "video",
+            "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)",
+            "videoId": "dQw4w9WgXcQ",
+            "author": "Rick Astley",
+            "lengthSeconds": 212,
+            "videoThumbnails": [
+                {"quality": "maxres", "url": "/vi/dQw4w9WgXcQ/maxres.jpg"},
+                {"quality": "high", "url": "/vi/dQw4w9WgXcQ/hqdefault.jpg"}
+            ]
+        },
+        {
+            "type": "video",
+            "title": "Never Gonna Give You Up (Lyrics)",
+            "videoId": "abc",
+            "author": "Lyrics",
+            "lengthSeconds": 213,
+            "videoThumbnails": []
+        },
+        {"type": "video", "title": "Broken result"}
+    ]"#;
+
+    const VIDEO: &str = r#"{
+        "title": "Rick Astley - Never Gonna Give You Up",
+        "formatStreams": [
+            {"url": "https://instance/360.mp4", "resolution": "360p"},
+            {"url": "https://instance/720.mp4", "resolution": "720p"},
+            {"url": "https://instance/unknown.mp4"}
+        ]
+    }"#;
+
+    fn instance() -> MockServer {
+        MockServer::new(|req| match req.route() {
+            "/api/v1/search" => (200, "application/json", SEARCH.into()),
+            "/api/v1/videos/dQw4w9WgXcQ" => {
+                (200, "application/json", VIDEO.into())
+            }
+            _ => (404, "text/plain", "Not found".into()),
+        })
+    }
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Rick Astley")),
+            title: Some(String::from("Never Gonna Give You Up")),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn search() {
+        let server = instance();
+        let provider = Invidious {
+            instances: Instances::new(&server.url).unwrap(),
+            results: 5,
+            query: QueryTemplate::default(),
+        };
+
+        let candidates = provider.search(&track()).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[0],
+            Candidate {
+                url: String::from(
+                    "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+                ),
+                title: String::from(
+                    "Rick Astley - Never Gonna Give You Up (Official Music \
+                     Video)"
+                ),
+                channel: Some(String::from("Rick Astley")),
+                duration: Some(time::Duration::from_secs(212)),
+                thumbnail: Some(format!(
+                    "{}/vi/dQw4w9WgXcQ/hqdefault.jpg",
+                    server.url
+                )),
+            }
+        );
+        assert_eq!(candidates[1].thumbnail, None);
+
+        let req = &server.requests()[0];
+        assert_eq!(req.method, "GET");
+        assert_eq!(
+            req.param("q").as_deref(),
+            Some("Rick Astley - Never Gonna Give You Up")
+        );
+        assert_eq!(req.param("type").as_deref(), Some("video"));
+
+        assert_eq!(
+            provider.stream_url(&candidates[0]).unwrap(),
+            "https://instance/720.mp4"
+        );
+        assert!(provider.stream_url(&candidates[1]).is_err());
+    }
+
+    #[test]
+    fn rotation() {
+        let down = MockServer::new(|_| (502, "text/plain", "Down".into()));
+        let up = instance();
+        let provider = Invidious {
+            instances: Instances::new(&format!("{} {}/", down.url, up.url))
+                .unwrap(),
+            results: 1,
+            query: QueryTemplate::default(),
+        };
+
+        assert_eq!(provider.search(&track()).unwrap().len(), 1);
+        assert_eq!(provider.search(&track()).unwrap().len(), 1);
+        // The working instance is remembered after the first failure.
+        assert_eq!(down.requests().len(), 1);
+        assert_eq!(up.requests().len(), 2);
+    }
+
+    #[test]
+    fn all_instances_down() {
+        let down = MockServer::new(|_| (500, "text/plain", "Down".into()));
+        let invalid = MockServer::new(|_| (200, "text/html", "<p>".into()));
+        let provider = Invidious {
+            instances: Instances::new(&format!(
+                "{} {}",
+                down.url, invalid.url
+            ))
+            .unwrap(),
+            results: 1,
+            query: QueryTemplate::default(),
+        };
+
+        assert!(provider.search(&track()).is_err());
+        assert_eq!(down.requests().len(), 1);
+        assert_eq!(invalid.requests().len(), 1);
+        assert!(Instances::new(" ").is_err());
+    }
+}
@@ -0,0 +1,511 @@
+//! A provider for the music videos already downloaded by the user. The
+//! configured directories are indexed with the artist and title of each
+//! video, obtained from their embedded tags with `ffprobe` or from file names
+//! like `Artist - Title.mkv`. The index is saved in the data directory, and
+//! only the new or modified files are read again.
+//!
+//! If the track is a local file too, like the ones reported by MPRIS with
+//! `xesam:url`, a video next to it with the same name is preferred.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::data::{find_files, parse_file_name, Res, ResKind};
+use crate::error::{Error, Result};
+use crate::video::query::{self, clean_artist, clean_title};
+use crate::video::{run_command, Candidate, VideoProvider};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time;
+
+use log::{info, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// The extensions of the video files.
+pub const EXTENSIONS: &[&str] = &[
+    "mkv", "mp4", "m4v", "webm", "avi", "mov", "wmv", "flv", "mpg",
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Video {
+    path: String,
+    artist: Option<String>,
+    title: String,
+    /// In seconds.
+    duration: Option<f64>,
+    /// Last modification of the file, in seconds since the UNIX epoch.
+    modified: u64,
+}
+
+impl Video {
+    fn candidate(&self) -> Option<Candidate> {
+        to_candidate(Path::new(&self.path), self.duration)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    videos: Vec<Video>,
+}
+
+impl Index {
+    /// Loads the index from a file, which may be empty.
+    fn load(path: &str) -> Result<Index> {
+        let data = fs::read_to_string(path)?;
+        if data.trim().is_empty() {
+            return Ok(Index::default());
+        }
+
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Updates the index with the videos inside the given directories,
+    /// recursively. Returns whether the index has changed.
+    fn update(&mut self, dirs: &[PathBuf], ffprobe: &str) -> Result<bool> {
+        let mut files = Vec::new();
+        for dir in dirs {
+            find_files(dir, EXTENSIONS, &mut files)?;
+        }
+
+        // Removing the videos that no longer exist or that were modified.
+        let before = self.videos.len();
+        let current: HashMap<String, u64> = files.into_iter().collect();
+        self.videos
+            .retain(|video| current.get(&video.path) == Some(&video.modified));
+        let mut changed = before != self.videos.len();
+
+        // ffprobe is optional, so it's only tried until it's known to be
+        // missing.
+        let mut use_ffprobe = true;
+        for (path, modified) in current {
+            if self.videos.iter().any(|video| video.path == path) {
+                continue;
+            }
+
+            info!("Indexing {}", path);
+            let probe = if use_ffprobe {
+                match probe(ffprobe, &path) {
+                    Ok(probe) => probe,
+                    Err(Error::MissingBinary(_)) => {
+                        warn!("ffprobe not found, using only the file names");
+                        use_ffprobe = false;
+                        Probe::default()
+                    }
+                    Err(e) => {
+                        warn!("Couldn't read the tags of {}: {}", path, e);
+                        Probe::default()
+                    }
+                }
+            } else {
+                Probe::default()
+            };
+
+            // Each tag falls back to the file name separately.
+            let (artist, title) = parse_file_name(Path::new(&path));
+            let artist = probe.artist.or(artist);
+            let title = probe.title.unwrap_or(title);
+            self.videos.push(Video {
+                path,
+                artist,
+                title,
+                duration: probe.duration,
+                modified,
+            });
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// The videos whose title matches the track's. The ones that also match
+    /// the artist come first, and those with a different artist are
+    /// discarded.
+    fn find(&self, track: &TrackInfo) -> Vec<&Video> {
+        let track = match query::normalize(track) {
+            Some(track) => track,
+            None => return Vec::new(),
+        };
+        let title = simplify(&track.title);
+        let artist = track.artist.as_deref().map(simplify);
+
+        let mut found: Vec<(bool, &Video)> = self
+            .videos
+            .iter()
+            .filter(|video| simplify(&clean_title(&video.title).0) == title)
+            .filter_map(|video| {
+                let other = video
+                    .artist
+                    .as_deref()
+                    .map(|other| simplify(&clean_artist(other).0));
+                match (&artist, other) {
+                    (Some(a), Some(b)) if *a == b => Some((true, video)),
+                    (Some(_), Some(_)) => None,
+                    _ => Some((false, video)),
+                }
+            })
+            .collect();
+        found.sort_by_key(|(same_artist, _)| !same_artist);
+
+        found.into_iter().map(|(_, video)| video).collect()
+    }
+}
+
+/// The metadata obtained with `ffprobe`.
+#[derive(Default)]
+struct Probe {
+    artist: Option<String>,
+    title: Option<String>,
+    duration: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    format: ProbeFormat,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    /// In seconds, as a string.
+    duration: Option<String>,
+    /// The case of the tag names depends on the container.
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+fn probe(ffprobe: &str, path: &str) -> Result<Probe> {
+    let output = run_command(
+        ffprobe,
+        &["-v", "quiet", "-print_format", "json", "-show_format", path],
+    )?;
+    let format = serde_json::from_str::<ProbeOutput>(&output)?.format;
+    let tag = |name: &str| {
+        format
+            .tags
+            .iter()
+            .find(|(key, value)| {
+                key.eq_ignore_ascii_case(name) && !value.trim().is_empty()
+            })
+            .map(|(_, value)| value.trim().to_string())
+    };
+
+    Ok(Probe {
+        artist: tag("artist"),
+        title: tag("title"),
+        duration: format.duration.as_deref().and_then(|d| d.parse().ok()),
+    })
+}
+
+/// Simplifies a title so that the differences in punctuation, case or
+/// anything between brackets are ignored.
+fn simplify(text: &str) -> String {
+    let mut normalized = String::new();
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth > 0 => {}
+            c if c.is_alphanumeric() => normalized.extend(c.to_lowercase()),
+            _ => normalized.push(' '),
+        }
+    }
+
+    normalized.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn to_candidate(path: &Path, duration: Option<f64>) -> Option<Candidate> {
+    Some(Candidate {
+        url: Url::from_file_path(path).ok()?.to_string(),
+        title: path.file_stem()?.to_string_lossy().into_owned(),
+        channel: None,
+        duration: duration.map(time::Duration::from_secs_f64),
+        thumbnail: None,
+    })
+}
+
+/// A video with the same name as the track's local file, if any.
+fn sibling(track: &TrackInfo) -> Option<PathBuf> {
+    let url = Url::parse(track.url.as_ref()?).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    let path = url.to_file_path().ok()?;
+
+    EXTENSIONS
+        .iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|video| video.is_file())
+}
+
+pub struct Library {
+    dirs: Vec<PathBuf>,
+    ffprobe: String,
+    /// Where the index is saved.
+    path: String,
+    index: Mutex<Index>,
+}
+
+impl VideoProvider for Library {
+    fn new(config: &Config) -> Result<Self> {
+        let dirs = config.video_library.as_ref().ok_or_else(|| {
+            Error::FailedConnection(String::from(
+                "no directories configured for the video library",
+            ))
+        })?;
+        let path = Res::new(ResKind::Data(String::from("videos.json")))?;
+        let library = Library {
+            dirs: env::split_paths(dirs).collect(),
+            ffprobe: config.ffprobe_path.clone(),
+            index: Mutex::new(Index::load(&path)?),
+            path: path.path,
+        };
+        library.update()?;
+
+        Ok(library)
+    }
+
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Candidate>> {
+        let sibling = sibling(track);
+        if sibling.is_none() && track.title.is_none() {
+            return Err(Error::NoTrackPlaying);
+        }
+
+        // The directories are checked again in case the files changed.
+        self.update()?;
+        let index = self.index.lock().unwrap();
+        let mut candidates: Vec<Candidate> = sibling
+            .and_then(|path| {
+                // The duration is taken from the index when available.
+                let path_str = path.to_string_lossy();
+                let duration = index
+                    .videos
+                    .iter()
+                    .find(|video| video.path == path_str)
+                    .and_then(|video| video.duration);
+                to_candidate(&path, duration)
+            })
+            .into_iter()
+            .collect();
+
+        for video in index.find(track) {
+            if let Some(candidate) = video.candidate() {
+                if !candidates.iter().any(|c| c.url == candidate.url) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+
+        Ok(candidates)
+    }
+}
+
+impl Library {
+    fn update(&self) -> Result<()> {
+        let mut index = self.index.lock().unwrap();
+        if index.update(&self.dirs, &self.ffprobe)? {
+            info!("Saving the video library with {}", index.videos.len());
+            index.save(&self.path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::temp_dir;
+
+    fn library(name: &str, ffprobe: &str) -> (Library, PathBuf) {
+        let dir = temp_dir(&format!("library-{}", name));
+        let videos = dir.join("videos");
+        fs::create_dir(&videos).unwrap();
+        fs::write(dir.join("index.json"), "").unwrap();
+
+        let library = Library {
+            dirs: vec![videos.clone()],
+            ffprobe: String::from(ffprobe),
+            path: dir.join("index.json").to_string_lossy().into_owned(),
+            index: Mutex::new(Index::default()),
+        };
+        (library, videos)
+    }
+
+    fn track(artist: Option<&str>, title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: artist.map(String::from),
+            title: Some(String::from(title)),
+            ..TrackInfo::default()
+        }
+    }
+
+    fn urls(candidates: Vec<Candidate>) -> Vec<String> {
+        candidates.into_iter().map(|c| c.url).collect()
+    }
+
+    #[test]
+    fn simplified() {
+        assert_eq!(
+            simplify("Never Gonna Give You Up (Official Music Video)"),
+            "never gonna give you up"
+        );
+        assert_eq!(simplify("  Don't   Stop [HD]"), "don t stop");
+        assert_eq!(simplify("Café del Mar"), "café del mar");
+        assert_eq!(simplify("Unbalanced)) (Live"), "unbalanced");
+    }
+
+    #[test]
+    fn file_names() {
+        let (library, videos) = library("names", "/nonexistent/ffprobe");
+        let rick =
+            videos.join("Rick Astley - Never Gonna Give You Up (HD).mkv");
+        fs::write(&rick, "").unwrap();
+        fs::create_dir(videos.join("other")).unwrap();
+        let cover = videos.join("other").join("Never gonna give you up!.mp4");
+        fs::write(&cover, "").unwrap();
+        fs::write(videos.join("Someone - Never Gonna Give You Up.webm"), "")
+            .unwrap();
+        fs::write(
+            videos.join("Rick Astley - Never Gonna Give You Up.txt"),
+            "",
+        )
+        .unwrap();
+
+        let candidates = library
+            .search(&track(Some("Rick Astley"), "Never Gonna Give You Up"))
+            .unwrap();
+        assert_eq!(
+            candidates[0],
+            Candidate {
+                url: Url::from_file_path(&rick).unwrap().to_string(),
+                title: String::from(
+                    "Rick Astley - Never Gonna Give You Up (HD)"
+                ),
+                ..Candidate::default()
+            }
+        );
+        assert_eq!(
+            urls(candidates)[1..],
+            [Url::from_file_path(&cover).unwrap().to_string()]
+        );
+
+        // Without an artist, all the titles match.
+        let found = library.search(&track(None, "never gonna give you up"));
+        assert_eq!(found.unwrap().len(), 3);
+        assert!(library.search(&track(None, "Unknown")).unwrap().is_empty());
+        assert!(library.search(&TrackInfo::default()).is_err());
+
+        // The index is saved, and it's updated when the files change.
+        let saved = Index::load(&library.path).unwrap();
+        assert_eq!(saved.videos.len(), 3);
+        fs::remove_file(&rick).unwrap();
+        fs::write(videos.join("Daft Punk - One More Time.mp4"), "").unwrap();
+        let found = library.search(&track(Some("Daft Punk"), "One More Time"));
+        assert_eq!(found.unwrap().len(), 1);
+        let found = library
+            .search(&track(Some("Rick Astley"), "Never Gonna Give You Up"));
+        assert_eq!(
+            urls(found.unwrap()),
+            [Url::from_file_path(&cover).unwrap().to_string()]
+        );
+        assert_eq!(Index::load(&library.path).unwrap().videos.len(), 3);
+    }
+
+    #[test]
+    fn siblings() {
+        let (library, videos) = library("siblings", "/nonexistent/ffprobe");
+        let music = videos.parent().unwrap().join("music");
+        fs::create_dir(&music).unwrap();
+        let audio = music.join("Daft Punk - Around the World.flac");
+        let video = music.join("Daft Punk - Around the World.webm");
+        fs::write(&audio, "").unwrap();
+        fs::write(&video, "").unwrap();
+        let indexed = videos.join("Daft Punk - Around the World.mkv");
+        fs::write(&indexed, "").unwrap();
+
+        // The spaces in the URL are percent-encoded.
+        let mut track = track(Some("Daft Punk"), "Around the World");
+        track.url = Some(Url::from_file_path(&audio).unwrap().to_string());
+        assert!(track.url.as_ref().unwrap().contains("%20"));
+        assert_eq!(
+            urls(library.search(&track).unwrap()),
+            [
+                Url::from_file_path(&video).unwrap().to_string(),
+                Url::from_file_path(&indexed).unwrap().to_string()
+            ]
+        );
+
+        // Sibling videos are found even without any metadata.
+        let track = TrackInfo {
+            url: track.url,
+            ..TrackInfo::default()
+        };
+        assert_eq!(library.search(&track).unwrap().len(), 1);
+
+        let track = TrackInfo {
+            url: Some(String::from("https://open.spotify.com/track/x")),
+            ..TrackInfo::default()
+        };
+        assert!(library.search(&track).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn embedded_tags() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("library-ffprobe");
+        let ffprobe = dir.join("ffprobe");
+        fs::write(
+            &ffprobe,
+            "#!/bin/sh\n\
+             case \"$6\" in\n\
+             *tagged.mp4) echo '{\"format\": {\"duration\": \"212.500000\", \
+             \"tags\": {\"ARTIST\": \"Rick Astley\", \"title\": \"Never \
+             Gonna Give You Up\"}}}' ;;\n\
+             *untagged.mkv) echo '{\"format\": {\"duration\": \"1.0\"}}' ;;\n\
+             *titled.mp4) echo '{\"format\": {\"tags\": {\"title\": \
+             \"One More Time\"}}}' ;;\n\
+             *) exit 1 ;;\n\
+             esac\n",
+        )
+        .unwrap();
+        fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let (library, videos) = library("tags", &ffprobe.to_string_lossy());
+        fs::write(videos.join("tagged.mp4"), "").unwrap();
+        fs::write(videos.join("untagged.mkv"), "").unwrap();
+        fs::write(videos.join("Artist - broken.mkv"), "").unwrap();
+        fs::write(videos.join("Daft Punk - titled.mp4"), "").unwrap();
+
+        let rick = track(Some("Rick Astley"), "Never Gonna Give You Up");
+        let candidates = library.search(&rick).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].title, "tagged");
+        assert_eq!(
+            candidates[0].duration,
+            Some(time::Duration::from_millis(212_500))
+        );
+
+        // The file names are used when the tags are missing or unreadable.
+        let found = library.search(&track(None, "untagged")).unwrap();
+        assert_eq!(found[0].duration, Some(time::Duration::from_secs(1)));
+        let found = library.search(&track(Some("Artist"), "Broken"));
+        assert_eq!(found.unwrap()[0].duration, None);
+        let daft_punk = track(Some("Daft Punk"), "One More Time");
+        assert_eq!(library.search(&daft_punk).unwrap().len(), 1);
+        let other = track(Some("Other"), "One More Time");
+        assert!(library.search(&other).unwrap().is_empty());
+    }
+}
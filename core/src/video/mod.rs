@@ -0,0 +1,178 @@
+//! Defines the video providers, which look for the music videos of the songs
+//! being played, and the basic functionalities they must provide, while
+//! listing the available implementations.
+
+pub mod cache;
+pub mod download;
+pub mod failover;
+pub mod fallback;
+mod instances;
+pub mod invidious;
+pub mod library;
+pub mod musicbrainz;
+pub mod overrides;
+pub mod piped;
+pub mod prefetch;
+pub mod query;
+pub mod resolver;
+pub mod score;
+pub mod sponsorblock;
+pub mod ytdlp;
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+use std::io;
+use std::process::Command;
+use std::time;
+
+use log::info;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+#[derive(Clone, Debug, Default, Display, EnumString)]
+pub enum Provider {
+    None,
+    #[default]
+    YtDlp,
+    Invidious,
+    Piped,
+    Library,
+}
+
+/// A music video found by a provider. Only the URL is guaranteed to be
+/// available, the rest of the metadata is used to rank the results.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub url: String,
+    pub title: String,
+    /// The name of the channel or user that uploaded the video.
+    pub channel: Option<String>,
+    pub duration: Option<time::Duration>,
+    /// The URL of the video's thumbnail.
+    pub thumbnail: Option<String>,
+}
+
+/// The providers are shared with the threads that prepare the videos in
+/// advance, so they must be thread-safe.
+pub trait VideoProvider: Send + Sync {
+    fn new(config: &Config) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Looks for the music videos of the given track, returning the
+    /// candidates ranked from best to worst. The list will be empty if
+    /// nothing was found.
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Candidate>>;
+
+    /// Obtains the URL to be opened by the player for a candidate. This is
+    /// only necessary for providers whose URLs point to a web page rather
+    /// than to the video itself, so by default it's the candidate's URL.
+    fn stream_url(&self, candidate: &Candidate) -> Result<String> {
+        Ok(candidate.url.clone())
+    }
+}
+
+pub fn init_video_provider(
+    provider: Provider,
+    config: &Config,
+) -> Result<Option<Box<dyn VideoProvider>>> {
+    let provider: Option<Box<dyn VideoProvider>> = match provider {
+        Provider::None => None,
+        Provider::YtDlp => Some(Box::new(ytdlp::YtDlp::new(config)?)),
+        Provider::Invidious => {
+            Some(Box::new(invidious::Invidious::new(config)?))
+        }
+        Provider::Piped => Some(Box::new(piped::Piped::new(config)?)),
+        Provider::Library => Some(Box::new(library::Library::new(config)?)),
+    };
+
+    Ok(provider)
+}
+
+/// Runs an external binary used by a provider with the given arguments,
+/// returning its output.
+pub fn run_command(path: &str, args: &[&str]) -> Result<String> {
+    info!("Running {} {}", path, args.join(" "));
+    let output =
+        Command::new(path).args(args).output().map_err(|e| {
+            match e.kind() {
+                io::ErrorKind::NotFound => {
+                    Error::MissingBinary(path.to_string())
+                }
+                _ => Error::IO(e),
+            }
+        })?;
+
+    if !output.status.success() {
+        return Err(Error::FailedCommand(format!(
+            "{} ({}): {}",
+            path,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether the URL is a valid HTTP or HTTPS one, which should be checked
+/// before passing URLs from untrusted sources to other programs.
+pub fn is_web_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Obtains the ID of a YouTube video from its URL, which may be relative.
+pub fn youtube_id(url: &str) -> Option<&str> {
+    let id = if let Some(pos) = url.find("youtu.be/") {
+        &url[pos + "youtu.be/".len()..]
+    } else {
+        url.split(['?', '&'])
+            .skip(1)
+            .find(|param| param.starts_with("v="))?
+            .trim_start_matches("v=")
+    };
+    let id = id.split(['?', '&', '#']).next()?;
+
+    Some(id).filter(|id| !id.is_empty())
+}
+
+pub fn youtube_url(id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn youtube_ids() {
+        let id = Some("dQw4w9WgXcQ");
+        assert_eq!(
+            youtube_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            id
+        );
+        assert_eq!(youtube_id("/watch?v=dQw4w9WgXcQ"), id);
+        assert_eq!(youtube_id("https://youtu.be/dQw4w9WgXcQ?t=10"), id);
+        assert_eq!(
+            youtube_id("https://www.youtube.com/watch?list=x&v=dQw4w9WgXcQ#t"),
+            id
+        );
+        assert_eq!(youtube_id("https://www.youtube.com/watch?dev=1"), None);
+        assert_eq!(youtube_id("https://www.youtube.com/watch?v="), None);
+        assert_eq!(youtube_id("https://vimeo.com/12345"), None);
+        assert_eq!(youtube_id(&youtube_url("abc")), Some("abc"));
+    }
+
+    #[test]
+    fn web_urls() {
+        assert!(is_web_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(is_web_url("http://192.168.1.20:3000/video.mp4"));
+        assert!(!is_web_url("--exec=touch /tmp/pwned"));
+        assert!(!is_web_url("file:///etc/passwd"));
+        assert!(!is_web_url("/watch?v=dQw4w9WgXcQ"));
+        assert!(!is_web_url(""));
+    }
+}
@@ -0,0 +1,549 @@
+//! MusicBrainz links many recordings to their official music video, which
+//! is a separate recording marked as a video with a "music video"
+//! relationship. Its URL relationships usually point to the official upload
+//! on YouTube, which is a better candidate than anything a search could
+//! find.
+//!
+//! It's disabled by default, since it makes the search a few seconds slower.
+//! When enabled, the videos found are ranked before the provider's results.
+//! The track is looked up by its ISRC when available, and searched by its
+//! artist and title otherwise. The API only allows one request per second
+//! and requires a meaningful User-Agent, so the requests are spaced out and
+//! identify the application:
+//! <https://musicbrainz.org/doc/MusicBrainz_API/Rate_Limiting>
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::video::query::normalize;
+use crate::video::{youtube_id, youtube_url, Candidate};
+
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+const USER_AGENT: &str = concat!(
+    "vidify/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/vidify/vidify )"
+);
+const TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// The minimum time between requests allowed by the API.
+const INTERVAL: time::Duration = time::Duration::from_secs(1);
+/// The minimum score of the search results, out of 100.
+const MIN_SCORE: u32 = 90;
+/// The maximum number of recordings whose videos are looked up, since each
+/// of them costs at least a second.
+const MAX_RECORDINGS: usize = 2;
+const MUSIC_VIDEO: &str = "music video";
+
+#[derive(Deserialize)]
+struct Recordings {
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(Deserialize)]
+struct Recording {
+    id: String,
+    #[serde(default)]
+    title: String,
+    /// In milliseconds.
+    length: Option<u64>,
+    /// It may be `null` in the search results.
+    video: Option<bool>,
+    /// Only available in the search results.
+    score: Option<u32>,
+    #[serde(default)]
+    relations: Vec<Relation>,
+}
+
+#[derive(Deserialize)]
+struct Relation {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "target-type")]
+    target_type: String,
+    url: Option<Url>,
+    recording: Option<Recording>,
+}
+
+#[derive(Deserialize)]
+struct Url {
+    resource: String,
+}
+
+impl Recording {
+    fn is_video(&self) -> bool {
+        self.video.unwrap_or(false)
+    }
+}
+
+pub struct MusicBrainz {
+    url: String,
+    client: Client,
+    /// The minimum time between requests, and when the last one was made.
+    interval: time::Duration,
+    last: Mutex<Option<time::Instant>>,
+}
+
+impl MusicBrainz {
+    pub fn new(url: &str, interval: time::Duration) -> Result<MusicBrainz> {
+        Ok(MusicBrainz {
+            url: url.trim_end_matches('/').to_string(),
+            client: Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(TIMEOUT)
+                .build()?,
+            interval,
+            last: Mutex::new(None),
+        })
+    }
+
+    /// Returns `None` if it's disabled in the config.
+    pub fn from_config(config: &Config) -> Result<Option<MusicBrainz>> {
+        if !config.musicbrainz {
+            return Ok(None);
+        }
+
+        Ok(Some(MusicBrainz::new(&config.musicbrainz_url, INTERVAL)?))
+    }
+
+    /// The official music videos of the track on YouTube. The list will be
+    /// empty if the track isn't in MusicBrainz or if it has no videos. The
+    /// recordings that can't be obtained are skipped.
+    pub fn videos(&self, track: &TrackInfo) -> Result<Vec<Candidate>> {
+        let mut recordings = Vec::new();
+        if let Some(isrc) = &track.isrc {
+            recordings = self.by_isrc(isrc)?;
+        }
+        if recordings.is_empty() {
+            recordings = self.search(track)?;
+        }
+
+        let mut videos = Vec::new();
+        for recording in recordings.into_iter().take(MAX_RECORDINGS) {
+            let recording = match self.recording(&recording.id) {
+                Ok(recording) => recording,
+                Err(e) => {
+                    warn!(
+                        "Couldn't obtain the recording {}: {}",
+                        recording.id, e
+                    );
+                    continue;
+                }
+            };
+            if recording.is_video() {
+                add_urls(&recording, &mut videos);
+                continue;
+            }
+
+            // The audio recording only links to its videos, whose URLs
+            // have to be requested separately. Its own URLs are usually
+            // audio-only uploads.
+            for relation in &recording.relations {
+                let video = match &relation.recording {
+                    Some(video)
+                        if relation.kind == MUSIC_VIDEO
+                            && relation.target_type == "recording" =>
+                    {
+                        video
+                    }
+                    _ => continue,
+                };
+                match self.recording(&video.id) {
+                    Ok(video) => add_urls(&video, &mut videos),
+                    Err(e) => {
+                        warn!("Couldn't obtain the video {}: {}", video.id, e)
+                    }
+                }
+            }
+        }
+
+        info!("Found {} official videos in MusicBrainz", videos.len());
+        Ok(videos)
+    }
+
+    /// The ISRCs are stored in uppercase, but the players don't always
+    /// report them like that.
+    fn by_isrc(&self, isrc: &str) -> Result<Vec<Recording>> {
+        let isrc = isrc.trim().to_uppercase();
+        if isrc.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let path = format!("/ws/2/isrc/{}", isrc);
+        Ok(self
+            .get::<Recordings>(&path, &[])?
+            .map(|found| found.recordings)
+            .unwrap_or_default())
+    }
+
+    /// Only the results with a high enough score are kept, since MusicBrainz
+    /// always returns something.
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Recording>> {
+        let track = match normalize(track) {
+            Some(track) => track,
+            None => return Ok(Vec::new()),
+        };
+        let artist = match &track.artist {
+            Some(artist) => artist,
+            None => return Ok(Vec::new()),
+        };
+
+        let query = format!(
+            "recording:\"{}\" AND artist:\"{}\"",
+            escape(&track.title),
+            escape(artist)
+        );
+        let found = self
+            .get::<Recordings>(
+                "/ws/2/recording",
+                &[("query", &query), ("limit", "5")],
+            )?
+            .map(|found| found.recordings)
+            .unwrap_or_default();
+
+        Ok(found
+            .into_iter()
+            .filter(|recording| recording.score.unwrap_or(0) >= MIN_SCORE)
+            .collect())
+    }
+
+    /// The recording with its URLs and the recordings related to it.
+    fn recording(&self, id: &str) -> Result<Recording> {
+        let path = format!("/ws/2/recording/{}", id);
+        self.get(&path, &[("inc", "url-rels recording-rels")])?
+            .ok_or_else(|| {
+                Error::FailedRequest(format!("recording {} not found", id))
+            })
+    }
+
+    /// Returns `None` if the resource doesn't exist.
+    fn get<T>(&self, path: &str, query: &[(&str, &str)]) -> Result<Option<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.wait();
+        let url = format!("{}{}", self.url, path);
+        let res = self
+            .client
+            .get(&url)
+            .query(query)
+            .query(&[("fmt", "json")])
+            .send()?;
+
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Error::FailedRequest(format!(
+                "{} returned {}",
+                url,
+                res.status()
+            )));
+        }
+
+        Ok(Some(res.json()?))
+    }
+
+    /// Sleeps until the next request is allowed. The lock is held in the
+    /// meantime, so that the requests from other threads are spaced out as
+    /// well.
+    fn wait(&self) {
+        let mut last = self.last.lock().unwrap();
+        if let Some(last) = *last {
+            let elapsed = last.elapsed();
+            if elapsed < self.interval {
+                thread::sleep(self.interval - elapsed);
+            }
+        }
+        *last = Some(time::Instant::now());
+    }
+}
+
+/// Adds the YouTube URLs of a video recording, without duplicates.
+fn add_urls(recording: &Recording, videos: &mut Vec<Candidate>) {
+    for relation in &recording.relations {
+        let id = match &relation.url {
+            Some(url) => match youtube_id(&url.resource) {
+                Some(id) => id,
+                None => continue,
+            },
+            None => continue,
+        };
+        let url = youtube_url(id);
+        if videos.iter().any(|video| video.url == url) {
+            continue;
+        }
+
+        videos.push(Candidate {
+            url,
+            title: recording.title.clone(),
+            duration: recording.length.map(time::Duration::from_millis),
+            ..Candidate::default()
+        });
+    }
+}
+
+/// Escapes the special characters of the query syntax inside a phrase.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::MockServer;
+
+    const ISRC: &str = r#"{
+        "isrc": "GBARL9300135",
+        "recordings": [
+            {
+                "id": "audio",
+                "title": "Never Gonna Give You Up",
+                "length": 213000,
+                "video": false
+            }
+        ]
+    }"#;
+
+    const AUDIO: &str = r#"{
+        "id": "audio",
+        "title": "Never Gonna Give You Up",
+        "length": 213000,
+        "video": false,
+        "relations": [
+            {
+                "type": "free streaming",
+                "target-type": "url",
+                "direction": "forward",
+                "url": {
+                    "id": "topic",
+                    "resource": "https://www.youtube.com/watch?v=topic"
+                }
+            },
+            {
+                "type": "music video",
+                "target-type": "recording",
+                "direction": "backward",
+                "recording": {
+                    "id": "video",
+                    "title": "Never Gonna Give You Up (official video)",
+                    "video": true
+                }
+            },
+            {
+                "type": "remix",
+                "target-type": "recording",
+                "direction": "backward",
+                "recording": {
+                    "id": "remix",
+                    "title": "Never Gonna Give You Up (remix)",
+                    "video": false
+                }
+            }
+        ]
+    }"#;
+
+    const VIDEO: &str = r#"{
+        "id": "video",
+        "title": "Never Gonna Give You Up (official video)",
+        "length": 212000,
+        "video": true,
+        "relations": [
+            {
+                "type": "free streaming",
+                "target-type": "url",
+                "direction": "forward",
+                "url": {
+                    "id": "youtube",
+                    "resource": "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+                }
+            },
+            {
+                "type": "free streaming",
+                "target-type": "url",
+                "direction": "forward",
+                "url": {
+                    "id": "short",
+                    "resource": "https://youtu.be/dQw4w9WgXcQ"
+                }
+            },
+            {
+                "type": "free streaming",
+                "target-type": "url",
+                "direction": "forward",
+                "url": {
+                    "id": "vimeo",
+                    "resource": "https://vimeo.com/12345"
+                }
+            }
+        ]
+    }"#;
+
+    const SEARCH: &str = r#"{
+        "count": 2,
+        "offset": 0,
+        "recordings": [
+            {
+                "id": "audio",
+                "score": 100,
+                "title": "Never Gonna Give You Up",
+                "video": null,
+                "artist-credit": [{"name": "Rick Astley"}]
+            },
+            {
+                "id": "cover",
+                "score": 60,
+                "title": "Never Gonna Give You Up",
+                "video": null,
+                "artist-credit": [{"name": "Someone Else"}]
+            }
+        ]
+    }"#;
+
+    fn server() -> MockServer {
+        MockServer::new(|req| match req.route() {
+            "/ws/2/isrc/GBARL9300135" => {
+                (200, "application/json", ISRC.into())
+            }
+            "/ws/2/recording" => (200, "application/json", SEARCH.into()),
+            "/ws/2/recording/audio" => (200, "application/json", AUDIO.into()),
+            "/ws/2/recording/video" => (200, "application/json", VIDEO.into()),
+            _ => (404, "application/json", r#"{"error": "Not Found"}"#.into()),
+        })
+    }
+
+    fn track(isrc: Option<&str>) -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Rick Astley")),
+            title: Some(String::from("Never Gonna Give You Up - Remastered")),
+            isrc: isrc.map(String::from),
+            ..TrackInfo::default()
+        }
+    }
+
+    fn official() -> Vec<Candidate> {
+        vec![Candidate {
+            url: youtube_url("dQw4w9WgXcQ"),
+            title: String::from("Never Gonna Give You Up (official video)"),
+            duration: Some(time::Duration::from_secs(212)),
+            ..Candidate::default()
+        }]
+    }
+
+    #[test]
+    fn isrc() {
+        let server = server();
+        let musicbrainz =
+            MusicBrainz::new(&server.url, time::Duration::from_secs(0))
+                .unwrap();
+
+        let videos = musicbrainz.videos(&track(Some("GBARL9300135"))).unwrap();
+        assert_eq!(videos, official());
+        let requests = server.requests();
+        let routes: Vec<_> = requests.iter().map(|req| req.route()).collect();
+        assert_eq!(
+            routes,
+            [
+                "/ws/2/isrc/GBARL9300135",
+                "/ws/2/recording/audio",
+                "/ws/2/recording/video"
+            ]
+        );
+        for req in &requests {
+            assert_eq!(req.param("fmt").as_deref(), Some("json"));
+            assert!(req.header("user-agent").unwrap().starts_with("vidify/"));
+        }
+        assert_eq!(
+            requests[1].param("inc").as_deref(),
+            Some("url-rels recording-rels")
+        );
+
+        // The ISRC is normalized before being looked up.
+        let videos =
+            musicbrainz.videos(&track(Some(" gbarl9300135 "))).unwrap();
+        assert_eq!(videos, official());
+        assert_eq!(server.requests()[3].route(), "/ws/2/isrc/GBARL9300135");
+    }
+
+    #[test]
+    fn failed_recording() {
+        const FOUND: &str = r#"{"recordings": [
+            {"id": "broken", "title": "Never Gonna Give You Up"},
+            {"id": "audio", "title": "Never Gonna Give You Up"}
+        ]}"#;
+        let server = MockServer::new(|req| match req.route() {
+            "/ws/2/isrc/GBARL9300135" => {
+                (200, "application/json", FOUND.into())
+            }
+            "/ws/2/recording/broken" => (500, "text/plain", "".into()),
+            "/ws/2/recording/audio" => (200, "application/json", AUDIO.into()),
+            "/ws/2/recording/video" => (200, "application/json", VIDEO.into()),
+            _ => (404, "application/json", r#"{"error": "Not Found"}"#.into()),
+        });
+        let musicbrainz =
+            MusicBrainz::new(&server.url, time::Duration::from_secs(0))
+                .unwrap();
+
+        // The recording that can't be obtained doesn't hide the others.
+        let videos = musicbrainz.videos(&track(Some("GBARL9300135"))).unwrap();
+        assert_eq!(videos, official());
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[test]
+    fn search() {
+        let server = server();
+        let musicbrainz =
+            MusicBrainz::new(&server.url, time::Duration::from_secs(0))
+                .unwrap();
+
+        // An unknown ISRC falls back to the search.
+        let videos = musicbrainz.videos(&track(Some("UNKNOWN"))).unwrap();
+        assert_eq!(videos, official());
+        let requests = server.requests();
+        assert_eq!(requests[1].route(), "/ws/2/recording");
+        assert_eq!(
+            requests[1].param("query").as_deref(),
+            Some(
+                "recording:\"Never Gonna Give You Up\" AND \
+                 artist:\"Rick Astley\""
+            )
+        );
+        // The cover's score is too low for it to be looked up.
+        assert_eq!(requests.len(), 4);
+
+        // The artist is required to search.
+        let unknown = TrackInfo {
+            title: Some(String::from("Song")),
+            ..TrackInfo::default()
+        };
+        assert!(musicbrainz.videos(&unknown).unwrap().is_empty());
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let server = server();
+        let interval = time::Duration::from_millis(200);
+        let musicbrainz = MusicBrainz::new(&server.url, interval).unwrap();
+
+        let start = time::Instant::now();
+        musicbrainz.videos(&track(Some("GBARL9300135"))).unwrap();
+        // The first request isn't delayed.
+        assert!(start.elapsed() >= interval * 2);
+    }
+
+    #[test]
+    fn escaped() {
+        assert_eq!(escape(r#"The "Song" \ B"#), r#"The \"Song\" \\ B"#);
+    }
+}
@@ -0,0 +1,337 @@
+//! The corrections made by the user for the videos chosen, which are saved
+//! in the data directory. A track can be pinned to a video so that it's
+//! always played, and the wrong videos can be blocked so that they're never
+//! chosen again.
+//!
+//! The tracks are identified by their ISRC or their ID when they're stable
+//! across sessions, and otherwise by their normalized artist and title. The
+//! overrides can be exported and imported to share them with other users.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::data::{Res, ResKind};
+use crate::error::{Error, Result};
+use crate::video::query::track_keys;
+use crate::video::{is_web_url, youtube_id};
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// The name of the file in the data directory.
+pub const DATA_FILE: &str = "overrides.json";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Override {
+    /// The pinned video.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked: Vec<String>,
+}
+
+impl Override {
+    fn is_empty(&self) -> bool {
+        self.video.is_none() && self.blocked.is_empty()
+    }
+
+    /// Whether all its videos are web URLs, since they're passed to other
+    /// programs like `yt-dlp`.
+    fn is_valid(&self) -> bool {
+        self.video
+            .iter()
+            .chain(&self.blocked)
+            .all(|url| is_web_url(url))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Overrides {
+    /// Sorted by key so that the exported files are easy to compare.
+    tracks: BTreeMap<String, Override>,
+}
+
+impl Overrides {
+    /// Loads the overrides from a file, which may be empty.
+    pub fn load(path: &str) -> Result<Overrides> {
+        let data = fs::read_to_string(path)?;
+        if data.trim().is_empty() {
+            return Ok(Overrides::default());
+        }
+
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// The file is indented because it may be edited by hand.
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The video pinned to the track, if any.
+    pub fn pinned(&self, track: &TrackInfo) -> Option<&str> {
+        track_keys(track).iter().find_map(|key| {
+            self.tracks
+                .get(key)
+                .and_then(|entry| entry.video.as_deref())
+        })
+    }
+
+    pub fn is_blocked(&self, track: &TrackInfo, url: &str) -> bool {
+        track_keys(track).iter().any(|key| {
+            self.tracks.get(key).is_some_and(|entry| {
+                entry.blocked.iter().any(|blocked| same_video(blocked, url))
+            })
+        })
+    }
+
+    /// Always plays the given video for the track, which is unblocked if
+    /// necessary. It's saved under all the keys of the track, so that it's
+    /// also found from sources with less metadata.
+    pub fn pin(&mut self, track: &TrackInfo, url: &str) -> Result<()> {
+        let keys = track_keys(track);
+        if keys.is_empty() {
+            return Err(Error::NoTrackPlaying);
+        }
+        for key in keys {
+            let entry = self.tracks.entry(key).or_default();
+            entry.blocked.retain(|blocked| !same_video(blocked, url));
+            entry.video = Some(url.to_string());
+        }
+        self.remove_empty();
+
+        Ok(())
+    }
+
+    pub fn unpin(&mut self, track: &TrackInfo) {
+        for key in track_keys(track) {
+            if let Some(entry) = self.tracks.get_mut(&key) {
+                entry.video = None;
+            }
+        }
+        self.remove_empty();
+    }
+
+    /// Never plays the given video for the track again. If it was pinned,
+    /// it's unpinned as well. Like the pins, it's saved under all the keys
+    /// of the track.
+    pub fn block(&mut self, track: &TrackInfo, url: &str) -> Result<()> {
+        let keys = track_keys(track);
+        if keys.is_empty() {
+            return Err(Error::NoTrackPlaying);
+        }
+        for key in keys {
+            let entry = self.tracks.entry(key).or_default();
+            if entry.video.as_deref().is_some_and(|v| same_video(v, url)) {
+                entry.video = None;
+            }
+            if !entry.blocked.iter().any(|blocked| same_video(blocked, url)) {
+                entry.blocked.push(url.to_string());
+            }
+        }
+        self.remove_empty();
+
+        Ok(())
+    }
+
+    /// Merges the overrides in a file with the current ones. The imported
+    /// pins take precedence, and the blocked videos are combined. The file
+    /// may be shared by others, so the entries with videos that aren't web
+    /// URLs are skipped. Returns the number of entries that changed.
+    pub fn import(&mut self, path: &str) -> Result<usize> {
+        let imported = Overrides::load(path)?;
+        let mut count = 0;
+        for (key, new) in imported.tracks {
+            if !new.is_valid() {
+                warn!("Skipping the overrides of {} with invalid URLs", key);
+                continue;
+            }
+
+            let entry = self.tracks.entry(key).or_default();
+            let before = entry.clone();
+            if new.video.is_some() {
+                entry.video = new.video;
+            }
+            for url in new.blocked {
+                if !entry.blocked.iter().any(|b| same_video(b, &url)) {
+                    entry.blocked.push(url);
+                }
+            }
+            if let Some(video) = &entry.video {
+                entry.blocked.retain(|blocked| !same_video(blocked, video));
+            }
+            if *entry != before {
+                count += 1;
+            }
+        }
+        self.remove_empty();
+
+        Ok(count)
+    }
+
+    pub fn export(&self, path: &str) -> Result<()> {
+        self.save(path)
+    }
+
+    fn remove_empty(&mut self) {
+        self.tracks.retain(|_, entry| !entry.is_empty());
+    }
+}
+
+/// Imports or exports the overrides if it was requested with the
+/// arguments. Returns whether anything was done, in which case the app
+/// shouldn't start.
+pub fn handle_args(config: &Config) -> Result<bool> {
+    if config.import_overrides.is_none() && config.export_overrides.is_none() {
+        return Ok(false);
+    }
+
+    let path = Res::new(ResKind::Data(String::from(DATA_FILE)))?;
+    let mut overrides = Overrides::load(&path)?;
+    if let Some(file) = &config.import_overrides {
+        let count = overrides.import(file)?;
+        overrides.save(&path)?;
+        info!("Imported {} changed overrides from {}", count, file);
+    }
+    if let Some(file) = &config.export_overrides {
+        overrides.export(file)?;
+        info!("Exported the overrides to {}", file);
+    }
+
+    Ok(true)
+}
+
+/// The same YouTube video may have different URLs.
+pub fn same_video(a: &str, b: &str) -> bool {
+    match (youtube_id(a), youtube_id(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::temp_dir;
+
+    fn track(artist: &str, title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: Some(artist.to_string()),
+            title: Some(title.to_string()),
+            ..TrackInfo::default()
+        }
+    }
+
+    const RICK: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+    const LYRICS: &str = "https://www.youtube.com/watch?v=lyrics";
+
+    #[test]
+    fn pin_and_block() {
+        let mut overrides = Overrides::default();
+        let mut rick = track("Rick Astley", "Never Gonna Give You Up");
+        assert_eq!(overrides.pinned(&rick), None);
+
+        overrides.block(&rick, LYRICS).unwrap();
+        overrides.block(&rick, LYRICS).unwrap();
+        overrides.pin(&rick, RICK).unwrap();
+        assert_eq!(overrides.pinned(&rick), Some(RICK));
+        assert!(overrides.is_blocked(&rick, LYRICS));
+        assert!(overrides.is_blocked(&rick, "https://youtu.be/lyrics"));
+        assert!(!overrides.is_blocked(&rick, RICK));
+
+        // The same track from another source, with a stable ID.
+        rick.title = Some("Never Gonna Give You Up (Remastered)".into());
+        rick.id = Some("spotify:track:4uLU6hMCjMI75M1A2tKUQC".into());
+        assert_eq!(overrides.pinned(&rick), Some(RICK));
+
+        // Blocking the pinned video unpins it.
+        overrides
+            .block(&rick, "https://youtu.be/dQw4w9WgXcQ")
+            .unwrap();
+        assert_eq!(overrides.pinned(&rick), None);
+        assert!(overrides.is_blocked(&rick, RICK));
+
+        // And pinning a blocked video unblocks it.
+        overrides.pin(&rick, LYRICS).unwrap();
+        assert!(!overrides.is_blocked(&rick, LYRICS));
+        assert_eq!(overrides.pinned(&rick), Some(LYRICS));
+        overrides.unpin(&rick);
+        assert_eq!(overrides.pinned(&rick), None);
+
+        // A pin made from a source with more metadata is also found from
+        // the ones with less.
+        let mut queen = track("Queen", "Bohemian Rhapsody");
+        queen.isrc = Some("GBUM71029604".into());
+        overrides.pin(&queen, RICK).unwrap();
+        overrides.block(&queen, LYRICS).unwrap();
+        queen.isrc = None;
+        assert_eq!(overrides.pinned(&queen), Some(RICK));
+        assert!(overrides.is_blocked(&queen, LYRICS));
+
+        let other = track("Rick Astley", "Together Forever");
+        assert!(!overrides.is_blocked(&other, RICK));
+        assert!(overrides.pin(&TrackInfo::default(), RICK).is_err());
+        assert!(overrides.block(&TrackInfo::default(), RICK).is_err());
+    }
+
+    #[test]
+    fn import_and_export() {
+        let dir = temp_dir("overrides");
+        let shared = dir.join("shared.json").to_string_lossy().into_owned();
+        let saved = dir.join("saved.json").to_string_lossy().into_owned();
+
+        let rick = track("Rick Astley", "Never Gonna Give You Up");
+        let queen = track("Queen", "Bohemian Rhapsody");
+        let mut theirs = Overrides::default();
+        theirs.pin(&rick, RICK).unwrap();
+        theirs.block(&queen, LYRICS).unwrap();
+        theirs.export(&shared).unwrap();
+
+        let mut ours = Overrides::default();
+        ours.block(&rick, RICK).unwrap();
+        ours.block(&queen, "https://www.youtube.com/watch?v=cover")
+            .unwrap();
+        assert_eq!(ours.import(&shared).unwrap(), 2);
+        assert_eq!(ours.pinned(&rick), Some(RICK));
+        assert!(!ours.is_blocked(&rick, RICK));
+        assert!(ours.is_blocked(&queen, LYRICS));
+        assert!(ours.is_blocked(&queen, "https://youtu.be/cover"));
+
+        // Only the entries that changed are counted, even if the tracks
+        // are stored under several keys.
+        assert_eq!(ours.import(&shared).unwrap(), 0);
+        let mut isrc = track("Daft Punk", "One More Time");
+        isrc.isrc = Some("GBDUW0000059".into());
+        let mut theirs = Overrides::default();
+        theirs.pin(&isrc, RICK).unwrap();
+        theirs.export(&shared).unwrap();
+        assert!(theirs.tracks.len() > 1);
+        assert_eq!(ours.import(&shared).unwrap(), theirs.tracks.len());
+        assert_eq!(ours.import(&shared).unwrap(), 0);
+
+        // The entries with videos that aren't web URLs are skipped.
+        let bad = r#"{"tracks": {
+            "a": {"video": "--exec=touch pwned"},
+            "b": {"blocked": ["https://youtu.be/ok", "file:///etc/passwd"]},
+            "c": {"video": "https://youtu.be/ok"}
+        }}"#;
+        fs::write(&shared, bad).unwrap();
+        assert_eq!(ours.import(&shared).unwrap(), 1);
+        assert!(!ours.tracks.contains_key("a"));
+        assert!(!ours.tracks.contains_key("b"));
+        assert_eq!(
+            ours.tracks["c"].video.as_deref(),
+            Some("https://youtu.be/ok")
+        );
+
+        ours.save(&saved).unwrap();
+        assert_eq!(Overrides::load(&saved).unwrap(), ours);
+        fs::write(&saved, "").unwrap();
+        assert_eq!(Overrides::load(&saved).unwrap(), Overrides::default());
+        fs::write(&saved, "{").unwrap();
+        assert!(ours.import(&saved).is_err());
+    }
+}
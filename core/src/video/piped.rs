@@ -0,0 +1,204 @@
+//! A provider that uses the API of a Piped instance, an alternative
+//! front-end to YouTube, so that no external binaries are required.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::video::instances::Instances;
+use crate::video::query::QueryTemplate;
+use crate::video::{youtube_id, youtube_url, Candidate, VideoProvider};
+
+use std::time;
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Search {
+    items: Vec<SearchItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchItem {
+    #[serde(rename = "type")]
+    kind: String,
+    /// Relative to YouTube, like `/watch?v=dQw4w9WgXcQ`.
+    url: String,
+    title: Option<String>,
+    uploader_name: Option<String>,
+    /// In seconds, or -1 for live streams.
+    duration: Option<i64>,
+    thumbnail: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Streams {
+    #[serde(default)]
+    video_streams: Vec<VideoStream>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VideoStream {
+    url: String,
+    /// For example, `720p`.
+    quality: Option<String>,
+    /// The video-only streams can't be used because the audio is a
+    /// separate stream.
+    video_only: bool,
+}
+
+pub struct Piped {
+    instances: Instances,
+    results: usize,
+    query: QueryTemplate,
+}
+
+impl VideoProvider for Piped {
+    fn new(config: &Config) -> Result<Self> {
+        Ok(Piped {
+            instances: Instances::new(&config.piped_instances)?,
+            results: config.video_results as usize,
+            query: QueryTemplate::from_config(config),
+        })
+    }
+
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Candidate>> {
+        let query = self.query.render(track)?;
+        let (search, _): (Search, _) = self
+            .instances
+            .get("/search", &[("q", &query), ("filter", "videos")])?;
+
+        let candidates = search
+            .items
+            .into_iter()
+            .filter(|item| item.kind == "stream")
+            .filter_map(|item| {
+                Some(Candidate {
+                    url: youtube_url(youtube_id(&item.url)?),
+                    title: item.title?,
+                    channel: item.uploader_name,
+                    duration: item
+                        .duration
+                        .filter(|secs| *secs >= 0)
+                        .map(|secs| time::Duration::from_secs(secs as u64)),
+                    thumbnail: item.thumbnail,
+                })
+            })
+            .take(self.results)
+            .collect();
+
+        Ok(candidates)
+    }
+
+    /// The stream with the highest quality that includes audio is used.
+    fn stream_url(&self, candidate: &Candidate) -> Result<String> {
+        let id = youtube_id(&candidate.url).ok_or_else(|| {
+            Error::FailedRequest(format!(
+                "{} isn't a YouTube video",
+                candidate.url
+            ))
+        })?;
+        let (streams, _): (Streams, _) =
+            self.instances.get(&format!("/streams/{}", id), &[])?;
+
+        streams
+            .video_streams
+            .into_iter()
+            .filter(|stream| !stream.video_only)
+            .max_by_key(|stream| {
+                stream
+                    .quality
+                    .as_deref()
+                    .and_then(|q| q.trim_end_matches('p').parse::<u32>().ok())
+                    .unwrap_or(0)
+            })
+            .map(|stream| stream.url)
+            .ok_or_else(|| {
+                Error::FailedRequest(format!(
+                    "no streams available for {}",
+                    id
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::MockServer;
+
+    const SEARCH: &str = r#"{
+        "items": [
+            {
+                "type": "stream",
+                "url": "/watch?v=dQw4w9WgXcQ",
+                "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)",
+                "uploaderName": "Rick Astley",
+                "duration": 212,
+                "thumbnail": "https://pipedproxy/vi/dQw4w9WgXcQ/hqdefault.jpg"
+            },
+            {"type": "channel", "url": "/channel/UCuAXFkgsw1L7xaCfnd5JJOw"},
+            {
+                "type": "stream",
+                "url": "/watch?v=live",
+                "title": "Rick Astley Live",
+                "duration": -1
+            }
+        ],
+        "nextpage": null
+    }"#;
+
+    const STREAMS: &str = r#"{
+        "title": "Rick Astley - Never Gonna Give You Up",
+        "videoStreams": [
+            {"url": "https://proxy/1080", "quality": "1080p", "videoOnly": true},
+            {"url": "https://proxy/360", "quality": "360p", "videoOnly": false},
+            {"url": "https://proxy/720", "quality": "720p", "videoOnly": false}
+        ]
+    }"#;
+
+    #[test]
+    fn search() {
+        let server = MockServer::new(|req| match req.route() {
+            "/search" => (200, "application/json", SEARCH.into()),
+            "/streams/dQw4w9WgXcQ" => {
+                (200, "application/json", STREAMS.into())
+            }
+            _ => (404, "application/json", "{}".into()),
+        });
+        let provider = Piped {
+            instances: Instances::new(&server.url).unwrap(),
+            results: 5,
+            query: QueryTemplate::default(),
+        };
+        let track = TrackInfo {
+            artist: Some(String::from("Rick Astley")),
+            title: Some(String::from("Never Gonna Give You Up")),
+            ..TrackInfo::default()
+        };
+
+        let candidates = provider.search(&track).unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[0].url,
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+        assert_eq!(candidates[0].channel.as_deref(), Some("Rick Astley"));
+        assert_eq!(
+            candidates[0].duration,
+            Some(time::Duration::from_secs(212))
+        );
+        assert_eq!(candidates[1].url, "https://www.youtube.com/watch?v=live");
+        assert_eq!(candidates[1].duration, None);
+        assert_eq!(server.requests()[0].param("filter").unwrap(), "videos");
+
+        assert_eq!(
+            provider.stream_url(&candidates[0]).unwrap(),
+            "https://proxy/720"
+        );
+        assert!(provider.stream_url(&candidates[1]).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Prepares the video of the next track in the queue while the current one
+//! is playing. Searching for it and obtaining its stream may take a few
+//! seconds, so it's done in the background and saved in the cache, making
+//! the switch at the end of the track close to instant. The video may also
+//! be preloaded in the player, if it supports it.
+//!
+//! The next track is only known for the APIs that expose their queue, like
+//! MPRIS with its TrackList interface or the Spotify Web API. There's no
+//! MPD API, so its playlist isn't read.
+
+use crate::api::{APIBase, TrackInfo};
+use crate::config::Config;
+use crate::error::Error;
+use crate::player::{PlayerBase, VideoSource};
+use crate::video::query::track_keys;
+use crate::video::resolver::{Resolved, Resolver};
+
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use log::{info, warn};
+
+/// The video resolved for an upcoming track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefetched {
+    pub track: TrackInfo,
+    pub resolved: Resolved,
+}
+
+pub struct Prefetcher {
+    resolver: Arc<Resolver>,
+    /// Whether the videos are loaded in the player as well.
+    preload: bool,
+    /// The keys of the last track prefetched, so that it's only done once.
+    last: Vec<String>,
+    /// Receives the result of the prefetch in progress, if any.
+    receiver: Option<mpsc::Receiver<Option<Prefetched>>>,
+}
+
+impl Prefetcher {
+    pub fn new(resolver: Arc<Resolver>, preload: bool) -> Prefetcher {
+        Prefetcher {
+            resolver,
+            preload,
+            last: Vec::new(),
+            receiver: None,
+        }
+    }
+
+    /// Returns `None` if prefetching is disabled in the config.
+    pub fn from_config(
+        config: &Config,
+        resolver: Arc<Resolver>,
+    ) -> Option<Prefetcher> {
+        if config.no_prefetch {
+            return None;
+        }
+
+        Some(Prefetcher::new(resolver, config.preload))
+    }
+
+    /// Asks the API for the upcoming track and prefetches its video.
+    /// Nothing is done if the API doesn't expose its queue. Returns whether
+    /// a new prefetch was started.
+    pub fn update(&mut self, api: &dyn APIBase) -> bool {
+        match api.upcoming_track() {
+            Ok(Some(track)) => self.prefetch(&track),
+            Ok(None) | Err(Error::Unsupported) => false,
+            Err(e) => {
+                warn!("Couldn't obtain the upcoming track: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Resolves the video of the track in a separate thread, unless it was
+    /// the last one prefetched. Returns whether it was started.
+    pub fn prefetch(&mut self, track: &TrackInfo) -> bool {
+        let keys = track_keys(track);
+        if keys.is_empty() || keys == self.last {
+            return false;
+        }
+        self.last = keys;
+
+        let (sender, receiver) = mpsc::channel();
+        let resolver = Arc::clone(&self.resolver);
+        let track = track.clone();
+        thread::spawn(move || {
+            info!("Prefetching the video of {:?}", track.title);
+            let prefetched = match resolver.resolve(&track) {
+                Ok(Some(resolved)) => Some(Prefetched { track, resolved }),
+                Ok(None) => None,
+                Err(e) => {
+                    warn!("Couldn't prefetch the next video: {}", e);
+                    None
+                }
+            };
+            // The prefetcher may have been dropped in the meantime, and the
+            // video is cached anyway.
+            let _ = sender.send(prefetched);
+        });
+        self.receiver = Some(receiver);
+
+        true
+    }
+
+    /// The result of the last prefetch, once it has finished. It's only
+    /// returned once.
+    pub fn finished(&mut self) -> Option<Prefetched> {
+        let result = self.receiver.as_ref()?.try_recv();
+        match result {
+            Ok(prefetched) => {
+                self.receiver = None;
+                prefetched
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.receiver = None;
+                None
+            }
+        }
+    }
+
+    /// Blocks until the last prefetch has finished, returning its result.
+    pub fn wait(&mut self) -> Option<Prefetched> {
+        self.receiver.take()?.recv().ok().flatten()
+    }
+
+    /// Loads the finished prefetch in the player if preloading is enabled.
+    /// Returns whether it was preloaded.
+    pub fn preload(&mut self, player: &mut dyn PlayerBase) -> bool {
+        if !self.preload {
+            return false;
+        }
+        let prefetched = match self.finished() {
+            Some(prefetched) => prefetched,
+            None => return false,
+        };
+
+        let source = VideoSource::new(&prefetched.resolved.url);
+        match player.preload(&source) {
+            Ok(()) => true,
+            Err(Error::Unsupported) => {
+                info!("The player doesn't support preloading videos");
+                self.preload = false;
+                false
+            }
+            Err(e) => {
+                warn!("Couldn't preload the next video: {}", e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{FakePlayer, FakeProvider};
+    use crate::video::score::Scorer;
+
+    use std::time;
+
+    fn track(title: &str) -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Artist")),
+            title: Some(title.to_string()),
+            duration: Some(time::Duration::from_secs(200)),
+            ..TrackInfo::default()
+        }
+    }
+
+    fn resolver(provider: &FakeProvider) -> Arc<Resolver> {
+        let provider = Box::new(provider.clone());
+        Arc::new(Resolver::new(provider, Scorer::default()))
+    }
+
+    #[test]
+    fn prefetch() {
+        let provider = FakeProvider::default();
+        let resolver = resolver(&provider);
+        let mut prefetcher = Prefetcher::new(Arc::clone(&resolver), false);
+        assert_eq!(prefetcher.finished(), None);
+
+        assert!(prefetcher.prefetch(&track("Next")));
+        let prefetched = prefetcher.wait().unwrap();
+        assert_eq!(prefetched.track, track("Next"));
+        assert_eq!(prefetched.resolved.url, "video-Next");
+        assert_eq!(prefetcher.finished(), None);
+
+        // The same track isn't prefetched twice, and once it starts playing
+        // its video is already cached.
+        assert!(!prefetcher.prefetch(&track("Next")));
+        let resolved = resolver.resolve(&track("Next")).unwrap().unwrap();
+        assert_eq!(resolved, prefetched.resolved);
+        assert_eq!(provider.searches(), 1);
+
+        assert!(!prefetcher.prefetch(&TrackInfo::default()));
+        assert!(prefetcher.prefetch(&track("After")));
+        assert!(prefetcher.wait().is_some());
+        assert_eq!(provider.searches(), 2);
+    }
+
+    #[test]
+    fn preload() {
+        let provider = FakeProvider::default();
+        let mut player = FakePlayer::new(&[]);
+
+        let mut prefetcher = Prefetcher::new(resolver(&provider), true);
+        assert!(!prefetcher.preload(&mut player));
+        prefetcher.prefetch(&track("Next"));
+        let start = time::Instant::now();
+        while !prefetcher.preload(&mut player) {
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+            thread::yield_now();
+        }
+        assert_eq!(player.preloaded, [VideoSource::new("video-Next")]);
+        assert!(!prefetcher.preload(&mut player));
+
+        let mut disabled = Prefetcher::new(resolver(&provider), false);
+        disabled.prefetch(&track("Other"));
+        assert!(!disabled.preload(&mut player));
+        assert_eq!(player.preloaded.len(), 1);
+    }
+}
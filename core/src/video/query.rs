@@ -0,0 +1,711 @@
+//! Builds the text used to search for the music video of a track. The
+//! metadata reported by the APIs is often decorated with details like
+//! `Song - Remastered 2011` or `Song (feat. X) - Radio Edit`, which only
+//! make the results worse, so they're removed first. The query is then
+//! built from a template in the config, like `{artist} - {title} official
+//! video`.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+use std::collections::HashSet;
+
+/// The words that introduce the featured artists.
+const FEAT_WORDS: &[&str] = &["feat", "feat.", "ft", "ft.", "featuring"];
+
+/// The words that indicate that a suffix or a part of the title between
+/// brackets isn't part of the song's name.
+const DECORATION_WORDS: &[&str] = &[
+    "remaster",
+    "remastered",
+    "remasterizado",
+    "edit",
+    "version",
+    "mix",
+    "mono",
+    "stereo",
+    "explicit",
+    "clean",
+    "bonus",
+    "deluxe",
+    "edition",
+    "official",
+    "video",
+    "audio",
+    "lyric",
+    "lyrics",
+    "visualizer",
+    "hd",
+    "hq",
+    "4k",
+];
+
+/// The track's metadata after removing the decorations.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Normalized {
+    pub artist: Option<String>,
+    pub title: String,
+    pub album: Option<String>,
+    /// The featured artists found in the title or in the artist.
+    pub featured: Vec<String>,
+}
+
+/// The templates used to build the queries. The placeholders `{artist}`,
+/// `{title}`, `{album}` and `{featured}` are replaced with the normalized
+/// metadata of the track, or removed if it's not available.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryTemplate {
+    template: String,
+    /// Used instead when the artist is unknown.
+    title_template: String,
+}
+
+impl Default for QueryTemplate {
+    fn default() -> Self {
+        QueryTemplate::new("{artist} - {title}", "{title}")
+    }
+}
+
+impl QueryTemplate {
+    pub fn new(template: &str, title_template: &str) -> QueryTemplate {
+        QueryTemplate {
+            template: template.to_string(),
+            title_template: title_template.to_string(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> QueryTemplate {
+        QueryTemplate::new(&config.video_query, &config.video_query_title)
+    }
+
+    /// The query for a track, which must at least have a title.
+    pub fn render(&self, track: &TrackInfo) -> Result<String> {
+        let track = normalize(track).ok_or(Error::NoTrackPlaying)?;
+        let template = match track.artist {
+            Some(_) => &self.template,
+            None => &self.title_template,
+        };
+
+        let query = template
+            .replace("{artist}", track.artist.as_deref().unwrap_or(""))
+            .replace("{title}", &track.title)
+            .replace("{album}", track.album.as_deref().unwrap_or(""))
+            .replace("{featured}", &track.featured.join(" "));
+
+        Ok(collapse(&query))
+    }
+}
+
+/// Removes the decorations of a track's metadata. If the artist is unknown
+/// and the title looks like `Artist - Title`, as it happens with some web
+/// players, it's obtained from the title instead. It's split before
+/// cleaning the title, so that a title like `Live Forever` isn't taken as
+/// a decoration. Returns `None` when there is no title.
+pub fn normalize(track: &TrackInfo) -> Option<Normalized> {
+    let mut featured = Vec::new();
+    let mut artist = None;
+    if let Some(name) = &track.artist {
+        let (name, feat) = clean_artist(name);
+        featured.extend(feat);
+        artist = Some(name).filter(|name| !name.is_empty());
+    }
+
+    let mut title = clean_text(track.title.as_ref()?);
+    if artist.is_none() {
+        if let Some((name, rest)) = title.split_once(" - ") {
+            let (name, feat) = clean_artist(name);
+            if !name.is_empty() && !rest.trim().is_empty() {
+                featured.extend(feat);
+                artist = Some(name);
+                title = rest.to_string();
+            }
+        }
+    }
+
+    let (title, feat) = clean_title(&title);
+    if title.is_empty() {
+        return None;
+    }
+    featured.extend(feat);
+
+    let album = track
+        .album
+        .as_ref()
+        .map(|album| clean_title(album).0)
+        .filter(|album| !album.is_empty());
+    let mut seen = HashSet::new();
+    featured.retain(|name| seen.insert(name.to_lowercase()));
+
+    Some(Normalized {
+        artist,
+        title,
+        album,
+        featured,
+    })
+}
+
+/// Removes the decorations from a title, returning the featured artists
+/// found as well. The title is left as it was if nothing would remain.
+pub fn clean_title(title: &str) -> (String, Vec<String>) {
+    let text = clean_text(title);
+    let mut featured = Vec::new();
+
+    // The groups between brackets may be anywhere in the title.
+    let mut title = remove_groups(&text, &mut featured);
+
+    // The suffixes after a dash, starting from the last one.
+    while let Some(pos) = title.rfind(" - ") {
+        let suffix = &title[pos + 3..];
+        if let Some(names) = featuring(suffix, false) {
+            featured.extend(names);
+        } else if !is_decoration(suffix) {
+            break;
+        }
+        title.truncate(pos);
+    }
+
+    // And the featured artists without brackets, like `Song feat. X`.
+    let (title, names) = split_featuring(&title);
+    featured.extend(names);
+
+    let title = collapse(title.trim_end_matches(&['-', ' '][..]));
+    if title.is_empty() {
+        (collapse(&text), featured)
+    } else {
+        (title, featured)
+    }
+}
+
+/// Removes the featured artists from an artist, like `A feat. B` or
+/// `A (ft. B)`, which are returned as well.
+pub fn clean_artist(artist: &str) -> (String, Vec<String>) {
+    let mut featured = Vec::new();
+    let artist = remove_groups(&clean_text(artist), &mut featured);
+    let (artist, names) = split_featuring(&artist);
+    featured.extend(names);
+
+    (collapse(&artist), featured)
+}
+
+/// The keys that may identify a track when saving data about it, from the
+/// most to the least reliable: its ISRC, its ID if it's stable across
+/// sessions, and its normalized artist and title.
+pub fn track_keys(track: &TrackInfo) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Some(isrc) = &track.isrc {
+        let isrc = isrc.trim();
+        if !isrc.is_empty() {
+            keys.push(format!("isrc:{}", isrc.to_uppercase()));
+        }
+    }
+    if let Some(id) = track.id.as_deref().and_then(stable_id) {
+        keys.push(format!("id:{}", id));
+    }
+    if let Some(track) = normalize(track) {
+        let name = match track.artist {
+            Some(artist) => format!("{} - {}", artist, track.title),
+            None => track.title,
+        };
+        keys.push(format!("track:{}", name.to_lowercase()));
+    }
+
+    keys
+}
+
+/// The IDs that are D-Bus paths are usually just the position in the
+/// playlist, except for Spotify's, which are converted to the same URIs as
+/// in its web API.
+fn stable_id(id: &str) -> Option<String> {
+    let id = id.trim();
+    if let Some(spotify) = id.strip_prefix("/com/spotify/") {
+        Some(format!("spotify:{}", spotify.replace('/', ":")))
+    } else if id.is_empty() || id.starts_with('/') {
+        None
+    } else {
+        Some(id.to_string())
+    }
+}
+
+/// Replaces the Unicode characters that are usually written differently in
+/// each source, like typographic quotes, dashes, fullwidth forms or special
+/// spaces, and removes the invisible ones.
+pub fn clean_text(text: &str) -> String {
+    let mut clean = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}'
+            | '\u{00B4}' | '`' => clean.push('\''),
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' | '\u{00AB}'
+            | '\u{00BB}' => clean.push('"'),
+            '\u{2010}'..='\u{2015}' | '\u{2212}' | '\u{FE58}' => {
+                clean.push('-')
+            }
+            '\u{2026}' => clean.push_str("..."),
+            '\u{00A0}'
+            | '\u{2000}'..='\u{200A}'
+            | '\u{202F}'
+            | '\u{205F}'
+            | '\u{3000}' => clean.push(' '),
+            '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}' | '\u{00AD}' => {
+            }
+            // The fullwidth forms of the ASCII characters.
+            '\u{FF01}'..='\u{FF5E}' => {
+                clean.push(std::char::from_u32(c as u32 - 0xFEE0).unwrap_or(c))
+            }
+            c if c.is_control() => clean.push(' '),
+            c => clean.push(c),
+        }
+    }
+
+    collapse(&clean)
+}
+
+/// Removes the whitespace at the ends and the repeated one.
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The lowercase words in a text, ignoring punctuation.
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn is_decoration(text: &str) -> bool {
+    let words = words(text);
+    match words.first() {
+        None => false,
+        Some(first) if first == "live" => true,
+        Some(_) => {
+            words
+                .iter()
+                .all(|word| word.chars().all(|c| c.is_ascii_digit()))
+                || words
+                    .iter()
+                    .any(|word| DECORATION_WORDS.contains(&word.as_str()))
+        }
+    }
+}
+
+/// The artists in a text like `feat. A & B`. Between brackets, `with A` is
+/// common too.
+fn featuring(text: &str, brackets: bool) -> Option<Vec<String>> {
+    let text = text.trim();
+    let first = text.split_whitespace().next()?.to_lowercase();
+    let introduces =
+        FEAT_WORDS.contains(&first.as_str()) || (brackets && first == "with");
+    if !introduces {
+        return None;
+    }
+
+    let names = text[first.len()..]
+        .split(&[',', '&'][..])
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+    Some(names)
+}
+
+/// Splits a text at the first word that introduces the featured artists.
+fn split_featuring(text: &str) -> (String, Vec<String>) {
+    let mut pos = 0;
+    for word in text.split(' ') {
+        if pos > 0 && FEAT_WORDS.contains(&word.to_lowercase().as_str()) {
+            let names = featuring(&text[pos..], false).unwrap_or_default();
+            return (text[..pos].trim().to_string(), names);
+        }
+        pos += word.len() + 1;
+    }
+
+    (text.to_string(), Vec::new())
+}
+
+/// Removes the groups between brackets that are decorations or featured
+/// artists. The rest of them are kept, like in `(I Can't Get No)
+/// Satisfaction`.
+fn remove_groups(text: &str, featured: &mut Vec<String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(&['(', '['][..]) {
+        result.push_str(&rest[..start]);
+        let end = match group_end(&rest[start..]) {
+            Some(len) => start + len,
+            None => {
+                // Unbalanced brackets are left as they are.
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        let inner = &rest[start + 1..end - 1];
+        if let Some(names) = featuring(inner, true) {
+            featured.extend(names);
+        } else if !is_decoration(inner) {
+            result.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+
+    collapse(&result)
+}
+
+/// The length of the group between brackets at the start of the text,
+/// including the brackets.
+fn group_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn titles() {
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("Yesterday", "Yesterday", &[]),
+            ("Yesterday - Remastered 2009", "Yesterday", &[]),
+            ("Yesterday - 2009 Remaster", "Yesterday", &[]),
+            ("Yesterday (Remastered 2009)", "Yesterday", &[]),
+            ("Here Comes the Sun - 2019 Mix", "Here Comes the Sun", &[]),
+            ("Wonderwall - Remastered", "Wonderwall", &[]),
+            ("Heroes - 2017 Remaster", "Heroes", &[]),
+            (
+                "Bohemian Rhapsody - Live at Wembley '86",
+                "Bohemian Rhapsody",
+                &[],
+            ),
+            ("Bohemian Rhapsody - Live Aid", "Bohemian Rhapsody", &[]),
+            ("Hurt - Live", "Hurt", &[]),
+            ("Hurt (Live)", "Hurt", &[]),
+            ("Creep - Radio Edit", "Creep", &[]),
+            ("Creep [Radio Edit]", "Creep", &[]),
+            ("Africa - Single Version", "Africa", &[]),
+            ("Blue Monday '88 - 7\" Version", "Blue Monday '88", &[]),
+            ("Paint It Black - Mono", "Paint It Black", &[]),
+            ("Song - Bonus Track", "Song", &[]),
+            ("Song - Deluxe Edition", "Song", &[]),
+            ("Song - 2011", "Song", &[]),
+            ("Song [Explicit]", "Song", &[]),
+            ("Song (Official Music Video)", "Song", &[]),
+            ("Song (Official Video) [HD]", "Song", &[]),
+            ("Song (Lyrics)", "Song", &[]),
+            ("Song (feat. X)", "Song", &["X"]),
+            ("Song (feat. X) - Radio Edit", "Song", &["X"]),
+            ("Song [ft. X & Y]", "Song", &["X", "Y"]),
+            ("Song (with X)", "Song", &["X"]),
+            ("Song (Featuring X, Y)", "Song", &["X", "Y"]),
+            ("Song feat. X", "Song", &["X"]),
+            ("Song ft X - Remastered", "Song", &["X"]),
+            ("Song - feat. X", "Song", &["X"]),
+            (
+                "(I Can't Get No) Satisfaction - Mono Version",
+                "(I Can't Get No) Satisfaction",
+                &[],
+            ),
+            ("Song (Part 2)", "Song (Part 2)", &[]),
+            ("Anti-Hero", "Anti-Hero", &[]),
+            ("Song - Acoustic Session", "Song - Acoustic Session", &[]),
+            (
+                "Somebody That I Used to Know",
+                "Somebody That I Used to Know",
+                &[],
+            ),
+            ("Live and Let Die", "Live and Let Die", &[]),
+            ("Song (Live", "Song (Live", &[]),
+            ("Song ((Live))", "Song", &[]),
+            ("(Live)", "(Live)", &[]),
+            ("Remastered", "Remastered", &[]),
+            ("Song -", "Song", &[]),
+            ("  Song   (Remastered)  ", "Song", &[]),
+        ];
+
+        for (title, expected, featured) in cases {
+            let (clean, names) = clean_title(title);
+            assert_eq!(&clean, expected, "title {:?}", title);
+            assert_eq!(&names, featured, "featured in {:?}", title);
+        }
+    }
+
+    #[test]
+    fn artists() {
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("Daft Punk", "Daft Punk", &[]),
+            ("Simon & Garfunkel", "Simon & Garfunkel", &[]),
+            ("Earth, Wind & Fire", "Earth, Wind & Fire", &[]),
+            ("Calvin Harris feat. Rihanna", "Calvin Harris", &["Rihanna"]),
+            ("Calvin Harris ft. Rihanna", "Calvin Harris", &["Rihanna"]),
+            (
+                "Calvin Harris (feat. Rihanna)",
+                "Calvin Harris",
+                &["Rihanna"],
+            ),
+            ("A Featuring B & C", "A", &["B", "C"]),
+            ("Florence + The Machine", "Florence + The Machine", &[]),
+            ("Feat", "Feat", &[]),
+            ("  Björk\u{00A0}", "Björk", &[]),
+        ];
+
+        for (artist, expected, featured) in cases {
+            let (clean, names) = clean_artist(artist);
+            assert_eq!(&clean, expected, "artist {:?}", artist);
+            assert_eq!(&names, featured, "featured in {:?}", artist);
+        }
+    }
+
+    #[test]
+    fn unicode() {
+        let cases: &[(&str, &str)] = &[
+            ("Don\u{2019}t Stop Me Now", "Don't Stop Me Now"),
+            ("\u{201C}Heroes\u{201D}", "\"Heroes\""),
+            ("Song \u{2013} Remastered", "Song - Remastered"),
+            ("Song \u{2014} Live", "Song - Live"),
+            ("Wait\u{2026}", "Wait..."),
+            ("Non\u{00A0}breaking\u{3000}space", "Non breaking space"),
+            ("Zero\u{200B}width\u{FEFF}", "Zerowidth"),
+            ("\u{FF33}\u{FF4F}\u{FF4E}\u{FF47}\u{FF01}", "Song!"),
+            ("Line\nbreak\ttab", "Line break tab"),
+            ("Beyoncé", "Beyoncé"),
+            ("東京", "東京"),
+            ("Sigur Rós", "Sigur Rós"),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(&clean_text(text), expected, "text {:?}", text);
+        }
+
+        assert_eq!(clean_title("Song \u{2013} Remastered 2011").0, "Song");
+        assert_eq!(
+            clean_title("Song\u{FF08}Official Video\u{FF09}").0,
+            "Song"
+        );
+    }
+
+    fn track(
+        artist: Option<&str>,
+        title: Option<&str>,
+        album: Option<&str>,
+    ) -> TrackInfo {
+        TrackInfo {
+            artist: artist.map(String::from),
+            title: title.map(String::from),
+            album: album.map(String::from),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn normalized() {
+        let cases = &[
+            (
+                track(Some("Queen"), Some("Bohemian Rhapsody - Remastered 2011"), None),
+                Some(Normalized {
+                    artist: Some("Queen".into()),
+                    title: "Bohemian Rhapsody".into(),
+                    ..Normalized::default()
+                }),
+            ),
+            (
+                track(
+                    Some("Calvin Harris feat. Rihanna"),
+                    Some("This Is What You Came For (feat. Rihanna)"),
+                    Some("This Is What You Came For (Remixes)"),
+                ),
+                Some(Normalized {
+                    artist: Some("Calvin Harris".into()),
+                    title: "This Is What You Came For".into(),
+                    album: Some("This Is What You Came For (Remixes)".into()),
+                    featured: vec!["Rihanna".into()],
+                }),
+            ),
+            (
+                track(None, Some("Rick Astley - Never Gonna Give You Up (Official Video)"), None),
+                Some(Normalized {
+                    artist: Some("Rick Astley".into()),
+                    title: "Never Gonna Give You Up".into(),
+                    ..Normalized::default()
+                }),
+            ),
+            (
+                track(Some(" "), Some("Song"), Some("Album (Deluxe Edition)")),
+                Some(Normalized {
+                    title: "Song".into(),
+                    album: Some("Album".into()),
+                    ..Normalized::default()
+                }),
+            ),
+            (
+                track(None, Some("Oasis - Live Forever"), None),
+                Some(Normalized {
+                    artist: Some("Oasis".into()),
+                    title: "Live Forever".into(),
+                    ..Normalized::default()
+                }),
+            ),
+            (
+                track(None, Some("The Buggles \u{2013} Video Killed the Radio Star"), None),
+                Some(Normalized {
+                    artist: Some("The Buggles".into()),
+                    title: "Video Killed the Radio Star".into(),
+                    ..Normalized::default()
+                }),
+            ),
+            (
+                track(None, Some("Daft Punk - One More Time - Radio Edit"), None),
+                Some(Normalized {
+                    artist: Some("Daft Punk".into()),
+                    title: "One More Time".into(),
+                    ..Normalized::default()
+                }),
+            ),
+            (
+                track(
+                    Some("A feat. B & C"),
+                    Some("Song (feat. C) (ft. b) (with B)"),
+                    None,
+                ),
+                Some(Normalized {
+                    artist: Some("A".into()),
+                    title: "Song".into(),
+                    featured: vec!["B".into(), "C".into()],
+                    ..Normalized::default()
+                }),
+            ),
+            (track(Some("Artist"), None, None), None),
+            (track(Some("Artist"), Some(" \u{200B} "), None), None),
+        ];
+
+        for (track, expected) in cases {
+            assert_eq!(&normalize(track), expected, "track {:?}", track);
+        }
+    }
+
+    #[test]
+    fn templates() {
+        let official =
+            QueryTemplate::new("{artist} - {title} official video", "{title}");
+        let album =
+            QueryTemplate::new("{artist} {album} {title}", "{title} {album}");
+        let featured =
+            QueryTemplate::new("{artist} {featured} {title}", "{title}");
+        let cases = &[
+            (
+                QueryTemplate::default(),
+                track(
+                    Some("Queen"),
+                    Some("Bohemian Rhapsody - Live Aid"),
+                    None,
+                ),
+                "Queen - Bohemian Rhapsody",
+            ),
+            (
+                QueryTemplate::default(),
+                track(None, Some("Song (Lyrics)"), None),
+                "Song",
+            ),
+            (
+                official.clone(),
+                track(
+                    Some("Queen"),
+                    Some("Bohemian Rhapsody - Remastered 2011"),
+                    None,
+                ),
+                "Queen - Bohemian Rhapsody official video",
+            ),
+            (
+                official,
+                track(None, Some("Bohemian Rhapsody"), None),
+                "Bohemian Rhapsody",
+            ),
+            (
+                album.clone(),
+                track(Some("Queen"), Some("Bohemian Rhapsody"), None),
+                "Queen Bohemian Rhapsody",
+            ),
+            (
+                album,
+                track(None, Some("Song"), Some("Album")),
+                "Song Album",
+            ),
+            (
+                featured,
+                track(
+                    Some("Calvin Harris"),
+                    Some("Song (feat. Rihanna)"),
+                    None,
+                ),
+                "Calvin Harris Rihanna Song",
+            ),
+            (
+                QueryTemplate::new("{artist} {unknown}", "{title}"),
+                track(Some("Queen"), Some("Song"), None),
+                "Queen {unknown}",
+            ),
+        ];
+
+        for (template, track, expected) in cases {
+            assert_eq!(
+                &template.render(track).unwrap(),
+                expected,
+                "template {:?} for {:?}",
+                template,
+                track
+            );
+        }
+        assert!(QueryTemplate::default()
+            .render(&TrackInfo::default())
+            .is_err());
+    }
+
+    #[test]
+    fn keys() {
+        let mut track =
+            track(Some("Rick Astley"), Some("Never Gonna Give You Up"), None);
+        assert_eq!(
+            track_keys(&track),
+            ["track:rick astley - never gonna give you up"]
+        );
+
+        track.title = Some("Never Gonna Give You Up - Remastered".into());
+        track.id = Some("/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC".into());
+        track.isrc = Some(" gbarl9300135 ".into());
+        assert_eq!(
+            track_keys(&track),
+            [
+                "isrc:GBARL9300135",
+                "id:spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+                "track:rick astley - never gonna give you up",
+            ]
+        );
+
+        // The position in a playlist isn't stable.
+        track.id = Some("/org/mpris/MediaPlayer2/Track/3".into());
+        track.isrc = None;
+        assert_eq!(track_keys(&track).len(), 1);
+
+        assert!(track_keys(&TrackInfo::default()).is_empty());
+        let title = TrackInfo {
+            title: Some("Song".into()),
+            ..TrackInfo::default()
+        };
+        assert_eq!(track_keys(&title), ["track:song"]);
+    }
+}
@@ -0,0 +1,759 @@
+//! Finds the video to be played for a track, combining the configured
+//! provider with the scorer of the candidates, the user's overrides and the
+//! cache of the previous results. The official music videos found in
+//! MusicBrainz are ranked first. The videos may also be
+//! downloaded, in which case the local copy is played.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::data::{Res, ResKind};
+use crate::error::{Error, Result};
+use crate::video::cache::{self, Cache, Limits};
+use crate::video::download::{self, Downloads};
+use crate::video::musicbrainz::MusicBrainz;
+use crate::video::overrides::{self, same_video, Overrides};
+use crate::video::score::Scorer;
+use crate::video::{init_video_provider, Candidate, Provider, VideoProvider};
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use log::{info, warn};
+use reqwest::Url;
+
+/// The chosen candidate, and the URL that the player should open for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resolved {
+    pub candidate: Candidate,
+    pub url: String,
+}
+
+pub struct Resolver {
+    provider: Box<dyn VideoProvider>,
+    scorer: Scorer,
+    overrides: Mutex<Overrides>,
+    /// Where the overrides are saved after being modified, if anywhere.
+    overrides_path: Option<String>,
+    cache: Mutex<Cache>,
+    cache_path: Option<String>,
+    /// Shared with the threads that download the videos.
+    downloads: Option<Arc<Mutex<Downloads>>>,
+    downloads_path: Option<String>,
+    /// Only the downloaded videos are played.
+    offline: bool,
+    musicbrainz: Option<MusicBrainz>,
+}
+
+impl Resolver {
+    pub fn new(provider: Box<dyn VideoProvider>, scorer: Scorer) -> Resolver {
+        Resolver {
+            provider,
+            scorer,
+            overrides: Mutex::new(Overrides::default()),
+            overrides_path: None,
+            cache: Mutex::new(Cache::new(Limits::default())),
+            cache_path: None,
+            downloads: None,
+            downloads_path: None,
+            offline: false,
+            musicbrainz: None,
+        }
+    }
+
+    /// Uses the given overrides, which will be saved to the path when
+    /// they're modified.
+    pub fn with_overrides(
+        mut self,
+        overrides: Overrides,
+        path: Option<String>,
+    ) -> Resolver {
+        self.overrides = Mutex::new(overrides);
+        self.overrides_path = path;
+        self
+    }
+
+    /// Uses the given cache, which will be saved to the path when it's
+    /// modified.
+    pub fn with_cache(
+        mut self,
+        cache: Cache,
+        path: Option<String>,
+    ) -> Resolver {
+        self.cache = Mutex::new(cache);
+        self.cache_path = path;
+        self
+    }
+
+    /// Plays the downloaded videos, and downloads the new ones in the
+    /// background. The index will be saved to the path when it's modified.
+    pub fn with_downloads(
+        mut self,
+        downloads: Downloads,
+        path: Option<String>,
+    ) -> Resolver {
+        self.downloads = Some(Arc::new(Mutex::new(downloads)));
+        self.downloads_path = path;
+        self
+    }
+
+    /// Never searches for the videos, only playing the downloaded ones.
+    pub fn with_offline(mut self, offline: bool) -> Resolver {
+        self.offline = offline;
+        self
+    }
+
+    /// Ranks the official music videos found in MusicBrainz first.
+    pub fn with_musicbrainz(mut self, musicbrainz: MusicBrainz) -> Resolver {
+        self.musicbrainz = Some(musicbrainz);
+        self
+    }
+
+    /// Returns `None` if the provider is disabled in the config.
+    pub fn from_config(config: &Config) -> Result<Option<Resolver>> {
+        let provider =
+            init_video_provider(config.video_provider.clone(), config)?;
+
+        let provider = match provider {
+            Some(provider) => provider,
+            None => return Ok(None),
+        };
+
+        let overrides_path =
+            Res::new(ResKind::Data(String::from(overrides::DATA_FILE)))?;
+        let cache_path =
+            Res::new(ResKind::Data(String::from(cache::DATA_FILE)))?;
+        let cache =
+            Cache::load_or_new(&cache_path, Limits::from_config(config));
+        let mut resolver =
+            Resolver::new(provider, Scorer::from_config(config))
+                .with_overrides(
+                    Overrides::load(&overrides_path)?,
+                    Some(overrides_path.path),
+                )
+                .with_cache(cache, Some(cache_path.path))
+                .with_offline(config.offline);
+        if config.download || config.offline {
+            let (downloads, path) = Downloads::from_config(config)?;
+            resolver = resolver.with_downloads(downloads, Some(path));
+        }
+        // The local files can't be matched with the videos in MusicBrainz.
+        if !matches!(config.video_provider, Provider::Library) {
+            if let Some(musicbrainz) = MusicBrainz::from_config(config)? {
+                resolver = resolver.with_musicbrainz(musicbrainz);
+            }
+        }
+
+        Ok(Some(resolver))
+    }
+
+    /// Looks for the best video for the track, unless it was downloaded,
+    /// one was pinned or it's cached. If the stream URL of a candidate
+    /// can't be obtained, the next one is tried. Returns `None` if none of
+    /// them has the minimum score, or if it's not downloaded in offline
+    /// mode.
+    pub fn resolve(&self, track: &TrackInfo) -> Result<Option<Resolved>> {
+        if let Some(resolved) = self.resolve_downloaded(track) {
+            info!("Using the downloaded video: {}", resolved.url);
+            return Ok(Some(resolved));
+        }
+        if self.offline {
+            info!("The video isn't downloaded and offline mode is enabled");
+            return Ok(None);
+        }
+
+        let resolved = self.resolve_online(track)?;
+        if let Some(resolved) = &resolved {
+            self.download(track, resolved);
+        }
+
+        Ok(resolved)
+    }
+
+    fn resolve_online(&self, track: &TrackInfo) -> Result<Option<Resolved>> {
+        let pinned = self
+            .overrides
+            .lock()
+            .unwrap()
+            .pinned(track)
+            .map(String::from);
+        // A pinned video that failed to play is skipped until the failure
+        // expires.
+        let pinned =
+            pinned.filter(|url| !self.cache.lock().unwrap().is_failed(url));
+        if let Some(url) = pinned {
+            info!("Using the video pinned to the track: {}", url);
+            return Ok(Some(self.resolve_pinned(url)));
+        }
+
+        if let Some(resolved) = self.resolve_cached(track) {
+            info!("Using the cached video: {}", resolved.candidate.url);
+            return Ok(Some(resolved));
+        }
+
+        let resolved = self.search(track)?;
+        if let Some(resolved) = &resolved {
+            let mut cache = self.cache.lock().unwrap();
+            cache.insert(track, &resolved.candidate);
+            cache.set_stream(track, &resolved.url);
+            self.save_cache(&cache);
+        }
+
+        Ok(resolved)
+    }
+
+    fn search(&self, track: &TrackInfo) -> Result<Option<Resolved>> {
+        // MusicBrainz is slow because of its rate limit, so it's queried
+        // while the provider searches.
+        let (official, searched) = thread::scope(|s| {
+            let official = s.spawn(|| self.official_videos(track));
+            let searched = self.provider.search(track);
+            (official.join().unwrap(), searched)
+        });
+        let searched = match searched {
+            Ok(searched) => searched,
+            Err(e) if !official.is_empty() => {
+                warn!("Couldn't search for the video: {}", e);
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+
+        let official = self.allowed(track, official);
+        let candidates = self.allowed(track, searched);
+        let found = candidates.len() + official.len();
+        let ranked =
+            self.scorer.rank_with_official(track, candidates, &official);
+        if ranked.is_empty() {
+            info!("None of the {} videos found has the minimum score", found);
+            return Ok(None);
+        }
+
+        let mut error = None;
+        for candidate in ranked {
+            match self.provider.stream_url(&candidate) {
+                Ok(url) => return Ok(Some(Resolved { candidate, url })),
+                Err(e) => {
+                    warn!(
+                        "Couldn't obtain the stream of {}: {}",
+                        candidate.url, e
+                    );
+                    error = Some(e);
+                }
+            }
+        }
+
+        // The ranking wasn't empty, so the error will be set.
+        Err(error.unwrap())
+    }
+
+    /// The official music videos of the track, if MusicBrainz is enabled.
+    /// It's only an improvement, so the errors are ignored.
+    fn official_videos(&self, track: &TrackInfo) -> Vec<Candidate> {
+        let musicbrainz = match &self.musicbrainz {
+            Some(musicbrainz) => musicbrainz,
+            None => return Vec::new(),
+        };
+
+        musicbrainz.videos(track).unwrap_or_else(|e| {
+            warn!("Couldn't look for the video in MusicBrainz: {}", e);
+            Vec::new()
+        })
+    }
+
+    /// Removes the candidates blocked for the track or that failed to play.
+    fn allowed(
+        &self,
+        track: &TrackInfo,
+        mut candidates: Vec<Candidate>,
+    ) -> Vec<Candidate> {
+        let cache = self.cache.lock().unwrap();
+        let overrides = self.overrides.lock().unwrap();
+        candidates.retain(|c| {
+            !overrides.is_blocked(track, &c.url) && !cache.is_failed(&c.url)
+        });
+        candidates
+    }
+
+    /// The stream URL is obtained again if it expired. The entry is removed
+    /// if the video has been blocked since or if it's no longer available.
+    /// The cache isn't locked while the stream is obtained, since it may
+    /// take a while.
+    fn resolve_cached(&self, track: &TrackInfo) -> Option<Resolved> {
+        let cached = self.cache.lock().unwrap().get(track)?;
+        let candidate = cached.candidate;
+        let blocked = self
+            .overrides
+            .lock()
+            .unwrap()
+            .is_blocked(track, &candidate.url);
+
+        let resolved = if blocked {
+            None
+        } else if let Some(url) = cached.stream {
+            Some(Resolved { candidate, url })
+        } else {
+            match self.provider.stream_url(&candidate) {
+                Ok(url) => Some(Resolved { candidate, url }),
+                Err(e) => {
+                    warn!(
+                        "Couldn't obtain the stream of {}: {}",
+                        candidate.url, e
+                    );
+                    None
+                }
+            }
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        match &resolved {
+            Some(resolved) => cache.set_stream(track, &resolved.url),
+            None => cache.remove(track),
+        }
+        self.save_cache(&cache);
+
+        resolved
+    }
+
+    /// A video already found for another song by the same artist, which
+    /// can be shown while there's none for the track.
+    pub fn artist_video(&self, track: &TrackInfo) -> Option<Resolved> {
+        let cached = self.cache.lock().unwrap().by_artist(track)?;
+        let candidate = cached.candidate;
+        let url = match cached.stream {
+            Some(url) => url,
+            None => self.provider.stream_url(&candidate).ok()?,
+        };
+
+        Some(Resolved { candidate, url })
+    }
+
+    /// The downloaded video is ignored if it has been blocked since, or if
+    /// a different one was pinned.
+    fn resolve_downloaded(&self, track: &TrackInfo) -> Option<Resolved> {
+        let mut downloads = self.downloads.as_ref()?.lock().unwrap();
+        let (video, path) = downloads.get(track)?;
+        if downloads.should_save() {
+            if let Err(e) = self.save_downloads(&mut downloads) {
+                warn!("Couldn't save the downloads: {}", e);
+            }
+        }
+        let usable = {
+            let overrides = self.overrides.lock().unwrap();
+            !overrides.is_blocked(track, &video)
+                && overrides
+                    .pinned(track)
+                    .is_none_or(|pinned| same_video(pinned, &video))
+        };
+        if !usable {
+            return None;
+        }
+
+        let url = Url::from_file_path(&path).ok()?.to_string();
+        let candidate = Candidate {
+            url: video.clone(),
+            title: video,
+            ..Candidate::default()
+        };
+        Some(Resolved { candidate, url })
+    }
+
+    /// Downloads the video in a separate thread, if enabled. The local
+    /// videos and the ones whose stream couldn't be obtained are skipped.
+    fn download(&self, track: &TrackInfo, resolved: &Resolved) {
+        let downloads = match &self.downloads {
+            Some(downloads) => Arc::clone(downloads),
+            None => return,
+        };
+        if resolved.url.starts_with("file://")
+            || resolved.url == resolved.candidate.url
+        {
+            return;
+        }
+        let video = resolved.candidate.url.clone();
+        let file = match downloads.lock().unwrap().start(track, &video) {
+            Some(file) => file,
+            None => return,
+        };
+
+        let track = track.clone();
+        let stream = resolved.url.clone();
+        let index = self.downloads_path.clone();
+        thread::spawn(move || {
+            let result = download::fetch(&stream, &file);
+            let mut downloads = downloads.lock().unwrap();
+            match result {
+                Ok(file) => downloads.insert(&track, &video, &file),
+                Err(e) => {
+                    warn!("Couldn't download {}: {}", video, e);
+                    downloads.cancel(&video);
+                    return;
+                }
+            }
+            if let Some(index) = index {
+                if let Err(e) = downloads.save(&index) {
+                    warn!("Couldn't save the downloads: {}", e);
+                }
+            }
+        });
+    }
+
+    /// The pinned videos may come from a different provider, so their URL
+    /// is played directly if the stream can't be obtained.
+    fn resolve_pinned(&self, url: String) -> Resolved {
+        let candidate = Candidate {
+            url: url.clone(),
+            title: url,
+            ..Candidate::default()
+        };
+        let url = self.provider.stream_url(&candidate).unwrap_or_else(|e| {
+            warn!("Couldn't obtain the stream of {}: {}", candidate.url, e);
+            candidate.url.clone()
+        });
+
+        Resolved { candidate, url }
+    }
+
+    /// Always plays the given video for the track from now on.
+    pub fn pin(&self, track: &TrackInfo, url: &str) -> Result<()> {
+        let mut overrides = self.overrides.lock().unwrap();
+        overrides.pin(track, url)?;
+        self.save_overrides(&overrides)
+    }
+
+    /// Never plays the given video for the track again.
+    pub fn block(&self, track: &TrackInfo, url: &str) -> Result<()> {
+        {
+            let mut overrides = self.overrides.lock().unwrap();
+            overrides.block(track, url)?;
+            self.save_overrides(&overrides)?;
+        }
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.remove(track);
+            self.save_cache(&cache);
+        }
+
+        if let Some(downloads) = &self.downloads {
+            let mut downloads = downloads.lock().unwrap();
+            let downloaded = downloads.video(track);
+            if downloaded.is_some_and(|video| same_video(video, url)) {
+                downloads.remove(track);
+                self.save_downloads(&mut downloads)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remembers that the video couldn't be played, so that it's not
+    /// chosen again for any track. Resolving the track again will return
+    /// the next candidate.
+    pub fn report_failure(&self, track: &TrackInfo, resolved: &Resolved) {
+        let video = &resolved.candidate.url;
+        warn!("The video {} couldn't be played", video);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.set_failed(track, video);
+            self.save_cache(&cache);
+        }
+
+        // The downloaded file may be corrupt as well.
+        if let Some(downloads) = &self.downloads {
+            let mut downloads = downloads.lock().unwrap();
+            let downloaded = downloads.video(track);
+            if downloaded.is_some_and(|other| same_video(other, video)) {
+                downloads.remove(track);
+                if let Err(e) = self.save_downloads(&mut downloads) {
+                    warn!("Couldn't save the downloads: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Never removes the video downloaded for the track, or allows it to
+    /// be removed again.
+    pub fn pin_download(&self, track: &TrackInfo, pinned: bool) -> Result<()> {
+        let downloads = self.downloads.as_ref().ok_or(Error::Unsupported)?;
+        let mut downloads = downloads.lock().unwrap();
+        downloads.pin(track, pinned)?;
+        self.save_downloads(&mut downloads)
+    }
+
+    fn save_downloads(&self, downloads: &mut Downloads) -> Result<()> {
+        match &self.downloads_path {
+            Some(path) => downloads.save(path),
+            None => Ok(()),
+        }
+    }
+
+    fn save_overrides(&self, overrides: &Overrides) -> Result<()> {
+        match &self.overrides_path {
+            Some(path) => overrides.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// The cache isn't essential, so it's fine if it can't be saved.
+    fn save_cache(&self, cache: &Cache) {
+        if let Some(path) = &self.cache_path {
+            if let Err(e) = cache.save(path) {
+                warn!("Couldn't save the cache: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{temp_dir, FakeProvider, MockServer};
+
+    use std::fs;
+    use std::time;
+
+    /// Returns the same candidates for any track. Their streams are
+    /// available unless their URL is `broken`.
+    fn fake(candidates: Vec<Candidate>) -> FakeProvider {
+        FakeProvider {
+            broken: vec![String::from("broken")],
+            stream_prefix: String::from("stream-"),
+            ..FakeProvider::new(candidates)
+        }
+    }
+
+    /// Finds a single video, whose stream is served by a mock server.
+    fn remote(url: &str) -> FakeProvider {
+        FakeProvider {
+            stream_prefix: format!("{}/", url),
+            ..FakeProvider::new(vec![candidate(
+                "video",
+                "Song (Official Video)",
+                200,
+            )])
+        }
+    }
+
+    fn candidate(url: &str, title: &str, secs: u64) -> Candidate {
+        Candidate {
+            url: url.to_string(),
+            title: title.to_string(),
+            duration: Some(time::Duration::from_secs(secs)),
+            ..Candidate::default()
+        }
+    }
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Artist")),
+            title: Some(String::from("Song")),
+            duration: Some(time::Duration::from_secs(200)),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn best_candidate() {
+        let resolver = Resolver::new(
+            Box::new(fake(vec![
+                candidate("ok-lyrics", "Song (Lyrics)", 200),
+                candidate("ok-video", "Song (Official Video)", 210),
+            ])),
+            Scorer::default(),
+        );
+
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.candidate.url, "ok-video");
+        assert_eq!(resolved.url, "stream-ok-video");
+    }
+
+    #[test]
+    fn unavailable_streams() {
+        let resolver = Resolver::new(
+            Box::new(fake(vec![
+                candidate("ok-second", "Song", 200),
+                candidate("broken", "Song (Official Video)", 200),
+            ])),
+            Scorer::default(),
+        );
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.url, "stream-ok-second");
+
+        let resolver = Resolver::new(
+            Box::new(fake(vec![candidate("broken", "Song", 200)])),
+            Scorer::default(),
+        );
+        assert!(resolver.resolve(&track()).is_err());
+    }
+
+    #[test]
+    fn minimum_score() {
+        let resolver = Resolver::new(
+            Box::new(fake(vec![
+                candidate("ok-cover", "Song (Cover)", 500),
+                candidate("ok-karaoke", "Song Karaoke", 400),
+            ])),
+            Scorer::default(),
+        );
+        assert_eq!(resolver.resolve(&track()).unwrap(), None);
+
+        let resolver =
+            Resolver::new(Box::new(fake(Vec::new())), Scorer::default());
+        assert_eq!(resolver.resolve(&track()).unwrap(), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let dir = temp_dir("resolver-overrides");
+        let path = dir.join("overrides.json").to_string_lossy().into_owned();
+        let resolver = Resolver::new(
+            Box::new(fake(vec![
+                candidate("ok-best", "Song (Official Video)", 200),
+                candidate("ok-other", "Song", 200),
+            ])),
+            Scorer::default(),
+        )
+        .with_overrides(Overrides::default(), Some(path.clone()));
+
+        resolver.block(&track(), "ok-best").unwrap();
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.url, "stream-ok-other");
+
+        // The pinned videos aren't searched, and they're played directly if
+        // the provider doesn't support them.
+        resolver.pin(&track(), "broken").unwrap();
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.url, "broken");
+        resolver.pin(&track(), "ok-pinned").unwrap();
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.url, "stream-ok-pinned");
+
+        let saved = Overrides::load(&path).unwrap();
+        assert_eq!(saved.pinned(&track()), Some("ok-pinned"));
+        assert!(saved.is_blocked(&track(), "ok-best"));
+    }
+
+    #[test]
+    fn cache() {
+        let provider = fake(vec![
+            candidate("ok-video", "Song (Official Video)", 200),
+            candidate("ok-other", "Song", 200),
+        ]);
+        let dir = temp_dir("resolver-cache");
+        let path = dir.join("cache.json").to_string_lossy().into_owned();
+        let limits = Limits {
+            stream_ttl: time::Duration::from_secs(0),
+            ..Limits::default()
+        };
+        let resolver =
+            Resolver::new(Box::new(provider.clone()), Scorer::default())
+                .with_cache(Cache::new(limits.clone()), Some(path.clone()));
+
+        let first = resolver.resolve(&track()).unwrap().unwrap();
+        let second = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(first, second);
+        assert_eq!(provider.searches(), 1);
+        let mut saved = Cache::load(&path, Limits::default()).unwrap();
+        assert_eq!(saved.get(&track()).unwrap().candidate.url, "ok-video");
+
+        // Blocking the cached video searches again.
+        resolver.block(&track(), "ok-video").unwrap();
+        let third = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(third.candidate.url, "ok-other");
+        assert_eq!(provider.searches(), 2);
+    }
+
+    #[test]
+    fn downloads() {
+        let dir = temp_dir("resolver-downloads");
+        let videos = dir.join("downloads");
+        fs::create_dir(&videos).unwrap();
+        let path = dir.join("downloads.json").to_string_lossy().into_owned();
+        let server = MockServer::new(|_| (200, "video/mp4", "x".repeat(100)));
+        let provider = remote(&server.url);
+        let resolver =
+            Resolver::new(Box::new(provider.clone()), Scorer::default())
+                .with_downloads(
+                    Downloads::new(videos.clone(), 1000),
+                    Some(path.clone()),
+                );
+
+        // The stream is played while it's downloaded in the background.
+        let streamed = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(streamed.url, format!("{}/video", server.url));
+        let start = time::Instant::now();
+        let local = loop {
+            if let Some(local) = resolver.resolve_downloaded(&track()) {
+                break local;
+            }
+            assert!(start.elapsed() < time::Duration::from_secs(5));
+            thread::sleep(time::Duration::from_millis(10));
+        };
+        assert!(local.url.starts_with("file://"));
+        assert_eq!(local.candidate.url, "video");
+        assert_eq!(resolver.resolve(&track()).unwrap(), Some(local.clone()));
+        resolver.pin_download(&track(), true).unwrap();
+
+        // In offline mode, only the downloaded videos are played.
+        let downloads = Downloads::load(&path, videos.clone(), 1000).unwrap();
+        assert!(downloads.is_pinned(&track()));
+        let offline =
+            Resolver::new(Box::new(provider.clone()), Scorer::default())
+                .with_downloads(downloads, None)
+                .with_offline(true);
+        assert_eq!(offline.resolve(&track()).unwrap(), Some(local.clone()));
+        let mut other = track();
+        other.title = Some(String::from("Other"));
+        assert_eq!(offline.resolve(&other).unwrap(), None);
+        assert_eq!(provider.searches(), 1);
+
+        // Blocking the downloaded video removes it.
+        resolver.block(&track(), "video").unwrap();
+        assert_eq!(fs::read_dir(&videos).unwrap().count(), 0);
+        assert!(resolver.pin_download(&track(), true).is_err());
+        let plain = Resolver::new(Box::new(provider), Scorer::default());
+        assert!(plain.pin_download(&track(), true).is_err());
+    }
+
+    #[test]
+    fn official_video() {
+        const SEARCH: &str = r#"{"recordings": [
+            {"id": "video", "score": 100, "title": "Song", "video": true}
+        ]}"#;
+        const VIDEO: &str = r#"{
+            "id": "video",
+            "title": "Song",
+            "video": true,
+            "relations": [{
+                "type": "free streaming",
+                "target-type": "url",
+                "url": {"resource": "https://youtu.be/official"}
+            }]
+        }"#;
+        let server = MockServer::new(|req| match req.route() {
+            "/ws/2/recording" => (200, "application/json", SEARCH.into()),
+            "/ws/2/recording/video" => (200, "application/json", VIDEO.into()),
+            _ => (404, "application/json", "{}".into()),
+        });
+        let musicbrainz =
+            MusicBrainz::new(&server.url, time::Duration::from_secs(0))
+                .unwrap();
+        let provider = remote("http://videos");
+        let resolver =
+            Resolver::new(Box::new(provider.clone()), Scorer::default())
+                .with_musicbrainz(musicbrainz);
+
+        // The official video outranks the provider's, even though it
+        // doesn't have a duration to compare.
+        let official = "https://www.youtube.com/watch?v=official";
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.candidate.url, official);
+        assert_eq!(provider.searches(), 1);
+
+        // It's skipped once it's blocked.
+        resolver.block(&track(), official).unwrap();
+        let resolved = resolver.resolve(&track()).unwrap().unwrap();
+        assert_eq!(resolved.candidate.url, "video");
+        assert_eq!(provider.searches(), 2);
+    }
+}
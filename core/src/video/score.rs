@@ -0,0 +1,396 @@
+//! Ranks the candidates returned by a provider. The first search result is
+//! often a lyric video, a live performance or a cover, so the candidates are
+//! scored by how much their duration differs from the track's, whether they
+//! were uploaded by an official channel, and the words in their title.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::video::overrides::same_video;
+use crate::video::query::{self, words};
+use crate::video::Candidate;
+
+use std::time;
+
+/// The duration difference from which the candidates are penalized rather
+/// than rewarded, since music videos are often longer than the song.
+const DURATION_TOLERANCE: time::Duration = time::Duration::from_secs(60);
+
+/// The words in a title that usually mean that it's not the music video.
+/// The ones in the same group are only penalized once.
+const UNWANTED: &[&[&str]] = &[
+    &["live"],
+    &["cover"],
+    &["lyric", "lyrics"],
+    &["reaction", "reacts", "reacting"],
+    &["karaoke"],
+    &["nightcore"],
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scorer {
+    /// For a candidate with the same duration as the track. It decreases
+    /// linearly with the difference, becoming negative after a minute.
+    pub duration: f32,
+    /// For a channel that belongs to the artist, or that is official or
+    /// VEVO.
+    pub official_channel: f32,
+    /// For a title that includes `official video`.
+    pub official_title: f32,
+    /// Subtracted for each unwanted kind of video, like live performances,
+    /// covers or lyric videos.
+    pub unwanted: f32,
+    /// The candidates with a lower score are discarded.
+    pub min_score: f32,
+}
+
+/// These are also the defaults in the config file.
+impl Default for Scorer {
+    fn default() -> Self {
+        Scorer {
+            duration: 3.0,
+            official_channel: 2.0,
+            official_title: 1.5,
+            unwanted: 3.0,
+            min_score: -1.0,
+        }
+    }
+}
+
+impl Scorer {
+    pub fn from_config(config: &Config) -> Scorer {
+        Scorer {
+            duration: config.score_duration,
+            official_channel: config.score_official_channel,
+            official_title: config.score_official_title,
+            unwanted: config.score_unwanted,
+            min_score: config.min_score,
+        }
+    }
+
+    pub fn score(&self, track: &TrackInfo, candidate: &Candidate) -> f32 {
+        let mut score = 0.0;
+
+        if let (Some(expected), Some(actual)) =
+            (track.duration, candidate.duration)
+        {
+            score += self.duration * duration_score(expected, actual);
+        }
+
+        let artist = query::normalize(track).and_then(|track| track.artist);
+        if let Some(channel) = &candidate.channel {
+            if is_official_channel(channel, artist.as_deref()) {
+                score += self.official_channel;
+            }
+        }
+
+        let title = words(&candidate.title);
+        if title.windows(2).any(|pair| pair == ["official", "video"])
+            || title
+                .windows(3)
+                .any(|w| w == ["official", "music", "video"])
+        {
+            score += self.official_title;
+        }
+
+        // The words that are part of the track's own metadata aren't
+        // penalized, like for a live album.
+        let known: Vec<String> = [&track.title, &track.album, &track.artist]
+            .iter()
+            .filter_map(|text| text.as_deref())
+            .flat_map(words)
+            .collect();
+        for group in UNWANTED {
+            let found = group.iter().any(|word| {
+                title.iter().any(|w| w == word)
+                    && !known.iter().any(|w| w == word)
+            });
+            if found {
+                score -= self.unwanted;
+            }
+        }
+
+        score
+    }
+
+    /// Sorts the candidates from best to worst, removing the ones below the
+    /// minimum score. The original order is kept for the same score, since
+    /// it's the relevance of the search.
+    pub fn rank(
+        &self,
+        track: &TrackInfo,
+        candidates: Vec<Candidate>,
+    ) -> Vec<Candidate> {
+        self.sort(track, candidates, self.min_score)
+    }
+
+    /// Like `rank`, but the official videos are placed first, sorted by
+    /// their score and without needing the minimum one. If the provider
+    /// found them as well, its candidates are kept, since they have more
+    /// information.
+    pub fn rank_with_official(
+        &self,
+        track: &TrackInfo,
+        candidates: Vec<Candidate>,
+        official: &[Candidate],
+    ) -> Vec<Candidate> {
+        let is_official = |candidate: &Candidate| {
+            official
+                .iter()
+                .any(|video| same_video(&video.url, &candidate.url))
+        };
+        let (mut found, others): (Vec<_>, Vec<_>) =
+            candidates.into_iter().partition(is_official);
+        for video in official {
+            if !found.iter().any(|c| same_video(&c.url, &video.url)) {
+                found.push(video.clone());
+            }
+        }
+
+        let mut ranked = self.sort(track, found, f32::NEG_INFINITY);
+        ranked.extend(self.rank(track, others));
+        ranked
+    }
+
+    /// Sorts the candidates by their score, discarding the ones below the
+    /// minimum.
+    fn sort(
+        &self,
+        track: &TrackInfo,
+        candidates: Vec<Candidate>,
+        min_score: f32,
+    ) -> Vec<Candidate> {
+        let mut scored: Vec<(f32, Candidate)> = candidates
+            .into_iter()
+            .map(|candidate| (self.score(track, &candidate), candidate))
+            .filter(|(score, _)| *score >= min_score)
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        scored.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+}
+
+/// From 1 for the same duration to -1 for twice the tolerance or more.
+fn duration_score(expected: time::Duration, actual: time::Duration) -> f32 {
+    let diff = expected.abs_diff(actual);
+    let ratio = diff.as_secs_f32() / DURATION_TOLERANCE.as_secs_f32();
+
+    (1.0 - ratio).max(-1.0)
+}
+
+/// Whether the channel is the artist's own, or an official one like VEVO's.
+/// The spaces are ignored because channel names like `RickAstleyVEVO` often
+/// don't have them.
+///
+/// The auto-generated `Artist - Topic` channels only have the audio with a
+/// static image, so they aren't considered official.
+fn is_official_channel(channel: &str, artist: Option<&str>) -> bool {
+    let channel = words(channel);
+    if channel.last().map(String::as_str) == Some("topic") {
+        return false;
+    }
+
+    let channel = channel.concat();
+    let artist = artist
+        .map(|artist| words(artist).concat())
+        .filter(|artist| !artist.is_empty());
+    channel.ends_with("vevo")
+        || channel.contains("official")
+        || artist.is_some_and(|artist| channel.contains(&artist))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Rick Astley")),
+            title: Some(String::from(
+                "Never Gonna Give You Up - Remastered 2022",
+            )),
+            duration: Some(time::Duration::from_secs(213)),
+            ..TrackInfo::default()
+        }
+    }
+
+    fn candidate(title: &str, channel: &str, secs: u64) -> Candidate {
+        Candidate {
+            url: title.to_string(),
+            title: title.to_string(),
+            channel: Some(channel.to_string()).filter(|c| !c.is_empty()),
+            duration: Some(time::Duration::from_secs(secs))
+                .filter(|d| d.as_secs() > 0),
+            thumbnail: None,
+        }
+    }
+
+    #[test]
+    fn durations() {
+        let secs = time::Duration::from_secs;
+        let cases = &[
+            (213, 213, 1.0),
+            (213, 243, 0.5),
+            (243, 213, 0.5),
+            (200, 260, 0.0),
+            (200, 320, -1.0),
+            (200, 3600, -1.0),
+        ];
+
+        for (expected, actual, score) in cases {
+            let result = duration_score(secs(*expected), secs(*actual));
+            assert!(
+                (result - score).abs() < 0.001,
+                "{} and {}: {}",
+                expected,
+                actual,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn channels() {
+        let cases = &[
+            ("RickAstleyVEVO", Some("Rick Astley"), true),
+            ("Rick Astley", Some("Rick Astley"), true),
+            ("Rick Astley - Topic", Some("Rick Astley"), false),
+            ("Queen Official", Some("Rick Astley"), true),
+            ("VEVO", None, true),
+            ("Lyrics Channel", Some("Rick Astley"), false),
+            ("Random Uploader", None, false),
+            ("Random Uploader", Some("!!!"), false),
+        ];
+
+        for (channel, artist, official) in cases {
+            assert_eq!(
+                is_official_channel(channel, *artist),
+                *official,
+                "{} by {:?}",
+                channel,
+                artist
+            );
+        }
+    }
+
+    #[test]
+    fn scores() {
+        let scorer = Scorer::default();
+        let cases = &[
+            (
+                "Rick Astley - Never Gonna Give You Up (Official Music Video)",
+                "Rick Astley",
+                213,
+                6.5,
+            ),
+            ("Rick Astley - Never Gonna Give You Up", "", 0, 0.0),
+            ("Never Gonna Give You Up (Lyrics)", "Lyrics Hub", 213, 0.0),
+            ("Never Gonna Give You Up - Live 1987", "Fan", 273, -3.0),
+            ("Never Gonna Give You Up (Cover) [Lyric Video]", "", 0, -6.0),
+            ("NIGHTCORE - Never Gonna Give You Up", "", 170, -2.15),
+            ("Rick Astley reacts to Never Gonna Give You Up", "", 0, -3.0),
+            ("Never Gonna Give You Up | KARAOKE", "", 0, -3.0),
+            ("Never Gonna Give You Up (10 hours)", "", 36000, -3.0),
+        ];
+
+        for (title, channel, secs, score) in cases {
+            let result =
+                scorer.score(&track(), &candidate(title, channel, *secs));
+            assert!((result - score).abs() < 0.001, "{}: {}", title, result);
+        }
+    }
+
+    #[test]
+    fn known_words() {
+        let scorer = Scorer::default();
+        let mut track = track();
+        track.title = Some(String::from("Never Gonna Give You Up - Live"));
+        let live = candidate("Never Gonna Give You Up (Live)", "", 0);
+        assert_eq!(scorer.score(&track, &live), 0.0);
+
+        track.title = Some(String::from("Live Forever"));
+        track.album = Some(String::from("Cover Songs"));
+        let cover = candidate("Live Forever (Cover)", "", 0);
+        assert_eq!(scorer.score(&track, &cover), 0.0);
+    }
+
+    #[test]
+    fn ranking() {
+        let candidates = vec![
+            candidate("Never Gonna Give You Up (Lyrics)", "Lyrics Hub", 213),
+            candidate("Never Gonna Give You Up (Karaoke)", "", 213),
+            candidate("Never Gonna Give You Up", "Fan", 0),
+            candidate("Never Gonna Give You Up (Official Video)", "", 0),
+            candidate("Never Gonna Give You Up", "RickAstleyVEVO", 215),
+            candidate("Never Gonna Give You Up (Live)", "", 0),
+        ];
+        let ranked: Vec<String> = Scorer::default()
+            .rank(&track(), candidates.clone())
+            .into_iter()
+            .map(|c| c.title)
+            .collect();
+        assert_eq!(
+            ranked,
+            [
+                "Never Gonna Give You Up",
+                "Never Gonna Give You Up (Official Video)",
+                "Never Gonna Give You Up (Lyrics)",
+                "Never Gonna Give You Up (Karaoke)",
+                "Never Gonna Give You Up",
+            ]
+        );
+
+        // The weights and the minimum score are configurable.
+        let strict = Scorer {
+            official_title: 0.0,
+            min_score: 1.0,
+            ..Scorer::default()
+        };
+        let ranked = strict.rank(&track(), candidates.clone());
+        assert_eq!(ranked, [candidates[4].clone()]);
+        assert!(strict.rank(&track(), Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn official_ranking() {
+        let mut found = candidate("Never Gonna Give You Up", "Fan", 213);
+        found.url = String::from("https://youtu.be/found");
+        let mut best =
+            candidate("Never Gonna Give You Up", "RickAstleyVEVO", 213);
+        best.url = String::from("https://youtu.be/best");
+        let mut other = candidate("Never Gonna Give You Up", "", 0);
+        other.url = String::from("https://www.youtube.com/watch?v=other");
+        let official = [
+            Candidate {
+                url: String::from("https://www.youtube.com/watch?v=found"),
+                ..Candidate::default()
+            },
+            other.clone(),
+        ];
+
+        // The official videos are ranked first, and the one found by the
+        // provider keeps its information.
+        let ranked = Scorer::default().rank_with_official(
+            &track(),
+            vec![best.clone(), found.clone()],
+            &official,
+        );
+        assert_eq!(ranked, [found, other.clone(), best.clone()]);
+
+        // They don't need the minimum score.
+        let strict = Scorer {
+            min_score: 1.0,
+            ..Scorer::default()
+        };
+        assert_eq!(
+            strict.rank_with_official(
+                &track(),
+                vec![best.clone()],
+                &[other.clone()]
+            ),
+            [other, best]
+        );
+    }
+}
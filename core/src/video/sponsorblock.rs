@@ -0,0 +1,352 @@
+//! Many official music videos have a skit before or after the song, which
+//! would break the synchronization with the music player. SponsorBlock is a
+//! crowdsourced database of segments in YouTube videos, and the ones in the
+//! `music_offtopic` category mark exactly the parts without music. They're
+//! used to convert between the position in the song and in the video.
+//!
+//! The segments are saved in the data directory, since they rarely change.
+
+use crate::config::Config;
+use crate::data::{Res, ResKind};
+use crate::error::{Error, Result};
+use crate::video::youtube_id;
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time;
+
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// The name of the file in the data directory.
+pub const DATA_FILE: &str = "sponsorblock.json";
+const CATEGORY: &str = "music_offtopic";
+const TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// New segments may be submitted after the video was first played, so
+/// they're requested again once in a while.
+const TTL: time::Duration = time::Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A segment in the response of the API.
+#[derive(Deserialize)]
+struct Segment {
+    /// The start and end, in seconds.
+    segment: (f64, f64),
+    category: String,
+}
+
+/// The non-music parts of a video, in seconds, sorted and without
+/// overlaps.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Segments(Vec<(f64, f64)>);
+
+impl Segments {
+    pub fn new(mut segments: Vec<(f64, f64)>) -> Segments {
+        segments.retain(|(start, end)| start < end && *start >= 0.0);
+        segments.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut merged: Vec<(f64, f64)> = Vec::new();
+        for (start, end) in segments {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        Segments(merged)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The position in the video where the song starts.
+    pub fn offset(&self) -> time::Duration {
+        self.to_video(time::Duration::from_secs(0))
+    }
+
+    /// Converts a position in the song to the position in the video,
+    /// skipping the segments without music.
+    pub fn to_video(&self, song: time::Duration) -> time::Duration {
+        let mut video = song.as_secs_f64();
+        for (start, end) in &self.0 {
+            if *start <= video {
+                video += end - start;
+            } else {
+                break;
+            }
+        }
+
+        time::Duration::from_secs_f64(video)
+    }
+
+    /// Converts a position in the video to the position in the song. The
+    /// positions inside a segment are the same as its end.
+    pub fn to_song(&self, video: time::Duration) -> time::Duration {
+        let video = video.as_secs_f64();
+        let skipped: f64 = self
+            .0
+            .iter()
+            .filter(|(start, _)| *start < video)
+            .map(|(start, end)| end.min(video) - start)
+            .sum();
+
+        time::Duration::from_secs_f64((video - skipped).max(0.0))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    segments: Segments,
+    /// In seconds since the UNIX epoch.
+    created: u64,
+}
+
+pub struct SponsorBlock {
+    url: String,
+    client: Client,
+    /// The segments of each video by its ID.
+    cache: Mutex<HashMap<String, Entry>>,
+    /// Where the cache is saved, if anywhere.
+    path: Option<String>,
+}
+
+impl SponsorBlock {
+    /// Uses the instance at the given URL, with the cache in the path if
+    /// any, which may be empty.
+    pub fn new(url: &str, path: Option<String>) -> Result<SponsorBlock> {
+        let cache = match &path {
+            Some(path) => {
+                let data = fs::read_to_string(path)?;
+                if data.trim().is_empty() {
+                    HashMap::new()
+                } else {
+                    serde_json::from_str(&data)?
+                }
+            }
+            None => HashMap::new(),
+        };
+
+        Ok(SponsorBlock {
+            url: url.trim_end_matches('/').to_string(),
+            client: Client::builder().timeout(TIMEOUT).build()?,
+            cache: Mutex::new(cache),
+            path,
+        })
+    }
+
+    /// Returns `None` if it's disabled in the config.
+    pub fn from_config(config: &Config) -> Result<Option<SponsorBlock>> {
+        if config.no_sponsorblock {
+            return Ok(None);
+        }
+
+        let path = Res::new(ResKind::Data(String::from(DATA_FILE)))?;
+        let sponsorblock =
+            SponsorBlock::new(&config.sponsorblock_url, Some(path.path))?;
+        Ok(Some(sponsorblock))
+    }
+
+    /// The segments without music of a video, which are empty if it's not
+    /// from YouTube or if there are none.
+    pub fn segments(&self, video: &str) -> Result<Segments> {
+        let id = match youtube_id(video) {
+            Some(id) => id,
+            None => return Ok(Segments::default()),
+        };
+
+        let now = now();
+        if let Some(entry) = self.cache.lock().unwrap().get(id) {
+            if now.saturating_sub(entry.created) < TTL.as_secs() {
+                return Ok(entry.segments.clone());
+            }
+        }
+
+        let segments = self.request(id)?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, entry| {
+            now.saturating_sub(entry.created) < TTL.as_secs()
+        });
+        cache.insert(
+            id.to_string(),
+            Entry {
+                segments: segments.clone(),
+                created: now,
+            },
+        );
+        if let Some(path) = &self.path {
+            let saved = serde_json::to_string(&*cache)
+                .map_err(Error::from)
+                .and_then(|data| Ok(fs::write(path, data)?));
+            if let Err(e) = saved {
+                warn!("Couldn't save the SponsorBlock segments: {}", e);
+            }
+        }
+
+        Ok(segments)
+    }
+
+    fn request(&self, id: &str) -> Result<Segments> {
+        let url = format!("{}/api/skipSegments", self.url);
+        info!("Requesting the SponsorBlock segments of {}", id);
+        let categories = format!("[\"{}\"]", CATEGORY);
+        let res = self
+            .client
+            .get(&url)
+            .query(&[("videoID", id), ("categories", &categories)])
+            .send()?;
+
+        // The API answers with a 404 when there are no segments.
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(Segments::default());
+        }
+        if !res.status().is_success() {
+            return Err(Error::FailedRequest(format!(
+                "{} returned {}",
+                url,
+                res.status()
+            )));
+        }
+
+        let segments: Vec<Segment> = res.json()?;
+        Ok(Segments::new(
+            segments
+                .into_iter()
+                .filter(|segment| segment.category == CATEGORY)
+                .map(|segment| segment.segment)
+                .collect(),
+        ))
+    }
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testing::{temp_dir, MockServer};
+
+    const SEGMENTS: &str = r#"[
+        {
+            "category": "music_offtopic",
+            "actionType": "skip",
+            "segment": [0, 35.5],
+            "UUID": "intro",
+            "videoDuration": 300
+        },
+        {
+            "category": "music_offtopic",
+            "actionType": "skip",
+            "segment": [280.5, 300],
+            "UUID": "outro",
+            "videoDuration": 300
+        },
+        {
+            "category": "sponsor",
+            "actionType": "skip",
+            "segment": [100, 110],
+            "UUID": "sponsor",
+            "videoDuration": 300
+        }
+    ]"#;
+
+    fn secs(secs: f64) -> time::Duration {
+        time::Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn positions() {
+        let segments = Segments::new(vec![(100.0, 110.0), (0.0, 30.0)]);
+        assert_eq!(segments.offset(), secs(30.0));
+        let cases = &[
+            (0.0, 30.0),
+            (50.0, 80.0),
+            (70.0, 110.0),
+            (75.0, 115.0),
+            (200.0, 240.0),
+        ];
+        for (song, video) in cases {
+            assert_eq!(
+                segments.to_video(secs(*song)),
+                secs(*video),
+                "{}",
+                song
+            );
+            assert_eq!(
+                segments.to_song(secs(*video)),
+                secs(*song),
+                "{}",
+                video
+            );
+        }
+
+        // Inside a segment, it's the same as its end.
+        assert_eq!(segments.to_song(secs(10.0)), secs(0.0));
+        assert_eq!(segments.to_song(secs(105.0)), secs(70.0));
+
+        let empty = Segments::default();
+        assert_eq!(empty.offset(), secs(0.0));
+        assert_eq!(empty.to_video(secs(42.0)), secs(42.0));
+        assert_eq!(empty.to_song(secs(42.0)), secs(42.0));
+    }
+
+    #[test]
+    fn merged() {
+        let segments =
+            Segments::new(vec![(20.0, 40.0), (0.0, 25.0), (50.0, 50.0)]);
+        assert_eq!(segments, Segments(vec![(0.0, 40.0)]));
+        assert!(Segments::new(vec![(-5.0, 3.0)]).is_empty());
+    }
+
+    #[test]
+    fn requests() {
+        let server =
+            MockServer::new(|req| match req.param("videoID").as_deref() {
+                Some("dQw4w9WgXcQ") => {
+                    (200, "application/json", SEGMENTS.into())
+                }
+                Some("broken") => (500, "text/plain", "Error".into()),
+                _ => (404, "text/plain", "Not Found".into()),
+            });
+        let dir = temp_dir("sponsorblock");
+        let path = dir.join(DATA_FILE).to_string_lossy().into_owned();
+        fs::write(&path, "").unwrap();
+        let sponsorblock =
+            SponsorBlock::new(&server.url, Some(path.clone())).unwrap();
+
+        let video = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        let segments = sponsorblock.segments(video).unwrap();
+        assert_eq!(segments, Segments(vec![(0.0, 35.5), (280.5, 300.0)]));
+        let requests = server.requests();
+        assert_eq!(requests[0].route(), "/api/skipSegments");
+        assert_eq!(
+            requests[0].param("categories").as_deref(),
+            Some("[\"music_offtopic\"]")
+        );
+
+        // The segments are cached, including when there are none.
+        assert_eq!(sponsorblock.segments(video).unwrap(), segments);
+        let none = "https://youtu.be/nothing";
+        assert!(sponsorblock.segments(none).unwrap().is_empty());
+        assert!(sponsorblock.segments(none).unwrap().is_empty());
+        assert_eq!(server.requests().len(), 2);
+        let loaded = SponsorBlock::new(&server.url, Some(path)).unwrap();
+        assert_eq!(loaded.segments(video).unwrap(), segments);
+        assert_eq!(server.requests().len(), 2);
+
+        // Only YouTube videos are supported.
+        let local = "file:///videos/song.mkv";
+        assert!(sponsorblock.segments(local).unwrap().is_empty());
+        assert!(sponsorblock.segments("https://youtu.be/broken").is_err());
+    }
+}
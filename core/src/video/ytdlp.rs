@@ -0,0 +1,287 @@
+//! A provider that looks for the music videos on YouTube with `yt-dlp`,
+//! which has to be installed separately. It's also used to resolve the
+//! direct stream URLs of the videos, so that any player can open them.
+
+use crate::api::TrackInfo;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::video::query::QueryTemplate;
+use crate::video::{is_web_url, run_command, Candidate, VideoProvider};
+
+use std::time;
+
+use log::warn;
+use serde::Deserialize;
+
+/// The subset of fields used from the output of `--dump-json`.
+#[derive(Deserialize)]
+struct Entry {
+    title: String,
+    webpage_url: Option<String>,
+    url: Option<String>,
+    channel: Option<String>,
+    uploader: Option<String>,
+    /// In seconds, may include decimals.
+    duration: Option<f64>,
+    thumbnail: Option<String>,
+}
+
+impl Entry {
+    fn into_candidate(self) -> Option<Candidate> {
+        Some(Candidate {
+            url: self.webpage_url.or(self.url)?,
+            title: self.title,
+            channel: self.channel.or(self.uploader),
+            duration: self.duration.map(time::Duration::from_secs_f64),
+            thumbnail: self.thumbnail,
+        })
+    }
+}
+
+pub struct YtDlp {
+    path: String,
+    format: String,
+    results: u32,
+    query: QueryTemplate,
+}
+
+impl VideoProvider for YtDlp {
+    fn new(config: &Config) -> Result<Self> {
+        Ok(YtDlp {
+            path: config.ytdlp_path.clone(),
+            format: config.ytdlp_format.clone(),
+            results: config.video_results,
+            query: QueryTemplate::from_config(config),
+        })
+    }
+
+    fn search(&self, track: &TrackInfo) -> Result<Vec<Candidate>> {
+        let query = self.query.render(track)?;
+        let search = format!("ytsearch{}:{}", self.results, query);
+
+        // Each of the results is printed as a JSON object in a new line.
+        let output = run_command(
+            &self.path,
+            &["--dump-json", "--no-warnings", &search],
+        )?;
+        let candidates = output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => entry.into_candidate(),
+                Err(e) => {
+                    warn!("Skipping invalid yt-dlp result: {}", e);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(candidates)
+    }
+
+    /// The format selector should return a single file with both video and
+    /// audio, since only the first URL printed by `yt-dlp` is used.
+    ///
+    /// The URL may come from a shared overrides file, so it's checked and
+    /// passed after `--` to never be parsed as an option.
+    fn stream_url(&self, candidate: &Candidate) -> Result<String> {
+        if !is_web_url(&candidate.url) {
+            return Err(Error::FailedCommand(format!(
+                "refusing to pass {:?} to {}, since it isn't a web URL",
+                candidate.url, self.path
+            )));
+        }
+        let output = run_command(
+            &self.path,
+            &[
+                "--get-url",
+                "--no-warnings",
+                "--format",
+                &self.format,
+                "--",
+                &candidate.url,
+            ],
+        )?;
+
+        output
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(String::from)
+            .ok_or_else(|| {
+                Error::FailedCommand(format!(
+                    "{} didn't return a stream URL for {}",
+                    self.path, candidate.url
+                ))
+            })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    use crate::testing::temp_dir;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    const SEARCH: &str = r#"{"id": "dQw4w9WgXcQ", "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)", "webpage_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "channel": "Rick Astley", "uploader": "Rick Astley", "duration": 212.0, "thumbnail": "https://i.ytimg.com/vi/dQw4w9WgXcQ/maxresdefault.jpg"}
+
+{"id": "abc", "title": "Never Gonna Give You Up (Lyrics)", "url": "https://www.youtube.com/watch?v=abc", "uploader": "Lyrics Channel", "duration": 213.5}
+{"title": "Missing URL"}
+not json"#;
+
+    /// Creates a fake `yt-dlp` that saves its arguments and prints the
+    /// given output.
+    fn fake_ytdlp(name: &str, output: &str, exit: i32) -> (YtDlp, PathBuf) {
+        let dir = temp_dir(&format!("ytdlp-{}", name));
+        fs::write(dir.join("output"), output).unwrap();
+
+        let script = dir.join("yt-dlp");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 printf '%s\\n' \"$@\" > '{dir}/args'\n\
+                 cat '{dir}/output'\n\
+                 echo 'ERROR: fake error' >&2\n\
+                 exit {}\n",
+                exit,
+                dir = dir.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let provider = YtDlp {
+            path: script.to_string_lossy().into_owned(),
+            format: String::from("best[height<=720]"),
+            results: 3,
+            query: QueryTemplate::default(),
+        };
+        (provider, dir)
+    }
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            artist: Some(String::from("Rick Astley")),
+            title: Some(String::from("Never Gonna Give You Up")),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn search() {
+        let (provider, dir) = fake_ytdlp("search", SEARCH, 0);
+        let candidates = provider.search(&track()).unwrap();
+
+        let args = fs::read_to_string(dir.join("args")).unwrap();
+        let query = "ytsearch3:Rick Astley - Never Gonna Give You Up";
+        assert!(args.lines().any(|arg| arg == query));
+        assert!(args.lines().any(|arg| arg == "--dump-json"));
+
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[0],
+            Candidate {
+                url: String::from(
+                    "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+                ),
+                title: String::from(
+                    "Rick Astley - Never Gonna Give You Up (Official Music \
+                     Video)"
+                ),
+                channel: Some(String::from("Rick Astley")),
+                duration: Some(time::Duration::from_secs(212)),
+                thumbnail: Some(String::from(
+                    "https://i.ytimg.com/vi/dQw4w9WgXcQ/maxresdefault.jpg"
+                )),
+            }
+        );
+        assert_eq!(candidates[1].url, "https://www.youtube.com/watch?v=abc");
+        assert_eq!(candidates[1].channel.as_deref(), Some("Lyrics Channel"));
+        assert_eq!(
+            candidates[1].duration,
+            Some(time::Duration::from_millis(213_500))
+        );
+        assert_eq!(candidates[1].thumbnail, None);
+    }
+
+    #[test]
+    fn stream_url() {
+        let output = "\nhttps://rr1.googlevideo.com/videoplayback?a=1\n";
+        let (provider, dir) = fake_ytdlp("stream", output, 0);
+        let candidate = Candidate {
+            url: String::from("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            ..Candidate::default()
+        };
+
+        assert_eq!(
+            provider.stream_url(&candidate).unwrap(),
+            "https://rr1.googlevideo.com/videoplayback?a=1"
+        );
+        let args = fs::read_to_string(dir.join("args")).unwrap();
+        assert!(args.contains("--format\nbest[height<=720]\n"));
+        assert!(args
+            .ends_with("--\nhttps://www.youtube.com/watch?v=dQw4w9WgXcQ\n"));
+    }
+
+    #[test]
+    fn option_url() {
+        let output = "https://rr1.googlevideo.com/videoplayback?a=1\n";
+        let (provider, dir) = fake_ytdlp("option", output, 0);
+        for url in &["--exec=touch pwned", "-o", "file:///etc/passwd"] {
+            let candidate = Candidate {
+                url: url.to_string(),
+                ..Candidate::default()
+            };
+            assert!(matches!(
+                provider.stream_url(&candidate),
+                Err(Error::FailedCommand(_))
+            ));
+        }
+
+        // It's never run with them.
+        assert!(!dir.join("args").exists());
+    }
+
+    #[test]
+    fn failed_command() {
+        let (provider, _) = fake_ytdlp("failed", "", 1);
+        match provider.search(&track()) {
+            Err(Error::FailedCommand(msg)) => {
+                assert!(msg.contains("ERROR: fake error"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let (provider, _) = fake_ytdlp("empty", "", 0);
+        assert!(provider.search(&track()).unwrap().is_empty());
+        assert!(provider.stream_url(&Candidate::default()).is_err());
+    }
+
+    #[test]
+    fn missing_binary() {
+        let provider = YtDlp {
+            path: String::from("/nonexistent/yt-dlp"),
+            format: String::from("best"),
+            results: 1,
+            query: QueryTemplate::default(),
+        };
+        match provider.search(&track()) {
+            Err(Error::MissingBinary(path)) => {
+                assert_eq!(path, "/nonexistent/yt-dlp")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_title() {
+        let (provider, _) = fake_ytdlp("title", SEARCH, 0);
+        assert!(provider.search(&TrackInfo::default()).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use std::fs::File;
+use std::process;
 
 use core::api::{init_api, API};
 use core::config::init_config;
 use core::data::{Res, ResKind};
-use log::info;
+use core::video::{cache, overrides};
+use log::{error, info};
 use simplelog::{
     CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger,
 };
@@ -36,6 +38,24 @@ fn main() {
     info!("Initialized the logger");
     info!("Config: {:?}", config);
 
+    // Clearing the cache, or importing or exporting the video overrides
+    // instead of starting
+    if config.clear_cache {
+        if let Err(err) = cache::clear_data() {
+            error!("Couldn't clear the cache: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    match overrides::handle_args(&config) {
+        Ok(false) => {}
+        Ok(true) => return,
+        Err(err) => {
+            error!("Couldn't handle the overrides: {}", err);
+            process::exit(1);
+        }
+    }
+
     // Initializing the API
     let api = init_api(config.api.clone().unwrap_or(API::SpotifyWeb), &config);
     match api {